@@ -0,0 +1,74 @@
+use crate::interp::Strictness;
+
+pub const USAGE: &str = "Usage: cmpsc-470-final [FLAGS] [COMMAND | <file>]
+
+Commands:
+  run <file>    Evaluate each form in a file
+  repl          Start an interactive session (the default)
+  check <file>  Parse a file without evaluating it
+  eval <expr>   Evaluate a single expression
+  ast <file>    Print the parse tree of a file
+
+A file given without a command is run.
+
+Flags:
+  --strict        Warn about lambda parameters that are never used
+  --strict=error  Reject lambdas with parameters that are never used
+  --fold          Fold constant subexpressions before evaluating each form";
+
+// What the interpreter was asked to do on the command line
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(String),
+    Repl,
+    Check(String),
+    Eval(String),
+    Ast(String),
+}
+
+// Interpreter options given as flags before the command
+#[derive(Debug, PartialEq, Default)]
+pub struct Flags {
+    pub strict: Option<Strictness>,
+    pub fold: bool,
+}
+
+// Takes the flags from the front of the arguments after the program name, returning the
+// options they ask for and the arguments left for parse_args
+pub fn parse_flags(args: &[String]) -> (Flags, &[String]) {
+    let mut flags = Flags::default();
+    let mut rest = args;
+    while let Some((flag, others)) = rest.split_first() {
+        match flag.as_str() {
+            "--strict" => flags.strict = Some(Strictness::Warn),
+            "--strict=error" => flags.strict = Some(Strictness::Error),
+            "--fold" => flags.fold = true,
+            _ => break,
+        }
+        rest = others;
+    }
+    (flags, rest)
+}
+
+// Parses the arguments after the program name; the error describes what was wrong. A lone
+// argument that isn't a command is a file to run
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(Command::Repl);
+    };
+    let operand = |what: &str| match rest {
+        [operand] => Ok(operand.clone()),
+        _ => Err(format!("'{}' expects exactly one {}", command, what)),
+    };
+    match command.as_str() {
+        "run" => operand("file").map(Command::Run),
+        "check" => operand("file").map(Command::Check),
+        "eval" => operand("expression").map(Command::Eval),
+        "ast" => operand("file").map(Command::Ast),
+        "repl" if rest.is_empty() => Ok(Command::Repl),
+        "repl" => Err("'repl' takes no arguments".to_string()),
+        flag if flag.starts_with('-') => Err(format!("Unknown flag '{}'", flag)),
+        file if rest.is_empty() => Ok(Command::Run(file.to_string())),
+        other => Err(format!("Unknown command '{}'", other)),
+    }
+}
@@ -0,0 +1,65 @@
+use std::io;
+
+use crate::interp::{Env, Interpreter, Value};
+use crate::parse::Exp;
+
+// Replaces pure subexpressions whose operands are all literals with the literal they evaluate to.
+// Identifiers, side effects (display, set, box, ...) and function application are never folded,
+// so anything depending on them is left for the interpreter. Folds for an interpreter with the
+// default options.
+pub fn fold_constants(exp: Exp) -> Exp {
+    fold_constants_for(exp, &Interpreter::new(Box::new(io::sink())))
+}
+
+// Like fold_constants, but following the int width, float division and condition options of
+// the interpreter the result will run in
+pub fn fold_constants_for(exp: Exp, options: &Interpreter) -> Exp {
+    let mut folder = Interpreter::new(Box::new(io::sink()));
+    folder.int_width = options.int_width;
+    folder.ieee_float_div = options.ieee_float_div;
+    folder.truthy_conditions = options.truthy_conditions;
+    fold(exp, &mut folder)
+}
+
+fn fold(exp: Exp, folder: &mut Interpreter) -> Exp {
+    let exp = exp.map_children(|e| fold(e, folder));
+    // A list that folded to a literal can't fail, so the span around it isn't needed and would
+    // keep its parent from folding
+    if let Exp::Spanned { exp, span } = exp {
+        return if is_literal(&exp) {
+            *exp
+        } else {
+            Exp::Spanned { exp, span }
+        };
+    }
+    if !is_foldable(&exp) {
+        return exp;
+    }
+    // Operations that fail (e.g. division by zero) are left in place to fail at runtime
+    match folder.interp(exp.clone(), &mut Env::new()) {
+        Ok(Value::Int(i)) => Exp::Int(i),
+        Ok(Value::Float(f)) => Exp::Float(f),
+        Ok(Value::Bool(b)) => Exp::Bool(b),
+        _ => exp,
+    }
+}
+
+fn is_foldable(exp: &Exp) -> bool {
+    matches!(
+        exp,
+        Exp::Add { .. }
+            | Exp::Sub { .. }
+            | Exp::Mult { .. }
+            | Exp::Div { .. }
+            | Exp::Eq { .. }
+            | Exp::Gt { .. }
+            | Exp::Ge { .. }
+            | Exp::Lt { .. }
+            | Exp::Le { .. }
+            | Exp::If { .. }
+    ) && exp.children().into_iter().all(is_literal)
+}
+
+fn is_literal(exp: &Exp) -> bool {
+    matches!(exp, Exp::Int(_) | Exp::Float(_) | Exp::Bool(_))
+}
@@ -1,65 +1,860 @@
 use core::fmt;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::rc::Rc;
+use std::time::Instant;
 
-use crate::parse::Exp;
+use crate::fold;
+use crate::parse::{Exp, Type};
+use crate::sexp::Span;
 use thiserror::Error;
 
 type Location = usize;
-type Env = HashMap<String, Value>;
+
+// Identity of a vector, for noticing one that contains itself
+type VectorPtr = *const RefCell<Vec<Value>>;
+
+thread_local! {
+    // Vectors whose Debug output is being written
+    static DEBUG_VECTORS: RefCell<Vec<VectorPtr>> = const { RefCell::new(Vec::new()) };
+}
+
+// Operators that evaluate to a Value::Primitive when not shadowed by a binding
+const PRIMITIVES: [&str; 9] = ["+", "-", "*", "/", "=", "<", ">", "<=", ">="];
+
+// Longest chain of refs and boxes deref will follow before giving up
+const MAX_DEREF_DEPTH: usize = 10_000;
+pub type Env = HashMap<String, Value>;
 
 #[derive(Clone)]
 pub enum Value {
-    Int(i64),
+    Int(i128),
     Float(f64),
     Bool(bool),
-    Closure { arg: String, body: Exp, env: Env },
+    Str(String),
+    Char(char),
+    Closure {
+        params: Vec<String>,
+        // Name bound to a list of any arguments beyond params
+        rest: Option<String>,
+        body: Exp,
+        env: Env,
+    },
+    // Closure whose results are cached by argument; clones share the same cache
+    Memo {
+        func: Box<Value>,
+        cache: Rc<RefCell<HashMap<MemoKey, Value>>>,
+    },
+    // Function that logs each call and its result to the trace output
+    Traced {
+        name: String,
+        func: Box<Value>,
+    },
+    // Placeholder for a letrec-bound name; looking it up builds the closure, whose env holds
+    // placeholders for the whole group, so the bindings can refer to each other without cycles
+    Rec {
+        group: Rc<RecGroup>,
+        index: usize,
+    },
+    // Built-in operator used as a value, such as + in (apply + (list 1 2))
+    Primitive(String),
     Box(Location),
     Ref(Box<Value>),
     MutRef(Box<Value>),
+    Pair(Box<Value>, Box<Value>),
+    Nil,
+    // Result of forms with nothing meaningful to return, such as (begin)
+    Unit,
+    List(Vec<Value>),
+    Values(Vec<Value>),
+    Bytes(Vec<u8>),
+    // Fixed-size array that is updated in place; clones share the same elements
+    Vector(Rc<RefCell<Vec<Value>>>),
+    // Unevaluated expression produced by read
+    Code(Exp),
     Moved,
 }
 
+// Bindings of a letrec and the env they were defined in
+pub struct RecGroup {
+    bindings: Vec<(String, Exp)>,
+    env: Env,
+}
+
+// Binds each name in group to its placeholder
+fn bind_rec_group(group: &Rc<RecGroup>, env: &mut Env) {
+    for (index, (name, _)) in group.bindings.iter().enumerate() {
+        let placeholder = Value::Rec {
+            group: group.clone(),
+            index,
+        };
+        env.insert(name.clone(), placeholder);
+    }
+}
+
+// Argument values that can be used to look up memoized results
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MemoKey {
+    Int(i128),
+    Bool(bool),
+    Str(String),
+}
+
+impl MemoKey {
+    fn from_value(value: &Value) -> Option<MemoKey> {
+        match value {
+            Value::Int(i) => Some(MemoKey::Int(*i)),
+            Value::Bool(b) => Some(MemoKey::Bool(*b)),
+            Value::Str(s) => Some(MemoKey::Str(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    // Name of the value's type as used in errors, matching the names of annotation types
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::Bool(_) => "Bool",
+            Value::Str(_) => "Str",
+            Value::Char(_) => "Char",
+            Value::Closure { .. }
+            | Value::Memo { .. }
+            | Value::Traced { .. }
+            | Value::Rec { .. }
+            | Value::Primitive(_) => "Fn",
+            Value::Box(_) => "Box",
+            Value::Ref(_) => "Ref",
+            Value::MutRef(_) => "MutRef",
+            Value::Pair(_, _) => "Pair",
+            Value::Nil => "Nil",
+            Value::Unit => "Unit",
+            Value::List(_) => "List",
+            Value::Values(_) => "Values",
+            Value::Bytes(_) => "Bytes",
+            Value::Vector(_) => "Vector",
+            Value::Code(_) => "Code",
+            Value::Moved => "Moved",
+        }
+    }
+
+    // Whether two values may be used interchangeably, such as in the branches of an if. Lists
+    // are treated as homogeneous, so every element of both lists must share a type; their
+    // lengths may differ. Refs compare their pointees
+    pub fn same_type(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => {
+                let mut elems = a.iter().chain(b);
+                elems
+                    .next()
+                    .is_none_or(|first| elems.all(|e| first.same_type(e)))
+            }
+            (Value::Ref(a), Value::Ref(b)) | (Value::MutRef(a), Value::MutRef(b)) => a.same_type(b),
+            _ => matches!(
+                (self, other),
+                (Value::Int(_), Value::Int(_))
+                    | (Value::Float(_), Value::Float(_))
+                    | (Value::Bool(_), Value::Bool(_))
+                    | (Value::Str(_), Value::Str(_))
+                    | (Value::Char(_), Value::Char(_))
+                    | (Value::Unit, Value::Unit)
+                    | (Value::Bytes(_), Value::Bytes(_))
+                    | (Value::Vector(_), Value::Vector(_))
+                    | (Value::Code(_), Value::Code(_))
+                    | (Value::Box(_), Value::Box(_))
+                    | (
+                        Value::Closure { .. }
+                            | Value::Memo { .. }
+                            | Value::Traced { .. }
+                            | Value::Primitive(_),
+                        Value::Closure { .. }
+                            | Value::Memo { .. }
+                            | Value::Traced { .. }
+                            | Value::Primitive(_)
+                    )
+                    | (
+                        Value::Pair(_, _) | Value::Nil,
+                        Value::Pair(_, _) | Value::Nil
+                    )
+            ),
+        }
+    }
+
+    // Equality as used by =. Values can be compared exactly when they have the same type;
+    // anything else is IncompatibleTypes. Boxes and vectors are equal only to themselves.
+    // Closures are equal when their params, bodies and captured values are, so renaming a
+    // param makes them unequal even if they behave the same. Other functions are equal only
+    // when they're the same primitive
+    pub fn value_eq(&self, other: &Value) -> Result<bool, InterpError> {
+        if !self.same_type(other) {
+            return Err(incompatible(self, other));
+        }
+        // Parts of pairs aren't required to share a type, so parts that don't are just unequal
+        let parts_eq = |a: &Value, b: &Value| a.same_type(b) && matches!(a.value_eq(b), Ok(true));
+        Ok(match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            (Value::Unit, Value::Unit) | (Value::Nil, Value::Nil) => true,
+            (Value::Box(a), Value::Box(b)) => a == b,
+            (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+            (Value::Code(a), Value::Code(b)) => a == b,
+            (Value::Ref(a), Value::Ref(b)) | (Value::MutRef(a), Value::MutRef(b)) => {
+                a.value_eq(b)?
+            }
+            (Value::List(a), Value::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| parts_eq(x, y))
+            }
+            (Value::Pair(a1, d1), Value::Pair(a2, d2)) => parts_eq(a1, a2) && parts_eq(d1, d2),
+            (Value::Primitive(a), Value::Primitive(b)) => a == b,
+            (
+                Value::Closure {
+                    params: p1,
+                    rest: r1,
+                    body: b1,
+                    env: e1,
+                },
+                Value::Closure {
+                    params: p2,
+                    rest: r2,
+                    body: b2,
+                    env: e2,
+                },
+            ) => {
+                p1 == p2
+                    && r1 == r2
+                    && b1.clone().without_spans() == b2.clone().without_spans()
+                    && e1.len() == e2.len()
+                    && e1
+                        .iter()
+                        .all(|(name, v)| e2.get(name).is_some_and(|w| parts_eq(v, w)))
+            }
+            _ => false,
+        })
+    }
+}
+
 impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Int(n) => write!(f, "Int({})", n),
             Value::Float(n) => write!(f, "Float({})", n),
             Value::Bool(b) => write!(f, "Bool({})", b),
+            Value::Str(s) => write!(f, "Str({:?})", s),
+            Value::Char(c) => write!(f, "Char({:?})", c),
             Value::Box(l) => write!(f, "Box({})", l),
             Value::Ref(v) => write!(f, "Ref({:?})", v),
             Value::MutRef(v) => write!(f, "MutRef({:?})", v),
+            Value::Pair(car, cdr) => write!(f, "Pair({:?}, {:?})", car, cdr),
+            Value::Nil => write!(f, "Nil"),
+            Value::Unit => write!(f, "Unit"),
+            Value::List(vals) => {
+                write!(f, "List(")?;
+                let mut iter = vals.iter();
+                if let Some(first) = iter.next() {
+                    write!(f, "{:?}", first)?;
+                    for val in iter {
+                        write!(f, ", {:?}", val)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Value::Values(vals) => {
+                write!(f, "Values(")?;
+                let mut iter = vals.iter();
+                if let Some(first) = iter.next() {
+                    write!(f, "{:?}", first)?;
+                    for val in iter {
+                        write!(f, ", {:?}", val)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Value::Bytes(bytes) => write!(f, "Bytes({:?})", bytes),
+            // A vector that contains itself is shown as Vector(...) the second time round
+            Value::Vector(vals) if DEBUG_VECTORS.with_borrow(|v| v.contains(&Rc::as_ptr(vals))) => {
+                write!(f, "Vector(...)")
+            }
+            Value::Vector(vals) => {
+                DEBUG_VECTORS.with_borrow_mut(|v| v.push(Rc::as_ptr(vals)));
+                let result = (|| {
+                    write!(f, "Vector(")?;
+                    let vals = vals.borrow();
+                    let mut iter = vals.iter();
+                    if let Some(first) = iter.next() {
+                        write!(f, "{:?}", first)?;
+                        for val in iter {
+                            write!(f, ", {:?}", val)?;
+                        }
+                    }
+                    write!(f, ")")
+                })();
+                DEBUG_VECTORS.with_borrow_mut(|v| v.pop());
+                result
+            }
+            Value::Code(exp) => write!(f, "Code({:?})", exp),
             Value::Moved => write!(f, "Moved"),
-            Value::Closure { arg, body, env } => {
-                write!(
-                    f,
-                    "Closure(arg: {:?}, body: {:?}, env: {:?}",
-                    arg, body, env
-                )
+            Value::Primitive(op) => write!(f, "Primitive({})", op),
+            Value::Rec { group, index } => write!(f, "Rec({})", group.bindings[*index].0),
+            Value::Memo { func, .. } => write!(f, "Memo({:?})", func),
+            Value::Traced { name, func } => write!(f, "Traced({}, {:?})", name, func),
+            Value::Closure {
+                params,
+                rest,
+                body,
+                env,
+            } => {
+                write!(f, "Closure(params: {:?}, ", params)?;
+                if let Some(rest) = rest {
+                    write!(f, "rest: {}, ", rest)?;
+                }
+                write!(f, "body: {:?}, env: {:?}", body, env)
             }
         }
     }
 }
 
+// Conversions for Rust code embedding the interpreter
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i.into())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = InterpError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(i) => i64::try_from(i).map_err(|_| InterpError::IntegerOverflow),
+            _ => Err(InterpError::NotAnInteger),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = InterpError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Float(f) => Ok(f),
+            _ => Err(InterpError::NotANumber),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = InterpError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            _ => Err(InterpError::NotABool),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = InterpError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Str(s) => Ok(s),
+            _ => Err(InterpError::NotAString),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum InterpError {
     #[error("{0} is not yet implemented")]
     NotImplemented(String),
     #[error("Value is not a number")]
     NotANumber,
-    #[error("Cannot perform operation on incompatible types")]
-    IncompatibleTypes,
-    #[error("Condition must be a boolean")]
-    ConditionNotBoolean,
+    #[error("Value is not an integer")]
+    NotAnInteger,
+    #[error("Cannot perform operation on incompatible types {left} and {right}")]
+    IncompatibleTypes {
+        left: &'static str,
+        right: &'static str,
+    },
+    #[error("Condition must be a boolean, got {0}")]
+    ConditionNotBoolean(&'static str),
     #[error("If branches must have the same type")]
     BranchTypeMismatch,
+    #[error("Integer does not fit in the selected width")]
+    IntegerOverflow,
     #[error("Division by zero")]
     DivisionByZero,
     #[error("This type cannot be displayed. Please use 'debug' instead")]
     CantDisplay,
-    #[error("Cannot find symbol '{0}'")]
-    SymbolNotFound(String),
+    #[error("Cannot find symbol '{0}'{hint}", hint = did_you_mean(.1))]
+    SymbolNotFound(String, Option<String>),
     #[error("Only closures can be applied")]
     NotAClosure,
+    #[error("Value is not a pair")]
+    NotAPair,
+    #[error("Value is not a character")]
+    NotAChar,
+    #[error("Integer is not a valid Unicode scalar value")]
+    ConversionOutOfRange,
+    #[error("Field width cannot be negative")]
+    NegativeWidth,
+    #[error("Repeat count cannot be negative")]
+    NegativeCount,
+    #[error("Vector length cannot be negative")]
+    NegativeLength,
+    #[error("Range step cannot be zero")]
+    ZeroStep,
+    #[error("Decimal places cannot be negative")]
+    NegativePlaces,
+    #[error("Decimal places cannot be more than 65535")]
+    TooManyPlaces,
+    #[error(
+        "'{0}' is used before it is defined; use letrec for definitions that refer to each other"
+    )]
+    ForwardReference(String),
+    #[error("Cannot set a value of type {0}; set takes a box or a vector-ref")]
+    InvalidPlace(&'static str),
+    #[error("Parameter '{0}' is never used")]
+    UnusedParameter(String),
+    #[error("Value is not a list")]
+    NotAList,
+    #[error("Value is not a string")]
+    NotAString,
+    #[error("Value is not a boolean")]
+    NotABool,
+    #[error("Value is not code produced by read")]
+    NotCode,
+    #[error("Cannot read code: {0}")]
+    ReadError(String),
+    #[error("Separator cannot be empty")]
+    EmptySeparator,
+    #[error("Value is not a byte buffer")]
+    NotBytes,
+    #[error("Value is not a vector")]
+    NotAVector,
+    #[error("I/O error: {0}")]
+    IoError(String),
+    #[error("Index {index} is out of bounds for a list of length {len}")]
+    IndexOutOfBounds { index: i128, len: usize },
+    #[error(
+        "'{func}' expects {}{expected} arguments but got {got}",
+        if *.at_least { "at least " } else { "" }
+    )]
+    ArityMismatch {
+        func: String,
+        expected: usize,
+        // Whether more than expected are accepted, as with a rest parameter
+        at_least: bool,
+        got: usize,
+    },
+    #[error("Value is not a box")]
+    NotABox,
+    #[error("Value does not have the annotated type {0}")]
+    AnnotationMismatch(Type),
+    #[error("'{0}' was moved into a function call and can no longer be used")]
+    UseAfterMove(String),
+    #[error("Cannot set a box to a value of a different type")]
+    RefTypeMismatch,
+    #[error("Cycle detected while dereferencing")]
+    RefCycleDetected,
+    #[error("Box has been freed by the garbage collector")]
+    FreedBox,
+    #[error("Cannot allocate more boxes; the heap limit has been reached")]
+    HeapExhausted,
+    #[error("Failed to write output")]
+    OutputError,
+    #[error("Multiple values cannot be used where a single value is expected")]
+    UnexpectedMultipleValues,
+    #[error("{source} (at {}..{})", span.start, span.end)]
+    At {
+        span: Span,
+        source: Box<InterpError>,
+    },
+    #[error("Expected {expected} values but got {got}")]
+    ValueCountMismatch { expected: usize, got: usize },
+    #[error("Assertion failed: expected {expected} but got {actual}")]
+    AssertionFailed { actual: String, expected: String },
+    #[error("Expected an error but got {0}")]
+    ExpectedError(String),
+    // Not a failure; raised by exit to unwind to whatever is running the program
+    #[error("Program exited with code {0}")]
+    Exit(i32),
+}
+
+impl InterpError {
+    // Stable identifier for the kind of error, for callers that need to handle errors without
+    // matching on messages. Errors with a source location report the code of the underlying error
+    pub fn code(&self) -> &'static str {
+        match self {
+            InterpError::NotImplemented(_) => "E_NOT_IMPLEMENTED",
+            InterpError::NotANumber => "E_NOT_A_NUMBER",
+            InterpError::NotAnInteger => "E_NOT_AN_INTEGER",
+            InterpError::IncompatibleTypes { .. } => "E_INCOMPATIBLE_TYPES",
+            InterpError::ConditionNotBoolean(_) => "E_CONDITION_NOT_BOOLEAN",
+            InterpError::BranchTypeMismatch => "E_BRANCH_TYPE_MISMATCH",
+            InterpError::IntegerOverflow => "E_INTEGER_OVERFLOW",
+            InterpError::DivisionByZero => "E_DIV_ZERO",
+            InterpError::CantDisplay => "E_CANT_DISPLAY",
+            InterpError::SymbolNotFound(_, _) => "E_SYMBOL_NOT_FOUND",
+            InterpError::NotAClosure => "E_NOT_A_CLOSURE",
+            InterpError::NotAPair => "E_NOT_A_PAIR",
+            InterpError::NotAChar => "E_NOT_A_CHAR",
+            InterpError::ConversionOutOfRange => "E_CONVERSION_OUT_OF_RANGE",
+            InterpError::NegativeWidth => "E_NEGATIVE_WIDTH",
+            InterpError::NegativeCount => "E_NEGATIVE_COUNT",
+            InterpError::NegativeLength => "E_NEGATIVE_LENGTH",
+            InterpError::ZeroStep => "E_ZERO_STEP",
+            InterpError::NegativePlaces => "E_NEGATIVE_PLACES",
+            InterpError::TooManyPlaces => "E_TOO_MANY_PLACES",
+            InterpError::UnusedParameter(_) => "E_UNUSED_PARAMETER",
+            InterpError::ForwardReference(_) => "E_FORWARD_REFERENCE",
+            InterpError::InvalidPlace(_) => "E_INVALID_PLACE",
+            InterpError::NotAList => "E_NOT_A_LIST",
+            InterpError::NotAString => "E_NOT_A_STRING",
+            InterpError::NotABool => "E_NOT_A_BOOL",
+            InterpError::NotCode => "E_NOT_CODE",
+            InterpError::ReadError(_) => "E_READ",
+            InterpError::EmptySeparator => "E_EMPTY_SEPARATOR",
+            InterpError::NotBytes => "E_NOT_BYTES",
+            InterpError::NotAVector => "E_NOT_A_VECTOR",
+            InterpError::IoError(_) => "E_IO",
+            InterpError::IndexOutOfBounds { .. } => "E_INDEX_OUT_OF_BOUNDS",
+            InterpError::ArityMismatch { .. } => "E_ARITY_MISMATCH",
+            InterpError::NotABox => "E_NOT_A_BOX",
+            InterpError::AnnotationMismatch(_) => "E_ANNOTATION_MISMATCH",
+            InterpError::UseAfterMove(_) => "E_USE_AFTER_MOVE",
+            InterpError::RefTypeMismatch => "E_REF_TYPE_MISMATCH",
+            InterpError::RefCycleDetected => "E_REF_CYCLE",
+            InterpError::FreedBox => "E_FREED_BOX",
+            InterpError::HeapExhausted => "E_HEAP_EXHAUSTED",
+            InterpError::OutputError => "E_OUTPUT",
+            InterpError::UnexpectedMultipleValues => "E_UNEXPECTED_MULTIPLE_VALUES",
+            InterpError::At { source, .. } => source.code(),
+            InterpError::ValueCountMismatch { .. } => "E_VALUE_COUNT_MISMATCH",
+            InterpError::AssertionFailed { .. } => "E_ASSERTION_FAILED",
+            InterpError::ExpectedError(_) => "E_EXPECTED_ERROR",
+            InterpError::Exit(_) => "E_EXIT",
+        }
+    }
+}
+
+// Width that integer literals and arithmetic results must fit in; ints are always stored as i128
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum IntWidth {
+    I32,
+    #[default]
+    I64,
+    I128,
+}
+
+impl IntWidth {
+    fn contains(self, i: i128) -> bool {
+        match self {
+            IntWidth::I32 => i32::try_from(i).is_ok(),
+            IntWidth::I64 => i64::try_from(i).is_ok(),
+            IntWidth::I128 => true,
+        }
+    }
+
+    // Bit pattern of i as a two's complement integer of this width
+    fn twos_complement(self, i: i128) -> u128 {
+        match self {
+            IntWidth::I32 => u128::from(i as u32),
+            IntWidth::I64 => u128::from(i as u64),
+            IntWidth::I128 => i as u128,
+        }
+    }
+}
+
+// Diagnostics that don't stop evaluation
+#[derive(Clone, PartialEq, Debug)]
+pub enum WarningKind {
+    // A non-final begin expression whose result is discarded and has no side effects
+    UnusedValue,
+    // A lambda parameter that its body never refers to
+    UnusedParameter,
+    // A recursive function that may call itself more than once per call without memoization
+    ExponentialRecursion,
+}
+
+// Number of times each kind of expression was evaluated. Spans aren't counted since they only
+// wrap the expression that is
+#[derive(Clone, Default, Debug)]
+pub struct Profile {
+    counts: HashMap<&'static str, usize>,
+}
+
+// Longest bar in a profile report
+const PROFILE_BAR_WIDTH: usize = 40;
+
+impl Profile {
+    fn record(&mut self, exp: &Exp) {
+        if !matches!(exp, Exp::Spanned { .. }) {
+            *self.counts.entry(exp.kind()).or_default() += 1;
+        }
+    }
+
+    // Evaluations of expressions of the given kind, named as in Exp::kind
+    pub fn count(&self, kind: &str) -> usize {
+        self.counts.get(kind).copied().unwrap_or(0)
+    }
+
+    // Histogram with a line per kind, most evaluated first, and bars scaled to the largest count
+    pub fn report(&self) -> String {
+        let mut counts: Vec<(&str, usize)> = self.counts.iter().map(|(k, n)| (*k, *n)).collect();
+        counts.sort_by(|(k1, n1), (k2, n2)| n2.cmp(n1).then(k1.cmp(k2)));
+        let max = counts.first().map_or(1, |(_, n)| *n);
+        let name_width = counts.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+        let count_width = max.to_string().len();
+        counts
+            .iter()
+            .map(|(kind, n)| {
+                let bar = "#".repeat((n * PROFILE_BAR_WIDTH).div_ceil(max));
+                format!("{:<name_width$} {:>count_width$} {}\n", kind, n, bar)
+            })
+            .collect()
+    }
+}
+
+// How strict mode reports problems found before evaluation
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Strictness {
+    Warn,
+    Error,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    pub form: Option<Exp>,
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    suggestion
+        .as_ref()
+        .map_or(String::new(), |s| format!("; did you mean '{}'?", s))
+}
+
+fn has_type(value: &Value, ty: Type) -> bool {
+    matches!(
+        (value, ty),
+        (Value::Int(_), Type::Int)
+            | (Value::Float(_), Type::Float)
+            | (Value::Bool(_), Type::Bool)
+            | (Value::Str(_), Type::Str)
+            | (Value::Char(_), Type::Char)
+            | (
+                Value::Closure { .. }
+                    | Value::Memo { .. }
+                    | Value::Traced { .. }
+                    | Value::Rec { .. }
+                    | Value::Primitive(_),
+                Type::Fn
+            )
+            | (Value::Box(_), Type::Box)
+            | (Value::Ref(_), Type::Ref)
+            | (Value::MutRef(_), Type::MutRef)
+            | (Value::Pair(_, _), Type::Pair)
+            | (Value::Nil, Type::Nil)
+            | (Value::Unit, Type::Unit)
+            | (Value::List(_), Type::List)
+            | (Value::Bytes(_), Type::Bytes)
+            | (Value::Vector(_), Type::Vector)
+    )
+}
+
+// Most calls to name made by evaluating exp once. Only one branch of an if runs, so it counts
+// whichever branch calls name more
+fn self_calls(exp: &Exp, name: &str) -> usize {
+    match exp {
+        Exp::If { cond, lhs, rhs } => {
+            self_calls(cond, name) + self_calls(lhs, name).max(self_calls(rhs, name))
+        }
+        Exp::App { func, .. } if variable_name(func) == Some(name) => {
+            1 + exp
+                .children()
+                .into_iter()
+                .map(|e| self_calls(e, name))
+                .sum::<usize>()
+        }
+        _ => exp
+            .children()
+            .into_iter()
+            .map(|e| self_calls(e, name))
+            .sum(),
+    }
+}
+
+// First name used by a form of a begin before the define that binds it, including a define
+// referring to itself. A name already bound outside the begin refers to that binding until it's
+// redefined, so it isn't a forward reference
+fn forward_reference(es: &[Exp], env: &Env) -> Option<String> {
+    es.iter().enumerate().find_map(|(i, e)| {
+        let used = e.free_vars();
+        let own = e.defined_name().into_iter();
+        own.chain(es[i + 1..].iter().filter_map(Exp::defined_name))
+            .find(|name| used.contains(*name) && !env.contains_key(*name))
+            .cloned()
+    })
+}
+
+// Name of the variable exp refers to, if it's just a variable
+fn variable_name(exp: &Exp) -> Option<&str> {
+    match exp {
+        Exp::Id(name) => Some(name),
+        Exp::Spanned { exp, .. } => variable_name(exp),
+        _ => None,
+    }
+}
+
+// Floats display in plain decimal unless they're huge or tiny, where that would print hundreds
+// of digits; those use shortest-round-trip scientific notation such as 1e308 or 5e-324.
+// The sign of zero is kept, so -0.0 displays as -0
+fn format_float(f: f64) -> String {
+    let magnitude = f.abs();
+    if f.is_finite() && magnitude != 0.0 && !(1e-5..1e16).contains(&magnitude) {
+        format!("{:e}", f)
+    } else {
+        format!("{}", f)
+    }
+}
+
+// Number of arguments func takes. + and * accept any number but report 2, the arity they're
+// usually written with, and closures with a rest param report their fixed params
+fn arity(func: &Value) -> Result<usize, InterpError> {
+    match func {
+        Value::Closure { params, .. } => Ok(params.len()),
+        Value::Memo { func, .. } | Value::Traced { func, .. } => arity(func),
+        Value::Primitive(_) => Ok(2),
+        _ => Err(InterpError::NotAClosure),
+    }
+}
+
+// The bindings in env of each of names that is bound there
+fn capture(env: &Env, names: HashSet<String>) -> Env {
+    names
+        .into_iter()
+        .filter_map(|name| env.get(&name).map(|v| (name, v.clone())))
+        .collect()
+}
+
+// exp without any spans wrapping it
+fn unspanned(exp: Exp) -> Exp {
+    match exp {
+        Exp::Spanned { exp, .. } => unspanned(*exp),
+        exp => exp,
+    }
+}
+
+// func with any closure in it taking env as the env its body is evaluated in, for dynamic scope.
+// Arguments already supplied to a partially applied closure are replaced along with the rest
+fn in_env(func: Value, env: &Env) -> Value {
+    match func {
+        Value::Closure {
+            params, rest, body, ..
+        } => Value::Closure {
+            params,
+            rest,
+            body,
+            env: env.clone(),
+        },
+        Value::Memo { func, cache } => Value::Memo {
+            func: Box::new(in_env(*func, env)),
+            cache,
+        },
+        Value::Traced { name, func } => Value::Traced {
+            name,
+            func: Box::new(in_env(*func, env)),
+        },
+        v => v,
+    }
+}
+
+// How a function is named in errors when it wasn't called through an identifier: closures
+// are shown as their lambda and primitives by their operator
+fn function_name(func: &Value) -> String {
+    match func {
+        Value::Closure {
+            params, rest, body, ..
+        } => Exp::Lambda {
+            params: params.clone(),
+            rest: rest.clone(),
+            body: Box::new(body.clone()),
+        }
+        .to_string(),
+        Value::Memo { func, .. } => function_name(func),
+        Value::Traced { name, .. } | Value::Primitive(name) => name.clone(),
+        v => format!("{:?}", v),
+    }
+}
+
+// Source of the lambda func was made from; None for functions that aren't lambdas
+fn function_source(func: &Value) -> Option<String> {
+    match func {
+        Value::Closure { .. } => Some(function_name(func)),
+        Value::Memo { func, .. } | Value::Traced { func, .. } => function_source(func),
+        Value::Rec { group, index } => Some(group.bindings[*index].1.to_string()),
+        _ => None,
+    }
+}
+
+// Number of single-character insertions, deletions and substitutions to turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr.push((prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+// The bound name closest to name, if any is within a third of its length
+fn closest_name<'a>(name: &str, names: impl Iterator<Item = &'a String>) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    names
+        .map(|n| (edit_distance(name, n), n))
+        .filter(|(d, _)| *d <= max_distance)
+        .min()
+        .map(|(_, n)| n.clone())
+}
+
+fn incompatible(lhs: &Value, rhs: &Value) -> InterpError {
+    InterpError::IncompatibleTypes {
+        left: lhs.type_name(),
+        right: rhs.type_name(),
+    }
 }
 
 // Helper function for numeric operations that work on both ints and floats
@@ -70,20 +865,47 @@ fn apply_numeric_op<F, G>(
     float_op: G,
 ) -> Result<Value, InterpError>
 where
-    F: FnOnce(i64, i64) -> Value,
+    F: FnOnce(i128, i128) -> Option<Value>,
     G: FnOnce(f64, f64) -> Value,
 {
     match (lhs, rhs) {
-        (Value::Int(a), Value::Int(b)) => Ok(int_op(a, b)),
+        // int_op returns None when the result doesn't fit in an i128
+        (Value::Int(a), Value::Int(b)) => int_op(a, b).ok_or(InterpError::IntegerOverflow),
         (Value::Float(a), Value::Float(b)) => Ok(float_op(a, b)),
-        (Value::Int(_), Value::Float(_)) | (Value::Float(_), Value::Int(_)) => {
-            Err(InterpError::IncompatibleTypes)
+        (lhs @ (Value::Int(_) | Value::Float(_)), rhs @ (Value::Int(_) | Value::Float(_))) => {
+            Err(incompatible(&lhs, &rhs))
         }
         _ => Err(InterpError::NotANumber),
     }
 }
 
-// Helper function for comparison operations
+// Combines the numbers of a list with int_op or float_op, which must all be Ints or all be
+// Floats. An empty list gives the Int identity
+fn fold_numbers<F, G>(
+    list: Value,
+    identity: i128,
+    int_op: F,
+    float_op: G,
+) -> Result<Value, InterpError>
+where
+    F: Fn(i128, i128) -> Option<Value>,
+    G: Fn(f64, f64) -> Value,
+{
+    let Value::List(vals) = list else {
+        return Err(InterpError::NotAList);
+    };
+    let mut vals = vals.into_iter();
+    let Some(first) = vals.next() else {
+        return Ok(Value::Int(identity));
+    };
+    if !matches!(first, Value::Int(_) | Value::Float(_)) {
+        return Err(InterpError::NotANumber);
+    }
+    vals.try_fold(first, |acc, v| apply_numeric_op(acc, v, &int_op, &float_op))
+}
+
+// Helper function for comparison operations. Like arithmetic, comparing an Int with a Float is
+// IncompatibleTypes rather than converting either side
 fn apply_comparison<F, G>(
     lhs: Value,
     rhs: Value,
@@ -91,166 +913,1962 @@ fn apply_comparison<F, G>(
     float_op: G,
 ) -> Result<Value, InterpError>
 where
-    F: FnOnce(i64, i64) -> bool,
+    F: FnOnce(i128, i128) -> bool,
     G: FnOnce(f64, f64) -> bool,
 {
     apply_numeric_op(
         lhs,
         rhs,
-        |a, b| Value::Bool(int_op(a, b)),
+        |a, b| Some(Value::Bool(int_op(a, b))),
         |a, b| Value::Bool(float_op(a, b)),
     )
 }
 
-fn div(lhs: Value, rhs: Value) -> Result<Value, InterpError> {
+// Adds numbers, concatenates strings and appends lists
+fn add(lhs: Value, rhs: Value) -> Result<Value, InterpError> {
+    match (lhs, rhs) {
+        (Value::Str(a), Value::Str(b)) => Ok(Value::Str(a + &b)),
+        (Value::List(mut a), Value::List(b)) => {
+            a.extend(b);
+            Ok(Value::List(a))
+        }
+        (lhs @ (Value::Str(_) | Value::List(_)), rhs)
+        | (lhs, rhs @ (Value::Str(_) | Value::List(_))) => Err(incompatible(&lhs, &rhs)),
+        (lhs, rhs) => apply_numeric_op(
+            lhs,
+            rhs,
+            |a, b| a.checked_add(b).map(Value::Int),
+            |a, b| Value::Float(a + b),
+        ),
+    }
+}
+
+// Float division by zero is an error unless ieee is set, in which case it gives inf, -inf or NaN.
+// Integer division by zero is always an error
+fn div(lhs: Value, rhs: Value, ieee: bool) -> Result<Value, InterpError> {
     match (lhs, rhs) {
         (Value::Int(a), Value::Int(b)) => {
             if b == 0 {
                 Err(InterpError::DivisionByZero)
             } else {
-                Ok(Value::Int(a / b))
+                a.checked_div(b)
+                    .map(Value::Int)
+                    .ok_or(InterpError::IntegerOverflow)
             }
         }
         (Value::Float(a), Value::Float(b)) => {
-            if b == 0.0 {
+            if b == 0.0 && !ieee {
                 Err(InterpError::DivisionByZero)
             } else {
                 Ok(Value::Float(a / b))
             }
         }
-        (Value::Int(_), Value::Float(_)) | (Value::Float(_), Value::Int(_)) => {
-            Err(InterpError::IncompatibleTypes)
+        (lhs @ (Value::Int(_) | Value::Float(_)), rhs @ (Value::Int(_) | Value::Float(_))) => {
+            Err(incompatible(&lhs, &rhs))
         }
         _ => Err(InterpError::NotANumber),
     }
 }
 
 fn eq(lhs: Value, rhs: Value) -> Result<Value, InterpError> {
+    lhs.value_eq(&rhs).map(Value::Bool)
+}
+
+// Helper function for division operations that are only defined on ints. op returns None
+// when the result doesn't fit in an i128, which only happens for the minimum value divided by -1
+fn apply_integer_div<F>(lhs: Value, rhs: Value, op: F) -> Result<Value, InterpError>
+where
+    F: FnOnce(i128, i128) -> Option<Value>,
+{
     match (lhs, rhs) {
-        (Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a == b)),
+        (Value::Int(_), Value::Int(0)) => Err(InterpError::DivisionByZero),
+        (Value::Int(a), Value::Int(b)) => op(a, b).ok_or(InterpError::IntegerOverflow),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            Err(InterpError::NotAnInteger)
+        }
         _ => Err(InterpError::NotANumber),
     }
 }
 
-fn check_same_type(v1: &Value, v2: &Value) -> bool {
-    match (v1, v2) {
-        (Value::Int(_), Value::Int(_))
-        | (Value::Float(_), Value::Float(_))
-        | (Value::Bool(_), Value::Bool(_))
-        | (Value::Box(_), Value::Box(_))
-        | (Value::Ref(_), Value::Ref(_))
-        | (Value::MutRef(_), Value::MutRef(_)) => true,
-        _ => false,
+// Euclidean algorithm on the magnitudes; only overflows when the result is 2^127
+fn gcd(a: i128, b: i128) -> Result<i128, InterpError> {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
     }
+    i128::try_from(a).map_err(|_| InterpError::IntegerOverflow)
 }
 
-#[allow(unused_variables)]
-pub fn interp(exp: Exp, env: &mut Env) -> Result<Value, InterpError> {
-    match exp {
-        Exp::Int(i) => Ok(Value::Int(i)),
-        Exp::Float(i) => Ok(Value::Float(i)),
-        Exp::Bool(i) => Ok(Value::Bool(i)),
-        Exp::Add { lhs, rhs } => apply_numeric_op(
-            interp(*lhs, env)?,
-            interp(*rhs, env)?,
-            |a, b| Value::Int(a + b),
-            |a, b| Value::Float(a + b),
-        ),
-        Exp::Sub { lhs, rhs } => apply_numeric_op(
-            interp(*lhs, env)?,
-            interp(*rhs, env)?,
-            |a, b| Value::Int(a - b),
-            |a, b| Value::Float(a - b),
-        ),
-        Exp::Mult { lhs, rhs } => apply_numeric_op(
-            interp(*lhs, env)?,
-            interp(*rhs, env)?,
-            |a, b| Value::Int(a * b),
-            |a, b| Value::Float(a * b),
-        ),
-        Exp::Div { lhs, rhs } => div(interp(*lhs, env)?, interp(*rhs, env)?),
-        Exp::Eq { lhs, rhs } => eq(interp(*lhs, env)?, interp(*rhs, env)?),
-        Exp::Gt { lhs, rhs } => apply_comparison(
-            interp(*lhs, env)?,
-            interp(*rhs, env)?,
-            |a, b| a > b,
-            |a, b| a > b,
-        ),
-        Exp::Ge { lhs, rhs } => apply_comparison(
-            interp(*lhs, env)?,
-            interp(*rhs, env)?,
-            |a, b| a >= b,
-            |a, b| a >= b,
-        ),
-        Exp::Lt { lhs, rhs } => apply_comparison(
-            interp(*lhs, env)?,
-            interp(*rhs, env)?,
-            |a, b| a < b,
-            |a, b| a < b,
-        ),
-        Exp::Le { lhs, rhs } => apply_comparison(
-            interp(*lhs, env)?,
-            interp(*rhs, env)?,
-            |a, b| a <= b,
-            |a, b| a <= b,
-        ),
-        Exp::If { cond, lhs, rhs } => {
-            let cond_val = interp(*cond, env)?;
-            match cond_val {
-                Value::Bool(test) => {
-                    let lhs_val = interp(*lhs, env)?;
-                    let rhs_val = interp(*rhs, env)?;
+fn lcm(a: i128, b: i128) -> Result<i128, InterpError> {
+    if a == 0 || b == 0 {
+        return Ok(0);
+    }
+    (a / gcd(a, b)?)
+        .checked_mul(b)
+        .and_then(i128::checked_abs)
+        .ok_or(InterpError::IntegerOverflow)
+}
 
-                    if !check_same_type(&lhs_val, &rhs_val) {
-                        return Err(InterpError::BranchTypeMismatch);
-                    }
+// Call in tail position that hasn't been made yet. It's handed back to trampoline instead of
+// being made where it appears, so tail calls don't grow the stack
+struct TailCall {
+    func: Value,
+    args: Vec<Value>,
+    name: Option<String>,
+    // Innermost span around the call; errors from it are reported there
+    span: Option<Span>,
+}
+
+impl TailCall {
+    fn new(func: Value, args: Vec<Value>, name: Option<String>) -> Self {
+        TailCall {
+            func,
+            args,
+            name,
+            span: None,
+        }
+    }
+}
+
+// Result of evaluating a form in tail position
+enum Tail {
+    Done(Value),
+    Call(TailCall),
+}
+
+// Shape, as same_type sees it, of the value exp evaluates to when that's evident from the form
+// alone, as with literals and the lists and refs built from them. None when it depends on
+// evaluation, such as for variables and calls
+fn static_shape(exp: &Exp) -> Option<Value> {
+    Some(match exp {
+        Exp::Int(_) => Value::Int(0),
+        Exp::Float(_) => Value::Float(0.0),
+        Exp::Bool(_) => Value::Bool(false),
+        Exp::Str(_) => Value::Str(String::new()),
+        Exp::Char(_) => Value::Char('\0'),
+        Exp::Nil | Exp::Cons { .. } => Value::Nil,
+        Exp::Lambda { .. } => Value::Primitive(String::new()),
+        Exp::Box(_) => Value::Box(0),
+        Exp::Ref(e) => Value::Ref(Box::new(static_shape(e)?)),
+        Exp::MutRef(e) => Value::MutRef(Box::new(static_shape(e)?)),
+        Exp::Begin(es) => match es.last() {
+            Some(last) => return static_shape(last),
+            None => Value::Unit,
+        },
+        // Elements whose type isn't evident are left out; the others still have to agree
+        Exp::List(es) => Value::List(es.iter().filter_map(static_shape).collect()),
+        Exp::Spanned { exp, .. } => return static_shape(exp),
+        _ => return None,
+    })
+}
+
+// Reports e at span, unless it already has a more precise location. Exits aren't errors, so
+// they're left as they are
+fn located(span: Option<Span>, e: InterpError) -> InterpError {
+    match (span, e) {
+        (_, e @ (InterpError::At { .. } | InterpError::Exit(_))) | (None, e) => e,
+        (Some(span), e) => InterpError::At {
+            span,
+            source: Box::new(e),
+        },
+    }
+}
+
+// Copy of an env and the store it points into, taken so evaluation can be rolled back
+pub struct Snapshot {
+    env: Env,
+    store: Vec<Option<Value>>,
+}
+
+// Snapshots taken before each REPL input, newest last; the oldest are dropped past limit
+pub struct UndoHistory {
+    snapshots: VecDeque<Snapshot>,
+    limit: usize,
+}
+
+impl UndoHistory {
+    pub fn new(limit: usize) -> Self {
+        UndoHistory {
+            snapshots: VecDeque::new(),
+            limit,
+        }
+    }
+
+    pub fn record(&mut self, interpreter: &Interpreter, env: &Env) {
+        if self.snapshots.len() == self.limit {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(interpreter.snapshot(env));
+    }
+
+    // Restores the most recent snapshot; returns false if there was nothing to undo
+    pub fn undo(&mut self, interpreter: &mut Interpreter, env: &mut Env) -> bool {
+        match self.snapshots.pop_back() {
+            Some(snapshot) => {
+                interpreter.restore(snapshot, env);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// Evaluates expressions, writing any program output to `out`. Output is buffered until flush
+// is called, the program runs (flush), or the interpreter is dropped
+pub struct Interpreter {
+    out: BufWriter<Box<dyn Write>>,
+    // Where read-line reads from; stdin by default
+    pub input: Box<dyn BufRead>,
+    // Where traced functions log their calls; stderr by default
+    pub trace_out: Box<dyn Write>,
+    // Number of traced calls currently in progress, used to indent the trace
+    trace_depth: usize,
+    // Heap storage for boxed values, indexed by Location; None marks a slot freed by gc
+    store: Vec<Option<Value>>,
+    // Values written into boxes, vectors and memo caches since evaluation last started at the
+    // top. They're the only way a begin's boxes can be reached from values older than the begin
+    written: Vec<Value>,
+    // Maximum number of boxes that can be allocated; unlimited when None
+    pub max_allocations: Option<usize>,
+    pub int_width: IntWidth,
+    // Follow IEEE 754 for float division by zero instead of raising DivisionByZero
+    pub ieee_float_div: bool,
+    // Accept any value as a condition rather than only Bools
+    pub truthy_conditions: bool,
+    // Resolve free variables in a closure's body in the env it's called from rather than the
+    // one it was created in
+    pub dynamic_scope: bool,
+    // Warn about letrec functions that call themselves more than once without memoization
+    pub recursion_hints: bool,
+    // Print each form of a top-level begin with its value as it's evaluated
+    pub trace_begin: bool,
+    // Set while the begin being stepped through by interp_top hasn't started yet
+    stepping: bool,
+    // Set while the form about to be evaluated is at the top level, where env holds every
+    // live value. Taken by the first subexpression evaluated; a begin passes it to its forms
+    top_level: bool,
+    // Name of each function call in progress, outermost first
+    call_stack: Vec<String>,
+    // Calls that were in progress when the last error was raised, outermost first. Each call
+    // adds its frame as the error unwinds through it
+    backtrace: Vec<String>,
+    // Depth in call_stack of the outermost frame in backtrace
+    backtrace_depth: usize,
+    // Arguments of the call that just failed, taken back from its bindings so they're only
+    // kept once there's an error to report
+    failed_args: Option<Vec<Value>>,
+    // Display booleans as #t and #f rather than true and false
+    pub scheme_bools: bool,
+    // Have display, debug, display-hex and write-string resolve to Unit rather than the value
+    // they print, so a printed value can't be used by accident
+    pub display_returns_unit: bool,
+    // What display prints for a function, which has no readable form; debug shows its details
+    pub procedure_display: String,
+    // Checks programs for likely mistakes before they're evaluated; off when None
+    pub strict: Option<Strictness>,
+    // Warnings collected during evaluation, in the order they were raised
+    warnings: Vec<Warning>,
+    // Counts evaluations of each kind of expression; off when None
+    pub profile: Option<Profile>,
+    // Fold constant subexpressions of each top-level form before it's evaluated
+    pub fold_constants: bool,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new(Box::new(io::stdout()))
+    }
+}
+
+impl Interpreter {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Interpreter {
+            out: BufWriter::new(out),
+            input: Box::new(io::BufReader::new(io::stdin())),
+            trace_out: Box::new(io::stderr()),
+            trace_depth: 0,
+            store: Vec::new(),
+            written: Vec::new(),
+            max_allocations: None,
+            int_width: IntWidth::default(),
+            ieee_float_div: false,
+            truthy_conditions: false,
+            dynamic_scope: false,
+            recursion_hints: false,
+            trace_begin: false,
+            stepping: false,
+            top_level: false,
+            call_stack: Vec::new(),
+            backtrace: Vec::new(),
+            backtrace_depth: 0,
+            failed_args: None,
+            scheme_bools: false,
+            procedure_display: "#<procedure>".to_string(),
+            display_returns_unit: false,
+            strict: None,
+            warnings: Vec::new(),
+            profile: None,
+            fold_constants: false,
+        }
+    }
+
+    // Writes out any buffered program output
+    pub fn flush(&mut self) -> Result<(), InterpError> {
+        self.out.flush().map_err(|_| InterpError::OutputError)
+    }
 
-                    Ok(if test { lhs_val } else { rhs_val })
+    fn check_width(&self, value: Value) -> Result<Value, InterpError> {
+        match value {
+            Value::Int(i) if !self.int_width.contains(i) => Err(InterpError::IntegerOverflow),
+            Value::Values(vals)
+                if vals
+                    .iter()
+                    .any(|v| matches!(v, Value::Int(i) if !self.int_width.contains(*i))) =>
+            {
+                Err(InterpError::IntegerOverflow)
+            }
+            v => Ok(v),
+        }
+    }
+
+    // Whether a condition holds. Conditions must be Bools unless truthy_conditions is set, in
+    // which case zero, empty strings and empty lists are false and everything else is true
+    fn test(&self, cond: Value) -> Result<bool, InterpError> {
+        match cond {
+            Value::Bool(b) => Ok(b),
+            v if !self.truthy_conditions => Err(InterpError::ConditionNotBoolean(v.type_name())),
+            Value::Int(i) => Ok(i != 0),
+            Value::Float(f) => Ok(f != 0.0),
+            Value::Str(s) => Ok(!s.is_empty()),
+            Value::List(vals) => Ok(!vals.is_empty()),
+            Value::Nil => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    // Evaluates es in order until one tests as settles_on, which is the result, so any later
+    // expressions are never evaluated. Otherwise the result is the last expression
+    fn short_circuit(
+        &mut self,
+        es: Vec<Exp>,
+        settles_on: bool,
+        env: &mut Env,
+    ) -> Result<Value, InterpError> {
+        let mut es = es.into_iter();
+        let Some(last) = es.next_back() else {
+            return Ok(Value::Bool(!settles_on));
+        };
+        for e in es {
+            let v = self.eval(e, env)?;
+            if self.test(v.clone())? == settles_on {
+                return Ok(v);
+            }
+        }
+        self.eval(last, env)
+    }
+
+    // Calls that were in progress when the last uncaught error was raised, outermost first, such
+    // as (f Int(2)) then (f Int(1))
+    pub fn backtrace(&self) -> &[String] {
+        &self.backtrace
+    }
+
+    // Applies func like apply, keeping a frame on the call stack for the backtrace while it runs
+    fn call(&mut self, call: TailCall) -> Result<Value, InterpError> {
+        let frame_name = call
+            .name
+            .clone()
+            .unwrap_or_else(|| function_name(&call.func));
+        self.call_stack.push(frame_name);
+        let depth = self.call_stack.len();
+        let result = self.trampoline(call, true);
+        let name = self.call_stack.pop().unwrap_or_default();
+        // The innermost call that fails starts the backtrace, and each caller the error unwinds
+        // through adds its frame in front
+        if matches!(&result, Err(e) if !matches!(e, InterpError::Exit(_))) {
+            let args = self.failed_args.take().unwrap_or_default();
+            if self.backtrace.is_empty() || self.backtrace_depth == depth + 1 {
+                let mut frame = format!("({}", name);
+                for arg in &args {
+                    frame.push(' ');
+                    frame.push_str(&self.render(arg));
+                }
+                self.backtrace.insert(0, frame + ")");
+                self.backtrace_depth = depth;
+            }
+        }
+        result
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    // Removes and returns the warnings raised so far, so each is only reported once
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Flags lambdas that ignore one of their parameters, either as warnings or as an error
+    // depending on the strictness. Parameters starting with an underscore are meant to be unused
+    pub fn check_strict(&mut self, exp: &Exp) -> Result<(), InterpError> {
+        let Some(strictness) = self.strict else {
+            return Ok(());
+        };
+        if let Exp::Lambda { params, rest, body } = exp {
+            let used = body.free_vars();
+            for param in params.iter().chain(rest) {
+                if param.starts_with('_') || used.contains(param) {
+                    continue;
+                }
+                if strictness == Strictness::Error {
+                    return Err(InterpError::UnusedParameter(param.clone()));
                 }
-                _ => Err(InterpError::ConditionNotBoolean),
+                self.warnings.push(Warning {
+                    kind: WarningKind::UnusedParameter,
+                    message: format!("Parameter '{}' is never used", param),
+                    form: Some(exp.clone()),
+                });
             }
         }
-        Exp::Debug(e) => {
-            let v = interp(*e, env)?;
-            print!("{:?}", v);
-            Ok(v)
+        exp.children()
+            .into_iter()
+            .try_for_each(|e| self.check_strict(e))
+    }
+
+    // Suggests memoizing letrec functions such as naive fibonacci whose calls each make two or
+    // more recursive calls, so the number of calls grows exponentially
+    pub fn check_recursion(&mut self, exp: &Exp) {
+        if !self.recursion_hints {
+            return;
         }
-        Exp::Display(e) => {
-            let v = interp(*e, env)?;
-            match v {
-                Value::Int(i) => print!("{}\n", i),
-                Value::Float(f) => print!("{}\n", f),
-                Value::Bool(b) => print!("{}\n", b),
-                _ => return Err(InterpError::CantDisplay),
+        if let Exp::LetRecMany { bindings, .. } = exp {
+            for (name, func) in bindings {
+                let mut func = func;
+                while let Exp::Spanned { exp, .. } = func {
+                    func = exp;
+                }
+                let Exp::Lambda { body, .. } = func else {
+                    continue;
+                };
+                if self_calls(body, name) >= 2 {
+                    self.warnings.push(Warning {
+                        kind: WarningKind::ExponentialRecursion,
+                        message: format!(
+                            "'{}' calls itself more than once, so it may be slow; consider \
+                             memoizing it",
+                            name
+                        ),
+                        form: Some(func.clone()),
+                    });
+                }
             }
-            Ok(v)
         }
-        Exp::Id(s) => env
-            .get(&s)
-            .ok_or(InterpError::SymbolNotFound(s))
-            .map(|v| v.clone()),
-        Exp::Lambda { arg, body } => Ok(Value::Closure {
-            arg,
-            body: *body,
+        for e in exp.children() {
+            self.check_recursion(e);
+        }
+    }
+
+    // Stores value in the box at loc, returning the value it replaced. A box keeps the type of
+    // the value it was created with
+    fn replace(&mut self, loc: Location, value: Value) -> Result<Value, InterpError> {
+        let old = self.load(loc)?.clone();
+        if !old.same_type(&value) {
+            return Err(InterpError::RefTypeMismatch);
+        }
+        self.note_write(&value);
+        self.store[loc] = Some(value);
+        Ok(old)
+    }
+
+    fn allocate(&mut self, value: Value) -> Result<Location, InterpError> {
+        if self
+            .max_allocations
+            .is_some_and(|max| self.live_count() >= max)
+        {
+            return Err(InterpError::HeapExhausted);
+        }
+        // Freed slots are never reused so a stale location can't alias a new value
+        self.store.push(Some(value));
+        Ok(self.store.len() - 1)
+    }
+
+    fn load(&self, loc: Location) -> Result<&Value, InterpError> {
+        self.store
+            .get(loc)
+            .and_then(Option::as_ref)
+            .ok_or(InterpError::FreedBox)
+    }
+
+    // Follows refs and boxes until a non-pointer value is reached
+    fn deref(&self, value: Value) -> Result<Value, InterpError> {
+        let mut visited = HashSet::new();
+        let mut current = value;
+        for _ in 0..MAX_DEREF_DEPTH {
+            current = match current {
+                Value::Ref(v) | Value::MutRef(v) => *v,
+                Value::Box(loc) => {
+                    if !visited.insert(loc) {
+                        return Err(InterpError::RefCycleDetected);
+                    }
+                    self.load(loc)?.clone()
+                }
+                v => return Ok(v),
+            };
+        }
+        Err(InterpError::RefCycleDetected)
+    }
+
+    // Formats a value for presentation, showing the contents of boxes instead of their locations
+    pub fn render(&self, value: &Value) -> String {
+        self.render_with(value, &mut Vec::new(), &mut Vec::new())
+    }
+
+    // visiting and vectors are the boxes and vectors being rendered, so cycles are cut short
+    fn render_with(
+        &self,
+        value: &Value,
+        visiting: &mut Vec<Location>,
+        vectors: &mut Vec<VectorPtr>,
+    ) -> String {
+        match value {
+            Value::Box(loc) if visiting.contains(loc) => "Box(...)".to_string(),
+            Value::Box(loc) => match self.store.get(*loc).and_then(Option::as_ref) {
+                Some(v) => {
+                    visiting.push(*loc);
+                    let inner = self.render_with(v, visiting, vectors);
+                    visiting.pop();
+                    format!("Box({})", inner)
+                }
+                None => "Box(<freed>)".to_string(),
+            },
+            Value::Ref(v) => format!("Ref({})", self.render_with(v, visiting, vectors)),
+            Value::MutRef(v) => format!("MutRef({})", self.render_with(v, visiting, vectors)),
+            Value::Pair(car, cdr) => format!(
+                "Pair({}, {})",
+                self.render_with(car, visiting, vectors),
+                self.render_with(cdr, visiting, vectors)
+            ),
+            Value::List(vals) => format!(
+                "List({})",
+                vals.iter()
+                    .map(|v| self.render_with(v, visiting, vectors))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Value::Values(vals) => format!(
+                "Values({})",
+                vals.iter()
+                    .map(|v| self.render_with(v, visiting, vectors))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Value::Vector(vals) if vectors.contains(&Rc::as_ptr(vals)) => "Vector(...)".to_string(),
+            Value::Vector(vals) => {
+                vectors.push(Rc::as_ptr(vals));
+                let inner = vals
+                    .borrow()
+                    .iter()
+                    .map(|v| self.render_with(v, visiting, vectors))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                vectors.pop();
+                format!("Vector({})", inner)
+            }
+            Value::Closure { .. }
+            | Value::Memo { .. }
+            | Value::Traced { .. }
+            | Value::Rec { .. }
+            | Value::Primitive(_) => self.procedure_display.clone(),
+            v => format!("{:?}", v),
+        }
+    }
+
+    // Number of store slots that have not been freed
+    pub fn live_count(&self) -> usize {
+        self.store.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    // Captures env and the store so they can be put back with restore
+    pub fn snapshot(&self, env: &Env) -> Snapshot {
+        Snapshot {
             env: env.clone(),
-        }),
-        Exp::App { func, arg } => match interp(*func, env)? {
+            store: self.store.clone(),
+        }
+    }
+
+    // Rolls env and the store back to how they were when snapshot was taken. Output that has
+    // already been written isn't undone. Boxes allocated since can still be reachable from
+    // outside env, such as from a memo cache, so their slots are kept but freed
+    pub fn restore(&mut self, snapshot: Snapshot, env: &mut Env) {
+        let len = self.store.len();
+        *env = snapshot.env;
+        self.store = snapshot.store;
+        self.store.resize(len, None);
+    }
+
+    // Frees every store slot not reachable from roots; returns the number of slots freed
+    pub fn gc(&mut self, roots: &Env) -> usize {
+        let mut marked = vec![false; self.store.len()];
+        self.mark(
+            roots.values().collect(),
+            &mut marked,
+            0,
+            &mut HashSet::new(),
+        );
+        self.sweep(&marked, 0)
+    }
+
+    // Marks every store slot from start onwards that is reachable from pending, where
+    // marked[i] stands for slot start + i. Boxes before start aren't looked through. Vectors
+    // already looked through are in vectors, so one that contains itself is only looked
+    // through once
+    fn mark<'a>(
+        &'a self,
+        mut pending: Vec<&'a Value>,
+        marked: &mut [bool],
+        start: usize,
+        vectors: &mut HashSet<VectorPtr>,
+    ) {
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Box(loc) if *loc >= start => {
+                    if !marked[*loc - start] {
+                        marked[*loc - start] = true;
+                        pending.extend(self.store[*loc].as_ref());
+                    }
+                }
+                Value::Box(_) => {}
+                Value::Ref(v) | Value::MutRef(v) => pending.push(v),
+                Value::Pair(car, cdr) => pending.extend([&**car, &**cdr]),
+                Value::List(vals) | Value::Values(vals) => pending.extend(vals),
+                Value::Closure { env, .. } => pending.extend(env.values()),
+                Value::Rec { group, .. } => pending.extend(group.env.values()),
+                Value::Traced { func, .. } => pending.push(func),
+                Value::Memo { func, cache } => {
+                    pending.push(func);
+                    self.mark(cache.borrow().values().collect(), marked, start, vectors);
+                }
+                Value::Vector(vals) => {
+                    if vectors.insert(Rc::as_ptr(vals)) {
+                        self.mark(vals.borrow().iter().collect(), marked, start, vectors);
+                    }
+                }
+                Value::Int(_)
+                | Value::Float(_)
+                | Value::Bool(_)
+                | Value::Str(_)
+                | Value::Char(_)
+                | Value::Primitive(_)
+                | Value::Nil
+                | Value::Unit
+                | Value::Bytes(_)
+                | Value::Code(_)
+                | Value::Moved => {}
+            }
+        }
+    }
+
+    // Frees the unmarked slots from start onwards, where marked[i] stands for slot start + i;
+    // returns the number of slots freed
+    fn sweep(&mut self, marked: &[bool], start: usize) -> usize {
+        let mut freed = 0;
+        for (slot, marked) in self.store[start..].iter_mut().zip(marked) {
+            if !marked && slot.take().is_some() {
+                freed += 1;
+            }
+        }
+        freed
+    }
+
+    // Frees boxes allocated since start that can't be reached from result or from a value
+    // written since written_start. Anything older than the begin that reaches one of its boxes
+    // does so through such a write, and the begin's own defines go out of scope with it
+    fn drop_scope(&mut self, start: usize, written_start: usize, result: &Value) {
+        if self.store.len() == start {
+            return;
+        }
+        let mut marked = vec![false; self.store.len() - start];
+        let written = self.written[written_start..].iter();
+        self.mark(
+            written.chain([result]).collect(),
+            &mut marked,
+            start,
+            &mut HashSet::new(),
+        );
+        self.sweep(&marked, start);
+    }
+
+    // Remembers a value written somewhere older values can reach it from, unless it can't hold
+    // a box
+    fn note_write(&mut self, value: &Value) {
+        if !matches!(
+            value,
+            Value::Int(_)
+                | Value::Float(_)
+                | Value::Bool(_)
+                | Value::Str(_)
+                | Value::Char(_)
+                | Value::Primitive(_)
+                | Value::Nil
+                | Value::Unit
+                | Value::Bytes(_)
+                | Value::Code(_)
+                | Value::Moved
+        ) {
+            self.written.push(value.clone());
+        }
+    }
+
+    // Evaluates a top-level form. With trace_begin, each form of a begin is printed with its
+    // value as it's evaluated
+    pub fn interp_top(&mut self, exp: Exp, env: &mut Env) -> Result<Value, InterpError> {
+        let exp = if self.fold_constants {
+            fold::fold_constants_for(exp, self)
+        } else {
+            exp
+        };
+        let mut inner = &exp;
+        while let Exp::Spanned { exp, .. } = inner {
+            inner = exp;
+        }
+        self.stepping = self.trace_begin && matches!(inner, Exp::Begin(_));
+        self.backtrace.clear();
+        self.written.clear();
+        self.top_level = true;
+        self.eval_values(exp, env)
+    }
+
+    // Result of a form that printed v
+    fn printed(&self, v: Value) -> Value {
+        if self.display_returns_unit {
+            Value::Unit
+        } else {
+            v
+        }
+    }
+
+    // Prints a step of a begin being stepped through; form is None otherwise
+    fn show_step(&mut self, form: Option<String>, value: &Value) -> Result<(), InterpError> {
+        if let Some(form) = form {
+            let rendered = self.render(value);
+            writeln!(self.out, "{} => {}", form, rendered).map_err(|_| InterpError::OutputError)?;
+        }
+        Ok(())
+    }
+
+    // Makes call, then each tail call the callee ends in, without growing the stack. With
+    // framed, each tail call takes over the frame of the call it was made from
+    fn trampoline(&mut self, mut call: TailCall, framed: bool) -> Result<Value, InterpError> {
+        let mut span = call.span;
+        loop {
+            match self.apply_once(call.func, call.args, call.name.as_deref()) {
+                Ok(Tail::Done(v)) => return Ok(v),
+                Ok(Tail::Call(next)) => call = next,
+                Err(e) => return Err(located(span, e)),
+            }
+            if framed {
+                if let Some(frame) = self.call_stack.last_mut() {
+                    let name = call.name.clone();
+                    *frame = name.unwrap_or_else(|| function_name(&call.func));
+                }
+            }
+            span = call.span.or(span);
+        }
+    }
+
+    // Evaluates the function and arguments of an application, leaving the call to be made
+    fn eval_app(
+        &mut self,
+        func: Exp,
+        args: Vec<Exp>,
+        env: &mut Env,
+    ) -> Result<TailCall, InterpError> {
+        let name = variable_name(&func).map(str::to_string);
+        let mut func_val = self.eval(func, env)?;
+        if self.dynamic_scope {
+            func_val = in_env(func_val, env);
+        }
+        let mut arg_vals = Vec::with_capacity(args.len());
+        for arg in args {
+            let name = variable_name(&arg).map(str::to_string);
+            let v = self.eval(arg, env)?;
+            // Passing a box by name gives the callee ownership of it
+            if let (Value::Box(_), Some(name)) = (&v, name) {
+                env.insert(name, Value::Moved);
+            }
+            arg_vals.push(v);
+        }
+        Ok(TailCall::new(func_val, arg_vals, name))
+    }
+
+    // Evaluates exp in tail position, where a call is returned to be made by the caller
+    fn eval_tail(&mut self, exp: Exp, env: &mut Env) -> Result<Tail, InterpError> {
+        match exp {
+            Exp::App { .. } | Exp::If { .. } | Exp::Begin(_) | Exp::Spanned { .. } => {
+                if let Some(profile) = &mut self.profile {
+                    profile.record(&exp);
+                }
+                self.tail_form(exp, env)
+            }
+            exp => self.eval_values(exp, env).map(Tail::Done),
+        }
+    }
+
+    // Evaluates the forms that can end in a tail call
+    fn tail_form(&mut self, exp: Exp, env: &mut Env) -> Result<Tail, InterpError> {
+        match exp {
+            Exp::App { func, args } => Ok(Tail::Call(self.eval_app(*func, args, env)?)),
+            Exp::If { cond, lhs, rhs } => {
+                let cond_val = self.eval(*cond, env)?;
+                // Only the taken branch is evaluated, so the branches' types are compared from
+                // their forms. A mismatch is caught when both types are evident, as with
+                // literals; otherwise the branches aren't checked
+                if let (Some(l), Some(r)) = (static_shape(&lhs), static_shape(&rhs)) {
+                    if !l.same_type(&r) {
+                        return Err(InterpError::BranchTypeMismatch);
+                    }
+                }
+                let taken = if self.test(cond_val)? { lhs } else { rhs };
+                self.eval_tail(*taken, env)
+            }
+            Exp::Begin(es) => {
+                let start = self.store.len();
+                let written_start = self.written.len();
+                // Only the outermost begin is stepped through
+                let stepping = std::mem::take(&mut self.stepping);
+                let top_level = std::mem::take(&mut self.top_level);
+                // Defines are only visible inside the begin, so it gets its own scope if it has any
+                let mut scope;
+                let env = if es.iter().any(|e| e.defined_name().is_some()) {
+                    // Checked up front so no form runs before the mistake is reported
+                    if let Some(name) = forward_reference(&es, env) {
+                        return Err(InterpError::ForwardReference(name));
+                    }
+                    scope = env.clone();
+                    &mut scope
+                } else {
+                    env
+                };
+                let mut es = es.into_iter();
+                let Some(last) = es.next_back() else {
+                    return Ok(Tail::Done(Value::Unit));
+                };
+                for e in es {
+                    if matches!(
+                        e,
+                        Exp::Int(_)
+                            | Exp::Float(_)
+                            | Exp::Str(_)
+                            | Exp::Bool(_)
+                            | Exp::Nil
+                            | Exp::Id(_)
+                            | Exp::Lambda { .. }
+                    ) {
+                        self.warnings.push(Warning {
+                            kind: WarningKind::UnusedValue,
+                            message: format!("Result of {:?} is never used", e),
+                            form: Some(e.clone()),
+                        });
+                    }
+                    let form = stepping.then(|| e.to_string());
+                    self.top_level = top_level;
+                    let v = self.eval(e, env)?;
+                    self.show_step(form, &v)?;
+                }
+                // Each form is dropped once it has been evaluated, and the last is in tail
+                // position. A stepped begin makes its last call itself so its value can be shown
+                self.top_level = top_level;
+                let result = if stepping {
+                    let form = last.to_string();
+                    let result = self.eval_values(last, env)?;
+                    self.show_step(Some(form), &result)?;
+                    Tail::Done(result)
+                } else {
+                    self.eval_tail(last, env)?
+                };
+                match &result {
+                    Tail::Done(v) => self.drop_scope(start, written_start, v),
+                    Tail::Call(call) => {
+                        let mut roots = call.args.clone();
+                        roots.push(call.func.clone());
+                        self.drop_scope(start, written_start, &Value::Values(roots));
+                    }
+                }
+                Ok(result)
+            }
+            // The innermost span is kept so errors point at the smallest failing form
+            Exp::Spanned { span, exp } => match self.eval_tail(*exp, env) {
+                Ok(Tail::Call(mut call)) => {
+                    call.span.get_or_insert(span);
+                    Ok(Tail::Call(call))
+                }
+                result => result.map_err(|e| located(Some(span), e)),
+            },
+            exp => self.eval_values(exp, env).map(Tail::Done),
+        }
+    }
+
+    // Evaluates an expression that must produce exactly one value
+    pub fn interp(&mut self, exp: Exp, env: &mut Env) -> Result<Value, InterpError> {
+        self.backtrace.clear();
+        self.written.clear();
+        self.eval(exp, env)
+    }
+
+    // Evaluates an expression that may produce multiple values; used for results that are
+    // returned rather than consumed
+    pub fn interp_values(&mut self, exp: Exp, env: &mut Env) -> Result<Value, InterpError> {
+        self.backtrace.clear();
+        self.written.clear();
+        self.eval_values(exp, env)
+    }
+
+    // interp without clearing the backtrace, for evaluating subexpressions
+    fn eval(&mut self, exp: Exp, env: &mut Env) -> Result<Value, InterpError> {
+        match self.eval_values(exp, env)? {
+            Value::Values(_) => Err(InterpError::UnexpectedMultipleValues),
+            v => Ok(v),
+        }
+    }
+
+    // Evaluates each form in order in a shared env, returning every form's result. With
+    // stop_on_error, forms after the first error aren't evaluated and have no result
+    pub fn eval_many(
+        &mut self,
+        forms: Vec<Exp>,
+        env: &mut Env,
+        stop_on_error: bool,
+    ) -> Vec<Result<Value, InterpError>> {
+        let mut results = Vec::with_capacity(forms.len());
+        for form in forms {
+            self.backtrace.clear();
+            self.written.clear();
+            self.top_level = true;
+            let result = self.eval_values(form, env);
+            let failed = result.is_err();
+            results.push(result);
+            if failed && stop_on_error {
+                break;
+            }
+        }
+        results
+    }
+
+    // interp_values without clearing the backtrace; used for results that are returned rather
+    // than consumed, such as function bodies and if branches
+    #[allow(unused_variables)]
+    fn eval_values(&mut self, exp: Exp, env: &mut Env) -> Result<Value, InterpError> {
+        if let Some(profile) = &mut self.profile {
+            profile.record(&exp);
+        }
+        let top_level = std::mem::take(&mut self.top_level);
+        match exp {
+            Exp::Int(i) => self.check_width(Value::Int(i)),
+            Exp::Float(i) => Ok(Value::Float(i)),
+            Exp::Str(s) => Ok(Value::Str(s)),
+            Exp::Char(c) => Ok(Value::Char(c)),
+            Exp::Bool(i) => Ok(Value::Bool(i)),
+            Exp::Add { lhs, rhs } => {
+                let v = add(self.eval(*lhs, env)?, self.eval(*rhs, env)?)?;
+                self.check_width(v)
+            }
+            Exp::Sub { lhs, rhs } => {
+                let v = apply_numeric_op(
+                    self.eval(*lhs, env)?,
+                    self.eval(*rhs, env)?,
+                    |a, b| a.checked_sub(b).map(Value::Int),
+                    |a, b| Value::Float(a - b),
+                )?;
+                self.check_width(v)
+            }
+            Exp::Mult { lhs, rhs } => {
+                let v = apply_numeric_op(
+                    self.eval(*lhs, env)?,
+                    self.eval(*rhs, env)?,
+                    |a, b| a.checked_mul(b).map(Value::Int),
+                    |a, b| Value::Float(a * b),
+                )?;
+                self.check_width(v)
+            }
+            Exp::Div { lhs, rhs } => {
+                let lhs = self.eval(*lhs, env)?;
+                let v = div(lhs, self.eval(*rhs, env)?, self.ieee_float_div)?;
+                self.check_width(v)
+            }
+            Exp::Eq { lhs, rhs } => eq(self.eval(*lhs, env)?, self.eval(*rhs, env)?),
+            Exp::Gt { lhs, rhs } => apply_comparison(
+                self.eval(*lhs, env)?,
+                self.eval(*rhs, env)?,
+                |a, b| a > b,
+                |a, b| a > b,
+            ),
+            Exp::Ge { lhs, rhs } => apply_comparison(
+                self.eval(*lhs, env)?,
+                self.eval(*rhs, env)?,
+                |a, b| a >= b,
+                |a, b| a >= b,
+            ),
+            Exp::Lt { lhs, rhs } => apply_comparison(
+                self.eval(*lhs, env)?,
+                self.eval(*rhs, env)?,
+                |a, b| a < b,
+                |a, b| a < b,
+            ),
+            Exp::Le { lhs, rhs } => apply_comparison(
+                self.eval(*lhs, env)?,
+                self.eval(*rhs, env)?,
+                |a, b| a <= b,
+                |a, b| a <= b,
+            ),
+            Exp::App { .. } | Exp::If { .. } | Exp::Begin(_) | Exp::Spanned { .. } => {
+                self.top_level = top_level && matches!(exp, Exp::Begin(_) | Exp::Spanned { .. });
+                match self.tail_form(exp, env)? {
+                    Tail::Done(v) => Ok(v),
+                    Tail::Call(call) => self.call(call),
+                }
+            }
+            // Only values bound in the current environment are treated as roots, which is all
+            // of them at the top level. Anywhere else, values held by enclosing calls or
+            // half-evaluated forms aren't visible from env, so nothing is collected
+            Exp::Gc if top_level => Ok(Value::Int(self.gc(env) as i128)),
+            Exp::Gc => Ok(Value::Int(0)),
+            Exp::Id(s) => match env.get(&s) {
+                Some(Value::Moved) => Err(InterpError::UseAfterMove(s)),
+                Some(Value::Rec { group, index }) => {
+                    let mut rec_env = group.env.clone();
+                    bind_rec_group(group, &mut rec_env);
+                    let lambda = group.bindings[*index].1.clone();
+                    self.eval(lambda, &mut rec_env)
+                }
+                Some(v) => Ok(v.clone()),
+                None if PRIMITIVES.contains(&s.as_str()) => Ok(Value::Primitive(s)),
+                None => {
+                    let suggestion = closest_name(&s, env.keys());
+                    Err(InterpError::SymbolNotFound(s, suggestion))
+                }
+            },
+            // Only the bindings the body can refer to are captured. Code run by eval isn't
+            // known until the call, so a body using it keeps the whole env
+            Exp::Lambda { params, rest, body } => {
+                let env = if body.uses_eval() {
+                    env.clone()
+                } else {
+                    let mut names = body.free_vars();
+                    for name in params.iter().chain(&rest) {
+                        names.remove(name);
+                    }
+                    capture(env, names)
+                };
+                Ok(Value::Closure {
+                    params,
+                    rest,
+                    body: *body,
+                    env,
+                })
+            }
+            Exp::Nil => Ok(Value::Nil),
+            Exp::Cons { car, cdr } => Ok(Value::Pair(
+                Box::new(self.eval(*car, env)?),
+                Box::new(self.eval(*cdr, env)?),
+            )),
+            // As with a lambda, the group only keeps the bindings its expressions can refer to,
+            // since the env is copied each time one of its names is looked up
+            Exp::LetRecMany { bindings, body } => {
+                let group_env = if bindings.iter().any(|(_, e)| e.uses_eval()) {
+                    env.clone()
+                } else {
+                    let mut names: HashSet<String> =
+                        bindings.iter().flat_map(|(_, e)| e.free_vars()).collect();
+                    for (name, _) in &bindings {
+                        names.remove(name);
+                    }
+                    capture(env, names)
+                };
+                let group = Rc::new(RecGroup {
+                    bindings,
+                    env: group_env,
+                });
+                let mut body_env = env.clone();
+                bind_rec_group(&group, &mut body_env);
+                self.eval_values(*body, &mut body_env)
+            }
+            Exp::Not(e) => match self.eval(*e, env)? {
+                Value::Bool(b) => Ok(Value::Bool(!b)),
+                _ => Err(InterpError::NotABool),
+            },
+            Exp::And(es) => self.short_circuit(es, false, env),
+            Exp::Or(es) => self.short_circuit(es, true, env),
+            // Boxes allocated in a begin that don't escape it are freed when it ends
+            Exp::Box(v) => {
+                let v = self.eval(*v, env)?;
+                Ok(Value::Box(self.allocate(v)?))
+            }
+            Exp::Unbox(b) => match self.eval(*b, env)? {
+                Value::Box(loc) => self.load(loc).cloned(),
+                _ => Err(InterpError::NotABox),
+            },
+            // The place is looked at before it's evaluated, since an element of a vector isn't a
+            // value that can be set on its own
+            Exp::Set { lhs, rhs } => match unspanned(*lhs) {
+                Exp::VectorRef { vector, index } => self.set_index(*vector, *index, *rhs, env),
+                lhs => match self.eval(lhs, env)? {
+                    Value::Box(loc) => {
+                        let v = self.eval(*rhs, env)?;
+                        self.replace(loc, v.clone())?;
+                        Ok(v)
+                    }
+                    v => Err(InterpError::InvalidPlace(v.type_name())),
+                },
+            },
+            // Less common forms live in their own function to keep this frame small, since
+            // it is on the stack once per level of recursion in the interpreted program
+            Exp::Arity(_)
+            | Exp::Sum(_)
+            | Exp::Product(_)
+            | Exp::Nth { .. }
+            | Exp::StringRef { .. }
+            | Exp::StringToList(_)
+            | Exp::StringSplit { .. }
+            | Exp::StringJoin { .. }
+            | Exp::NumberToString { .. }
+            | Exp::Contains { .. }
+            | Exp::RoundTo { .. }
+            | Exp::Range { .. }
+            | Exp::ReadBytes(_)
+            | Exp::BytesLength(_)
+            | Exp::BytesRef { .. }
+            | Exp::MakeVector { .. }
+            | Exp::VectorRef { .. }
+            | Exp::VectorSet { .. }
+            | Exp::Repeat { .. }
+            | Exp::Ref(_)
+            | Exp::MutRef(_)
+            | Exp::ReadLine
+            | Exp::Flush
+            | Exp::Exit(_)
+            | Exp::Read(_)
+            | Exp::Eval(_)
+            | Exp::FunctionToString(_)
+            | Exp::AssertEq { .. }
+            | Exp::AssertError(_)
+            | Exp::WriteString(_)
+            | Exp::Deref(_)
+            | Exp::Apply { .. }
+            | Exp::GetAndSet { .. }
+            | Exp::Display(_)
+            | Exp::Debug(_)
+            | Exp::DisplayHex(_)
+            | Exp::Time(_)
+            | Exp::Car(_)
+            | Exp::Cdr(_)
+            | Exp::IsPair(_)
+            | Exp::IsNull(_)
+            | Exp::CharToInt(_)
+            | Exp::IntToChar(_)
+            | Exp::List(_)
+            | Exp::Values(_)
+            | Exp::Define { .. }
+            | Exp::LetValues { .. }
+            | Exp::Quotient { .. }
+            | Exp::Remainder { .. }
+            | Exp::Gcd { .. }
+            | Exp::Lcm { .. }
+            | Exp::Memoize(_)
+            | Exp::Trace { .. }
+            | Exp::DivMod { .. }
+            | Exp::FormatWidth { .. }
+            | Exp::Ann { .. }
+            | Exp::Quasiquote(_)
+            | Exp::Unquote(_) => self.interp_builtin(exp, env),
+        }
+    }
+
+    // Sets element index of vector to value, returning the new value
+    fn set_index(
+        &mut self,
+        vector: Exp,
+        index: Exp,
+        value: Exp,
+        env: &mut Env,
+    ) -> Result<Value, InterpError> {
+        let Value::Vector(vals) = self.eval(vector, env)? else {
+            return Err(InterpError::NotAVector);
+        };
+        let Value::Int(index) = self.eval(index, env)? else {
+            return Err(InterpError::NotAnInteger);
+        };
+        let value = self.eval(value, env)?;
+        let mut vals = vals.borrow_mut();
+        let len = vals.len();
+        let slot = usize::try_from(index)
+            .ok()
+            .and_then(|i| vals.get_mut(i))
+            .ok_or(InterpError::IndexOutOfBounds { index, len })?;
+        *slot = value.clone();
+        self.note_write(&value);
+        Ok(value)
+    }
+
+    // Evaluates the forms that eval_values hands off
+    fn interp_builtin(&mut self, exp: Exp, env: &mut Env) -> Result<Value, InterpError> {
+        match exp {
+            Exp::CharToInt(e) => match self.eval(*e, env)? {
+                Value::Char(c) => Ok(Value::Int(c as i128)),
+                _ => Err(InterpError::NotAChar),
+            },
+            Exp::IntToChar(e) => match self.eval(*e, env)? {
+                Value::Int(i) => u32::try_from(i)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .map(Value::Char)
+                    .ok_or(InterpError::ConversionOutOfRange),
+                _ => Err(InterpError::NotAnInteger),
+            },
+            Exp::Debug(e) => {
+                let v = self.eval(*e, env)?;
+                write!(self.out, "{:?}", v).map_err(|_| InterpError::OutputError)?;
+                Ok(self.printed(v))
+            }
+            Exp::Display(e) => {
+                let v = self.eval(*e, env)?;
+                match &v {
+                    Value::Int(i) => writeln!(self.out, "{}", i),
+                    Value::Float(f) => writeln!(self.out, "{}", format_float(*f)),
+                    Value::Bool(b) if self.scheme_bools => {
+                        writeln!(self.out, "{}", if *b { "#t" } else { "#f" })
+                    }
+                    Value::Bool(b) => writeln!(self.out, "{}", b),
+                    Value::Str(s) => writeln!(self.out, "{}", s),
+                    Value::Char(c) => writeln!(self.out, "{}", c),
+                    Value::Box(_)
+                    | Value::Ref(_)
+                    | Value::MutRef(_)
+                    | Value::Pair(..)
+                    | Value::List(_)
+                    | Value::Vector(_) => {
+                        let rendered = self.render(&v);
+                        writeln!(self.out, "{}", rendered)
+                    }
+                    Value::Closure { .. }
+                    | Value::Memo { .. }
+                    | Value::Traced { .. }
+                    | Value::Rec { .. }
+                    | Value::Primitive(_) => writeln!(self.out, "{}", self.procedure_display),
+                    _ => return Err(InterpError::CantDisplay),
+                }
+                .map_err(|_| InterpError::OutputError)?;
+                Ok(self.printed(v))
+            }
+            Exp::DisplayHex(e) => match self.eval(*e, env)? {
+                // Negative numbers are printed as their two's complement in the selected width
+                Value::Int(i) => {
+                    let bits = self.int_width.twos_complement(i);
+                    writeln!(self.out, "{:#x}", bits).map_err(|_| InterpError::OutputError)?;
+                    Ok(self.printed(Value::Int(i)))
+                }
+                _ => Err(InterpError::NotAnInteger),
+            },
+            Exp::Time(e) => {
+                let start = Instant::now();
+                let v = self.eval_values(*e, env)?;
+                eprintln!("Elapsed time: {:?}", start.elapsed());
+                Ok(v)
+            }
+            Exp::Arity(e) => {
+                let func = self.eval(*e, env)?;
+                arity(&func).map(|n| Value::Int(n as i128))
+            }
+            Exp::Memoize(e) => match self.eval(*e, env)? {
+                func @ Value::Closure { .. } => Ok(Value::Memo {
+                    func: Box::new(func),
+                    cache: Rc::new(RefCell::new(HashMap::new())),
+                }),
+                _ => Err(InterpError::NotAClosure),
+            },
+            Exp::Trace { name, func } => {
+                let func = self.eval(*func, env)?;
+                arity(&func)?;
+                Ok(Value::Traced {
+                    name,
+                    func: Box::new(func),
+                })
+            }
+            Exp::Car(p) => match self.eval(*p, env)? {
+                Value::Pair(car, _) => Ok(*car),
+                _ => Err(InterpError::NotAPair),
+            },
+            Exp::Cdr(p) => match self.eval(*p, env)? {
+                Value::Pair(_, cdr) => Ok(*cdr),
+                _ => Err(InterpError::NotAPair),
+            },
+            Exp::IsPair(e) => Ok(Value::Bool(matches!(
+                self.eval(*e, env)?,
+                Value::Pair(_, _)
+            ))),
+            Exp::IsNull(e) => Ok(Value::Bool(matches!(self.eval(*e, env)?, Value::Nil))),
+            Exp::List(es) => Ok(Value::List(
+                es.into_iter()
+                    .map(|e| self.eval(e, env))
+                    .collect::<Result<Vec<Value>, InterpError>>()?,
+            )),
+            Exp::Values(es) => {
+                let mut vals = es
+                    .into_iter()
+                    .map(|e| self.eval(e, env))
+                    .collect::<Result<Vec<Value>, InterpError>>()?;
+                if vals.len() == 1 {
+                    Ok(vals.remove(0))
+                } else {
+                    Ok(Value::Values(vals))
+                }
+            }
+            Exp::LetValues { names, value, body } => {
+                let vals = match self.eval_values(*value, env)? {
+                    Value::Values(vals) => vals,
+                    v => vec![v],
+                };
+                if vals.len() != names.len() {
+                    return Err(InterpError::ValueCountMismatch {
+                        expected: names.len(),
+                        got: vals.len(),
+                    });
+                }
+                let mut body_env = env.clone();
+                body_env.extend(names.into_iter().zip(vals));
+                self.eval_values(*body, &mut body_env)
+            }
+            Exp::DivMod { lhs, rhs } => {
+                let lhs = self.eval(*lhs, env)?;
+                let v = apply_integer_div(lhs, self.eval(*rhs, env)?, |a, b| {
+                    Some(Value::Values(vec![
+                        Value::Int(a.checked_div(b)?),
+                        Value::Int(a.checked_rem(b)?),
+                    ]))
+                })?;
+                self.check_width(v)
+            }
+            Exp::FormatWidth { value, width } => {
+                let text = match self.eval(*value, env)? {
+                    Value::Int(i) => i.to_string(),
+                    Value::Float(f) => format_float(f),
+                    _ => return Err(InterpError::NotANumber),
+                };
+                let width = match self.eval(*width, env)? {
+                    Value::Int(w) if w < 0 => return Err(InterpError::NegativeWidth),
+                    Value::Int(w) => usize::try_from(w).unwrap_or(usize::MAX),
+                    _ => return Err(InterpError::NotAnInteger),
+                };
+                Ok(Value::Str(format!("{:>width$}", text)))
+            }
+            Exp::Quotient { lhs, rhs } => {
+                let lhs = self.eval(*lhs, env)?;
+                let v = apply_integer_div(lhs, self.eval(*rhs, env)?, |a, b| {
+                    a.checked_div(b).map(Value::Int)
+                })?;
+                self.check_width(v)
+            }
+            // Overflows whenever quotient would, even though the remainder itself is 0
+            Exp::Remainder { lhs, rhs } => {
+                let lhs = self.eval(*lhs, env)?;
+                let rhs = self.eval(*rhs, env)?;
+                let q = apply_integer_div(lhs.clone(), rhs.clone(), |a, b| {
+                    a.checked_div(b).map(Value::Int)
+                })?;
+                self.check_width(q)?;
+                let v = apply_integer_div(lhs, rhs, |a, b| a.checked_rem(b).map(Value::Int))?;
+                self.check_width(v)
+            }
+            Exp::Gcd { lhs, rhs } => match (self.eval(*lhs, env)?, self.eval(*rhs, env)?) {
+                (Value::Int(a), Value::Int(b)) => self.check_width(Value::Int(gcd(a, b)?)),
+                _ => Err(InterpError::NotANumber),
+            },
+            Exp::Lcm { lhs, rhs } => match (self.eval(*lhs, env)?, self.eval(*rhs, env)?) {
+                (Value::Int(a), Value::Int(b)) => self.check_width(Value::Int(lcm(a, b)?)),
+                _ => Err(InterpError::NotANumber),
+            },
+            Exp::Ann { exp, ty } => match self.eval(*exp, env)? {
+                v if has_type(&v, ty) => Ok(v),
+                _ => Err(InterpError::AnnotationMismatch(ty)),
+            },
+            Exp::Nth { list, index } => {
+                let Value::List(mut vals) = self.eval(*list, env)? else {
+                    return Err(InterpError::NotAList);
+                };
+                let Value::Int(index) = self.eval(*index, env)? else {
+                    return Err(InterpError::NotAnInteger);
+                };
+                let len = vals.len();
+                match usize::try_from(index) {
+                    Ok(i) if i < len => Ok(vals.swap_remove(i)),
+                    _ => Err(InterpError::IndexOutOfBounds { index, len }),
+                }
+            }
+            Exp::StringRef { string, index } => {
+                let Value::Str(s) = self.eval(*string, env)? else {
+                    return Err(InterpError::NotAString);
+                };
+                let Value::Int(index) = self.eval(*index, env)? else {
+                    return Err(InterpError::NotAnInteger);
+                };
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|i| s.chars().nth(i))
+                    .map(Value::Char)
+                    .ok_or_else(|| InterpError::IndexOutOfBounds {
+                        index,
+                        len: s.chars().count(),
+                    })
+            }
+            Exp::StringToList(e) => match self.eval(*e, env)? {
+                Value::Str(s) => Ok(Value::List(s.chars().map(Value::Char).collect())),
+                _ => Err(InterpError::NotAString),
+            },
+            Exp::Sum(e) => {
+                let v = self.eval(*e, env)?;
+                let total = fold_numbers(
+                    v,
+                    0,
+                    |a, b| a.checked_add(b).map(Value::Int),
+                    |a, b| Value::Float(a + b),
+                )?;
+                self.check_width(total)
+            }
+            Exp::Product(e) => {
+                let v = self.eval(*e, env)?;
+                let total = fold_numbers(
+                    v,
+                    1,
+                    |a, b| a.checked_mul(b).map(Value::Int),
+                    |a, b| Value::Float(a * b),
+                )?;
+                self.check_width(total)
+            }
+            Exp::NumberToString { value, precision } => {
+                let value = self.eval(*value, env)?;
+                let precision = match precision {
+                    Some(precision) => match self.eval(*precision, env)? {
+                        Value::Int(p) if p < 0 => return Err(InterpError::NegativePlaces),
+                        // The most the formatter supports
+                        Value::Int(p) => {
+                            Some(u16::try_from(p).map_err(|_| InterpError::TooManyPlaces)? as usize)
+                        }
+                        _ => return Err(InterpError::NotAnInteger),
+                    },
+                    None => None,
+                };
+                match (value, precision) {
+                    (Value::Int(i), _) => Ok(Value::Str(i.to_string())),
+                    (Value::Float(f), Some(p)) => Ok(Value::Str(format!("{:.*}", p, f))),
+                    (Value::Float(f), None) => Ok(Value::Str(format_float(f))),
+                    _ => Err(InterpError::NotANumber),
+                }
+            }
+            // Elements of a different type than value are just unequal to it
+            Exp::Contains { list, value } => {
+                let Value::List(vals) = self.eval(*list, env)? else {
+                    return Err(InterpError::NotAList);
+                };
+                let value = self.eval(*value, env)?;
+                Ok(Value::Bool(vals.iter().any(|v| {
+                    v.same_type(&value) && matches!(v.value_eq(&value), Ok(true))
+                })))
+            }
+            // Ints are rounded as the Float they convert to. If scaling overflows, places is past
+            // the precision of a Float, so the value is returned as it is
+            Exp::RoundTo { value, places } => {
+                let x = match self.eval(*value, env)? {
+                    Value::Float(f) => f,
+                    Value::Int(i) => i as f64,
+                    _ => return Err(InterpError::NotANumber),
+                };
+                // Checked before clamping so a hugely negative count isn't clamped to i32::MAX
+                let places = match self.eval(*places, env)? {
+                    Value::Int(p) if p < 0 => return Err(InterpError::NegativePlaces),
+                    Value::Int(p) => i32::try_from(p).unwrap_or(i32::MAX),
+                    _ => return Err(InterpError::NotAnInteger),
+                };
+                let scale = 10f64.powi(places);
+                let scaled = x * scale;
+                if !scaled.is_finite() {
+                    return Ok(Value::Float(x));
+                }
+                Ok(Value::Float(scaled.round() / scale))
+            }
+            // A negative step counts down, stopping before end
+            Exp::Range { start, end, step } => {
+                let (Value::Int(start), Value::Int(end), Value::Int(step)) = (
+                    self.eval(*start, env)?,
+                    self.eval(*end, env)?,
+                    self.eval(*step, env)?,
+                ) else {
+                    return Err(InterpError::NotAnInteger);
+                };
+                if step == 0 {
+                    return Err(InterpError::ZeroStep);
+                }
+                let ints = std::iter::successors(Some(start), |i| i.checked_add(step))
+                    .take_while(|&i| if step > 0 { i < end } else { i > end })
+                    .map(Value::Int)
+                    .collect();
+                Ok(Value::List(ints))
+            }
+            Exp::StringSplit { string, sep } => {
+                let Value::Str(string) = self.eval(*string, env)? else {
+                    return Err(InterpError::NotAString);
+                };
+                let Value::Str(sep) = self.eval(*sep, env)? else {
+                    return Err(InterpError::NotAString);
+                };
+                if sep.is_empty() {
+                    return Err(InterpError::EmptySeparator);
+                }
+                Ok(Value::List(
+                    string
+                        .split(sep.as_str())
+                        .map(|part| Value::Str(part.to_string()))
+                        .collect(),
+                ))
+            }
+            Exp::StringJoin { list, sep } => {
+                let Value::List(vals) = self.eval(*list, env)? else {
+                    return Err(InterpError::NotAList);
+                };
+                let Value::Str(sep) = self.eval(*sep, env)? else {
+                    return Err(InterpError::NotAString);
+                };
+                let parts = vals
+                    .into_iter()
+                    .map(|v| match v {
+                        Value::Str(s) => Ok(s),
+                        _ => Err(InterpError::NotAString),
+                    })
+                    .collect::<Result<Vec<String>, InterpError>>()?;
+                Ok(Value::Str(parts.join(&sep)))
+            }
+            Exp::ReadBytes(path) => match self.eval(*path, env)? {
+                Value::Str(path) => std::fs::read(&path)
+                    .map(Value::Bytes)
+                    .map_err(|e| InterpError::IoError(format!("{}: {}", path, e))),
+                _ => Err(InterpError::NotAString),
+            },
+            Exp::BytesLength(e) => match self.eval(*e, env)? {
+                Value::Bytes(bytes) => Ok(Value::Int(bytes.len() as i128)),
+                _ => Err(InterpError::NotBytes),
+            },
+            Exp::BytesRef { bytes, index } => {
+                let Value::Bytes(bytes) = self.eval(*bytes, env)? else {
+                    return Err(InterpError::NotBytes);
+                };
+                let Value::Int(index) = self.eval(*index, env)? else {
+                    return Err(InterpError::NotAnInteger);
+                };
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|i| bytes.get(i))
+                    .map(|b| Value::Int(*b as i128))
+                    .ok_or(InterpError::IndexOutOfBounds {
+                        index,
+                        len: bytes.len(),
+                    })
+            }
+            Exp::MakeVector { len, init } => {
+                let len = match self.eval(*len, env)? {
+                    Value::Int(n) if n < 0 => return Err(InterpError::NegativeLength),
+                    Value::Int(n) => usize::try_from(n).map_err(|_| InterpError::HeapExhausted)?,
+                    _ => return Err(InterpError::NotAnInteger),
+                };
+                let init = self.eval(*init, env)?;
+                // Asked for up front so a length too large to allocate is an error rather than
+                // an abort
+                let mut vals = Vec::new();
+                vals.try_reserve_exact(len)
+                    .map_err(|_| InterpError::HeapExhausted)?;
+                vals.resize(len, init);
+                Ok(Value::Vector(Rc::new(RefCell::new(vals))))
+            }
+            Exp::VectorRef { vector, index } => {
+                let Value::Vector(vals) = self.eval(*vector, env)? else {
+                    return Err(InterpError::NotAVector);
+                };
+                let Value::Int(index) = self.eval(*index, env)? else {
+                    return Err(InterpError::NotAnInteger);
+                };
+                let vals = vals.borrow();
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|i| vals.get(i))
+                    .cloned()
+                    .ok_or(InterpError::IndexOutOfBounds {
+                        index,
+                        len: vals.len(),
+                    })
+            }
+            Exp::VectorSet {
+                vector,
+                index,
+                value,
+            } => {
+                self.set_index(*vector, *index, *value, env)?;
+                Ok(Value::Unit)
+            }
+            Exp::Repeat { count, body } => {
+                let count = match self.eval(*count, env)? {
+                    Value::Int(n) => u128::try_from(n).map_err(|_| InterpError::NegativeCount)?,
+                    _ => return Err(InterpError::NotAnInteger),
+                };
+                let mut result = Value::Unit;
+                for _ in 0..count {
+                    result = self.eval((*body).clone(), env)?;
+                }
+                Ok(result)
+            }
+            // Refs hold a copy of the value they point at
+            Exp::Ref(b) => Ok(Value::Ref(Box::new(self.eval(*b, env)?))),
+            Exp::MutRef(b) => Ok(Value::MutRef(Box::new(self.eval(*b, env)?))),
+            Exp::ReadLine => {
+                let mut line = String::new();
+                let read = self
+                    .input
+                    .read_line(&mut line)
+                    .map_err(|e| InterpError::IoError(e.to_string()))?;
+                if read == 0 {
+                    return Ok(Value::Nil);
+                }
+                let len = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(len);
+                Ok(Value::Str(line))
+            }
+            Exp::Flush => {
+                self.flush()?;
+                Ok(Value::Unit)
+            }
+            Exp::Read(e) => match self.eval(*e, env)? {
+                Value::Str(s) => Exp::try_from(s.as_str())
+                    .map(Value::Code)
+                    .map_err(|e| InterpError::ReadError(e.to_string())),
+                _ => Err(InterpError::NotAString),
+            },
+            Exp::Eval(e) => match self.eval(*e, env)? {
+                // Defines made by the code stay inside it
+                Value::Code(code) => self.eval_values(code, &mut env.clone()),
+                _ => Err(InterpError::NotCode),
+            },
+            Exp::FunctionToString(e) => function_source(&self.eval(*e, env)?)
+                .map(Value::Str)
+                .ok_or(InterpError::NotAClosure),
+            Exp::GetAndSet { lhs, rhs } => match self.eval(*lhs, env)? {
+                Value::Box(loc) => {
+                    let v = self.eval(*rhs, env)?;
+                    self.replace(loc, v)
+                }
+                _ => Err(InterpError::NotABox),
+            },
+            Exp::Define { name, value } => {
+                let v = self.eval(*value, env)?;
+                env.insert(name, v);
+                Ok(Value::Unit)
+            }
+            // Values of different types are never equal here rather than being an error
+            Exp::AssertEq { actual, expected } => {
+                let actual = self.eval(*actual, env)?;
+                let expected = self.eval(*expected, env)?;
+                if actual.same_type(&expected) && actual.value_eq(&expected)? {
+                    Ok(Value::Unit)
+                } else {
+                    Err(InterpError::AssertionFailed {
+                        actual: self.render(&actual),
+                        expected: self.render(&expected),
+                    })
+                }
+            }
+            // Exits still stop the program since they aren't errors
+            Exp::AssertError(e) => match self.eval(*e, env) {
+                Err(InterpError::Exit(code)) => Err(InterpError::Exit(code)),
+                // The error was expected, so it shouldn't be reported with a later one
+                Err(_) => {
+                    self.backtrace.clear();
+                    Ok(Value::Unit)
+                }
+                Ok(v) => Err(InterpError::ExpectedError(self.render(&v))),
+            },
+            Exp::Exit(code) => match self.eval(*code, env)? {
+                Value::Int(i) => Err(InterpError::Exit(
+                    i32::try_from(i).map_err(|_| InterpError::ConversionOutOfRange)?,
+                )),
+                _ => Err(InterpError::NotAnInteger),
+            },
+            Exp::WriteString(e) => match self.eval(*e, env)? {
+                Value::Str(s) => {
+                    write!(self.out, "{}", s).map_err(|_| InterpError::OutputError)?;
+                    Ok(self.printed(Value::Str(s)))
+                }
+                _ => Err(InterpError::NotAString),
+            },
+            Exp::Apply { func, args_list } => {
+                let name = variable_name(&func).map(str::to_string);
+                let mut func = self.eval(*func, env)?;
+                if self.dynamic_scope {
+                    func = in_env(func, env);
+                }
+                match self.eval(*args_list, env)? {
+                    Value::List(args) => self.call(TailCall::new(func, args, name)),
+                    _ => Err(InterpError::NotAList),
+                }
+            }
+            Exp::Deref(r) => {
+                let v = self.eval(*r, env)?;
+                self.deref(v)
+            }
+            // Lists in the template are Exp::List, so only the unquoted parts need evaluating
+            Exp::Quasiquote(template) => self.eval(*template, env),
+            Exp::Unquote(e) => self.eval(*e, env),
+            Exp::Int(_)
+            | Exp::Float(_)
+            | Exp::Str(_)
+            | Exp::Id(_)
+            | Exp::Add { .. }
+            | Exp::Sub { .. }
+            | Exp::Mult { .. }
+            | Exp::Div { .. }
+            | Exp::Lambda { .. }
+            | Exp::App { .. }
+            | Exp::If { .. }
+            | Exp::Eq { .. }
+            | Exp::Gt { .. }
+            | Exp::Ge { .. }
+            | Exp::Lt { .. }
+            | Exp::Le { .. }
+            | Exp::Begin(_)
+            | Exp::Not(_)
+            | Exp::And(_)
+            | Exp::Or(_)
+            | Exp::Bool(_)
+            | Exp::Box(_)
+            | Exp::Unbox(_)
+            | Exp::Gc
+            | Exp::Set { .. }
+            | Exp::Nil
+            | Exp::Cons { .. }
+            | Exp::Char(_)
+            | Exp::LetRecMany { .. }
+            | Exp::Spanned { .. } => unreachable!("{:?} is evaluated by eval_values", exp),
+        }
+    }
+
+    // Applies func to its arguments; closures take one argument per param plus any number for a
+    // rest param, + and * take any number and the other primitives take two. A closure without a
+    // rest param given some but not all of its arguments returns a closure awaiting the others.
+    // Arity errors refer to func by name when it was called through one
+    fn apply(
+        &mut self,
+        func: Value,
+        args: Vec<Value>,
+        name: Option<&str>,
+    ) -> Result<Value, InterpError> {
+        self.trampoline(TailCall::new(func, args, name.map(str::to_string)), false)
+    }
+
+    // Applies func to args, handing back any call the body of a closure ends in
+    fn apply_once(
+        &mut self,
+        func: Value,
+        args: Vec<Value>,
+        name: Option<&str>,
+    ) -> Result<Tail, InterpError> {
+        match func {
             Value::Closure {
-                arg: c_arg,
+                mut params,
+                rest: None,
                 body,
                 env: mut c_env,
-            } => interp(body, {
-                c_env.insert(c_arg, interp(*arg, env)?);
-                &mut c_env
-            }),
+            } if !args.is_empty() && args.len() < params.len() => {
+                let remaining = params.split_off(args.len());
+                c_env.extend(params.into_iter().zip(args));
+                Ok(Tail::Done(Value::Closure {
+                    params: remaining,
+                    rest: None,
+                    body,
+                    env: c_env,
+                }))
+            }
+            Value::Closure {
+                params,
+                rest,
+                body,
+                env: mut c_env,
+            } => {
+                let arity_ok = match rest {
+                    Some(_) => args.len() >= params.len(),
+                    None => args.len() == params.len(),
+                };
+                if !arity_ok {
+                    let expected = params.len();
+                    let at_least = rest.is_some();
+                    let func = Value::Closure {
+                        params,
+                        rest,
+                        body,
+                        env: c_env,
+                    };
+                    let got = args.len();
+                    self.failed_args = Some(args);
+                    return Err(InterpError::ArityMismatch {
+                        func: name.map_or_else(|| function_name(&func), str::to_string),
+                        expected,
+                        at_least,
+                        got,
+                    });
+                }
+                let mut args = args.into_iter();
+                c_env.extend(params.iter().cloned().zip(args.by_ref()));
+                if let Some(rest) = &rest {
+                    c_env.insert(rest.clone(), Value::List(args.collect()));
+                }
+                let result = self.eval_tail(body, &mut c_env);
+                // The arguments are moved into the bindings rather than copied for the
+                // backtrace, so they're taken back from there, as they are after the body ran
+                if result.is_err() {
+                    let mut args: Vec<Value> =
+                        params.iter().filter_map(|p| c_env.remove(p)).collect();
+                    if let Some(Value::List(extra)) = rest.and_then(|r| c_env.remove(&r)) {
+                        args.extend(extra);
+                    }
+                    self.failed_args = Some(args);
+                }
+                result
+            }
+            // The function they wrap records the arguments if it fails
+            func @ (Value::Memo { .. } | Value::Traced { .. }) => {
+                self.apply_value(func, args, name).map(Tail::Done)
+            }
+            func => {
+                let shown = args.clone();
+                let result = self.apply_value(func, args, name).map(Tail::Done);
+                if result.is_err() {
+                    self.failed_args = Some(shown);
+                }
+                result
+            }
+        }
+    }
+
+    // Applies anything other than a closure
+    fn apply_value(
+        &mut self,
+        func: Value,
+        args: Vec<Value>,
+        name: Option<&str>,
+    ) -> Result<Value, InterpError> {
+        match func {
+            Value::Traced { name, func } => {
+                let shown = args
+                    .iter()
+                    .map(|arg| self.render(arg))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let indent = "  ".repeat(self.trace_depth);
+                writeln!(self.trace_out, "{}call {}({})", indent, name, shown)
+                    .map_err(|_| InterpError::OutputError)?;
+                self.trace_depth += 1;
+                let result = self.apply(*func, args, Some(&name));
+                self.trace_depth -= 1;
+                let result = result?;
+                let rendered = self.render(&result);
+                writeln!(
+                    self.trace_out,
+                    "{}{}({}) => {}",
+                    indent, name, shown, rendered
+                )
+                .map_err(|_| InterpError::OutputError)?;
+                Ok(result)
+            }
+            Value::Memo { func, cache } => {
+                // Only single arguments that can be used as a key are cached; anything else is
+                // passed straight through
+                let key = match &args[..] {
+                    [arg] => MemoKey::from_value(arg),
+                    _ => None,
+                };
+                if let Some(cached) = key.as_ref().and_then(|k| cache.borrow().get(k).cloned()) {
+                    return Ok(cached);
+                }
+                let result = self.apply(*func, args, name)?;
+                if let Some(key) = key {
+                    cache.borrow_mut().insert(key, result.clone());
+                    self.note_write(&result);
+                }
+                Ok(result)
+            }
+            Value::Primitive(op) if op == "+" || op == "*" => {
+                let mut args = args.into_iter();
+                let first = args
+                    .next()
+                    .unwrap_or(Value::Int(if op == "+" { 0 } else { 1 }));
+                args.try_fold(first, |acc, v| self.apply_primitive(&op, acc, v))
+            }
+            // - negates a single operand and subtracts the rest from the first otherwise
+            Value::Primitive(op) if op == "-" => {
+                let mut args = args.into_iter();
+                match (args.next(), args.len()) {
+                    (None, _) => Err(InterpError::ArityMismatch {
+                        func: name.unwrap_or(&op).to_string(),
+                        expected: 1,
+                        at_least: true,
+                        got: 0,
+                    }),
+                    (Some(Value::Int(i)), 0) => {
+                        let v = i.checked_neg().ok_or(InterpError::IntegerOverflow)?;
+                        self.check_width(Value::Int(v))
+                    }
+                    (Some(Value::Float(f)), 0) => Ok(Value::Float(-f)),
+                    (Some(_), 0) => Err(InterpError::NotANumber),
+                    (Some(first), _) => {
+                        args.try_fold(first, |acc, v| self.apply_primitive(&op, acc, v))
+                    }
+                }
+            }
+            Value::Primitive(op) => match <[Value; 2]>::try_from(args) {
+                Ok([lhs, rhs]) => self.apply_primitive(&op, lhs, rhs),
+                Err(args) => Err(InterpError::ArityMismatch {
+                    func: name.unwrap_or(&op).to_string(),
+                    expected: 2,
+                    at_least: false,
+                    got: args.len(),
+                }),
+            },
             _ => Err(InterpError::NotAClosure),
-        },
-        Exp::Begin(es) => Err(InterpError::NotImplemented("Begin".to_string())),
-        Exp::Ref(b) => Err(InterpError::NotImplemented("Ref".to_string())),
-        Exp::MutRef(b) => Err(InterpError::NotImplemented("MutRef".to_string())),
-        Exp::Box(v) => Err(InterpError::NotImplemented("Box".to_string())),
-        Exp::Unbox(b) => Err(InterpError::NotImplemented("Unbox".to_string())),
-        Exp::Deref(r) => Err(InterpError::NotImplemented("Deref".to_string())),
-        Exp::Set { lhs, rhs } => Err(InterpError::NotImplemented("Set".to_string())),
+        }
+    }
+
+    fn apply_primitive(&self, op: &str, lhs: Value, rhs: Value) -> Result<Value, InterpError> {
+        let v = match op {
+            "+" => add(lhs, rhs),
+            "-" => apply_numeric_op(
+                lhs,
+                rhs,
+                |a, b| a.checked_sub(b).map(Value::Int),
+                |a, b| Value::Float(a - b),
+            ),
+            "*" => apply_numeric_op(
+                lhs,
+                rhs,
+                |a, b| a.checked_mul(b).map(Value::Int),
+                |a, b| Value::Float(a * b),
+            ),
+            "/" => div(lhs, rhs, self.ieee_float_div),
+            "=" => eq(lhs, rhs),
+            "<" => apply_comparison(lhs, rhs, |a, b| a < b, |a, b| a < b),
+            ">" => apply_comparison(lhs, rhs, |a, b| a > b, |a, b| a > b),
+            "<=" => apply_comparison(lhs, rhs, |a, b| a <= b, |a, b| a <= b),
+            ">=" => apply_comparison(lhs, rhs, |a, b| a >= b, |a, b| a >= b),
+            _ => Err(InterpError::NotImplemented(op.to_string())),
+        }?;
+        self.check_width(v)
     }
 }
+
+// Evaluates a program with a fresh interpreter writing to stdout
+pub fn interp(exp: Exp, env: &mut Env) -> Result<Value, InterpError> {
+    Interpreter::default().interp_top(exp, env)
+}
@@ -2,26 +2,179 @@ use std::{
     collections::HashMap,
     env,
     fs::read_to_string,
-    io::{self, Write},
+    io::{self, IsTerminal, Read, Write},
+    process,
 };
 
+use args::Command;
+
+pub mod args;
+pub mod fold;
 pub mod interp;
 pub mod parse;
+pub mod sexp;
+#[cfg(test)]
+pub mod test_args;
+#[cfg(test)]
+pub mod test_fold;
+#[cfg(test)]
+pub mod test_interp;
 #[cfg(test)]
 pub mod test_parse;
 
+// Number of inputs the REPL can undo
+const MAX_UNDO: usize = 100;
+
+// Evaluates each top-level form of a program as it's parsed, printing each result.
+// Stops at the first error, or when the program exits, returning the exit code
+fn run(
+    interpreter: &mut interp::Interpreter,
+    parser: &mut parse::Parser,
+    env: &mut interp::Env,
+    input: &str,
+) -> Option<i32> {
+    for form in parser.forms(input) {
+        match form {
+            Ok(exp) => {
+                let result = interpreter
+                    .check_strict(&exp)
+                    .and_then(|_| interpreter.interp_top(exp, env));
+                // Program output is buffered, so write it out before the result is printed
+                let flushed = interpreter.flush();
+                print_warnings(interpreter);
+                match result.and_then(|value| flushed.map(|_| value)) {
+                    Ok(value) => println!("{}", interpreter.render(&value)),
+                    Err(interp::InterpError::Exit(code)) => return Some(code),
+                    Err(e) => {
+                        println!("Evaluation error: {}", e);
+                        for call in interpreter.backtrace().iter().rev() {
+                            println!("  in {}", call);
+                        }
+                        return None;
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Parse error: {}", e);
+                return None;
+            }
+        }
+    }
+    None
+}
+
+// Prints the warnings raised since they were last printed to stderr, so they don't mix with
+// the program's output
+fn print_warnings(interpreter: &mut interp::Interpreter) {
+    for warning in interpreter.take_warnings() {
+        eprintln!("Warning: {}", warning.message);
+    }
+}
+
+// An interpreter with the options asked for on the command line
+fn interpreter(flags: &args::Flags) -> interp::Interpreter {
+    let mut interpreter = interp::Interpreter::default();
+    interpreter.strict = flags.strict;
+    interpreter.fold_constants = flags.fold;
+    interpreter
+}
+
+// A parser that keeps source spans so evaluation errors can say where they happened
+fn parser() -> parse::Parser {
+    let mut parser = parse::Parser::default();
+    parser.track_spans = true;
+    parser
+}
+
+// Exits the process if the program asked to
+fn exit_if_requested(code: Option<i32>) {
+    if let Some(code) = code {
+        process::exit(code);
+    }
+}
+
+// Parses input without evaluating it and describes the tree of each top-level form, a line
+// per form, stopping at the first parse error
+fn show_ast(input: &str) -> String {
+    let mut lines = Vec::new();
+    for form in parse::parse_forms(input) {
+        match form {
+            Ok(exp) => lines.push(format!("{:?}", exp)),
+            Err(e) => {
+                lines.push(format!("Parse error: {}", e));
+                break;
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+// Checks that every form in input parses and passes the strict checks, printing the first
+// error and any warnings
+fn check(input: &str, flags: &args::Flags) -> bool {
+    let mut interpreter = interpreter(flags);
+    for form in parse::parse_forms(input) {
+        let result = match form {
+            Ok(exp) => interpreter
+                .check_strict(&exp)
+                .map_err(|e| format!("Error: {}", e)),
+            Err(e) => Err(format!("Parse error: {}", e)),
+        };
+        print_warnings(&mut interpreter);
+        if let Err(e) = result {
+            println!("{}", e);
+            return false;
+        }
+    }
+    println!("OK");
+    true
+}
+
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        let filename = &args[1];
-        let input = read_to_string(filename)?;
-        match parse::Exp::try_from(&input[..]) {
-            Ok(exp) => match interp::interp(exp, &mut HashMap::new()) {
-                Ok(value) => println!("{:?}", value),
-                Err(e) => println!("Evaluation error: {}", e),
-            },
-            Err(e) => println!("Parse error: {}", e),
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (flags, args) = args::parse_flags(&args);
+    let command = match args::parse_args(args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, args::USAGE);
+            process::exit(2);
+        }
+    };
+    match command {
+        Command::Run(filename) => exit_if_requested(run(
+            &mut interpreter(&flags),
+            &mut parser(),
+            &mut HashMap::new(),
+            &read_to_string(filename)?,
+        )),
+        Command::Eval(expr) => exit_if_requested(run(
+            &mut interpreter(&flags),
+            &mut parser(),
+            &mut HashMap::new(),
+            &expr,
+        )),
+        Command::Ast(filename) => println!("{}", show_ast(&read_to_string(filename)?)),
+        Command::Check(filename) => {
+            if !check(&read_to_string(filename)?, &flags) {
+                process::exit(1);
+            }
         }
+        Command::Repl => repl(&flags)?,
+    }
+    Ok(())
+}
+
+fn repl(flags: &args::Flags) -> io::Result<()> {
+    // Input piped in from another program is treated as a program rather than REPL lines
+    if !io::stdin().is_terminal() {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        exit_if_requested(run(
+            &mut interpreter(flags),
+            &mut parser(),
+            &mut HashMap::new(),
+            &input,
+        ));
         return Ok(());
     }
     println!("Welcome to the expression evaluator REPL!");
@@ -31,8 +184,16 @@ fn main() -> io::Result<()> {
     println!("  (+ 4 5)");
     println!("  (* 3 (+ 2 4))");
     println!("  (if (> 5 3) 1 2)");
+    println!("Prefix an expression with :ast to see how it parses.");
+    println!("Enter :undo to revert the previous input.");
+    println!("Enter :trace-begin to toggle showing each step of a begin.");
     println!();
 
+    let mut interpreter = interpreter(flags);
+    // Bindings, boxes and macros persist between inputs
+    let mut parser = parser();
+    let mut env = HashMap::new();
+    let mut history = interp::UndoHistory::new(MAX_UNDO);
     loop {
         // Print prompt and flush to ensure it appears
         print!("> ");
@@ -50,13 +211,35 @@ fn main() -> io::Result<()> {
             break;
         }
 
+        if let Some(rest) = input.strip_prefix(":ast") {
+            println!("{}", show_ast(rest.trim()));
+            continue;
+        }
+
+        if input == ":trace-begin" {
+            interpreter.trace_begin = !interpreter.trace_begin;
+            let state = if interpreter.trace_begin { "on" } else { "off" };
+            println!("Begin tracing is {}", state);
+            continue;
+        }
+
+        if input == ":undo" {
+            if !history.undo(&mut interpreter, &mut env) {
+                println!("Nothing to undo");
+            }
+            continue;
+        }
+
+        // Blank lines do nothing, so they aren't recorded as inputs :undo could revert
+        if input.is_empty() {
+            continue;
+        }
+
         // Parse and evaluate the input
-        match parse::Exp::try_from(input) {
-            Ok(exp) => match interp::interp(exp, &mut HashMap::new()) {
-                Ok(value) => println!("{:?}", value),
-                Err(e) => println!("Evaluation error: {}", e),
-            },
-            Err(e) => println!("Parse error: {}", e),
+        history.record(&interpreter, &env);
+        if run(&mut interpreter, &mut parser, &mut env, input).is_some() {
+            println!("Goodbye!");
+            break;
         }
     }
 
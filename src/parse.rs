@@ -1,15 +1,21 @@
-use sexp::Sexp;
+use crate::sexp::{self, Sexp, Span, SpanTree};
+use phf::phf_set;
+use std::borrow::BorrowMut;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use thiserror::Error;
 
 #[derive(Clone, PartialEq)]
 pub enum Exp {
     // Integer
-    Int(i64),
+    Int(i128),
 
     // Float
     Float(f64),
 
+    // String
+    Str(String),
+
     // Symbolic identifier
     Id(String),
 
@@ -35,18 +41,24 @@ pub enum Exp {
         rhs: Box<Exp>,
     },
 
-    // Lambda function
+    // Lambda function; written (lambda x body) for a single param, or with a list of params such
+    // as (lambda (x y) body) or (lambda () body). A rest param, written (lambda (x . rest) body)
+    // or (lambda (x &rest rest) body), collects any extra arguments into a list
     Lambda {
-        arg: String,
+        params: Vec<String>,
+        rest: Option<String>,
         body: Box<Exp>,
     },
 
-    // Application of a function
+    // Application of a function to any number of arguments
     App {
         func: Box<Exp>,
-        arg: Box<Exp>,
+        args: Vec<Exp>,
     },
 
+    // Number of arguments a function takes; resolves to an Int
+    Arity(Box<Exp>),
+
     // Conditional; cond must resolve to a Bool; resolves to lhs when cond is true, otherwise rhs
     // lhs and rhs must resolve to the same type
     If {
@@ -55,12 +67,14 @@ pub enum Exp {
         rhs: Box<Exp>,
     },
 
-    // Equality; lhs and rhs must resolve to numbers
+    // Equality; lhs and rhs must resolve to values of the same type
     Eq {
         lhs: Box<Exp>,
         rhs: Box<Exp>,
     },
 
+    // Ordering comparisons; lhs and rhs must both be Ints or both be Floats. As with arithmetic,
+    // an Int and a Float aren't converted to be compared, so mixing them is an error
     Gt {
         lhs: Box<Exp>,
         rhs: Box<Exp>,
@@ -84,6 +98,113 @@ pub enum Exp {
     // Sequence of expressions; resolves to the last expression
     Begin(Vec<Exp>),
 
+    // Negation of a Bool; other values are an error even with truthy conditions
+    Not(Box<Exp>),
+
+    // Total of a list of numbers of one type; Int(0) when empty
+    Sum(Box<Exp>),
+
+    // Product of a list of numbers of one type; Int(1) when empty
+    Product(Box<Exp>),
+
+    // Evaluates expressions in order until one is false, resolving to it, or else to the last;
+    // true when empty
+    And(Vec<Exp>),
+
+    // Evaluates expressions in order until one is true, resolving to it, or else to the last;
+    // false when empty
+    Or(Vec<Exp>),
+
+    // Element of a list at a zero-based index
+    Nth {
+        list: Box<Exp>,
+        index: Box<Exp>,
+    },
+
+    // Character of a string at a zero-based index, counting Unicode scalar values
+    StringRef {
+        string: Box<Exp>,
+        index: Box<Exp>,
+    },
+
+    // List of the characters of a string
+    StringToList(Box<Exp>),
+
+    // List of the parts of a string between occurrences of a non-empty separator
+    StringSplit {
+        string: Box<Exp>,
+        sep: Box<Exp>,
+    },
+
+    // Single string of a list of strings with a separator between each
+    StringJoin {
+        list: Box<Exp>,
+        sep: Box<Exp>,
+    },
+
+    // String of a number as display prints it. A precision gives Floats that many digits after
+    // the decimal point, rounding the last; Ints ignore it
+    NumberToString {
+        value: Box<Exp>,
+        precision: Option<Box<Exp>>,
+    },
+
+    // Whether any element of a list is equal to value, as compared by =
+    Contains {
+        list: Box<Exp>,
+        value: Box<Exp>,
+    },
+
+    // Float rounded to a non-negative number of decimal places, with halves away from zero
+    RoundTo {
+        value: Box<Exp>,
+        places: Box<Exp>,
+    },
+
+    // List of ints from start up to but not including end, counting by a non-zero step
+    Range {
+        start: Box<Exp>,
+        end: Box<Exp>,
+        step: Box<Exp>,
+    },
+
+    // Contents of the file at a path, as bytes
+    ReadBytes(Box<Exp>),
+
+    // Number of bytes in a byte buffer
+    BytesLength(Box<Exp>),
+
+    // Byte of a byte buffer at a zero-based index, as an Int
+    BytesRef {
+        bytes: Box<Exp>,
+        index: Box<Exp>,
+    },
+
+    // Vector of len elements, each a copy of init
+    MakeVector {
+        len: Box<Exp>,
+        init: Box<Exp>,
+    },
+
+    // Element of a vector at a zero-based index
+    VectorRef {
+        vector: Box<Exp>,
+        index: Box<Exp>,
+    },
+
+    // Replaces the element of a vector at a zero-based index in place; resolves to Unit
+    VectorSet {
+        vector: Box<Exp>,
+        index: Box<Exp>,
+        value: Box<Exp>,
+    },
+
+    // Evaluates body count times; resolves to the last result, or Unit if count is 0
+    Repeat {
+        count: Box<Exp>,
+        body: Box<Exp>,
+    },
+
     // Boolean
     Bool(bool),
 
@@ -99,20 +220,243 @@ pub enum Exp {
     // Get the value stored in a box
     Unbox(Box<Exp>),
 
+    // Free boxes that are unreachable from the current environment; resolves to the number freed
+    Gc,
+
+    // Next line of input without its line ending, or nil at the end of input
+    ReadLine,
+
+    // Writes out program output that is still buffered; resolves to Unit
+    Flush,
+
+    // Stops the program with the exit code the expression resolves to
+    Exit(Box<Exp>),
+
+    // Parses the string the expression resolves to into code without evaluating it
+    Read(Box<Exp>),
+
+    // Evaluates code produced by read in a child of the current env, so its defines stay inside
+    Eval(Box<Exp>),
+
+    // Source of the lambda a function was made from, without the env it captured
+    FunctionToString(Box<Exp>),
+
+    // Raises an error showing both values unless actual equals expected; resolves to Unit
+    AssertEq {
+        actual: Box<Exp>,
+        expected: Box<Exp>,
+    },
+
+    // Resolves to Unit if evaluating the expression raises an error, and is an error otherwise
+    AssertError(Box<Exp>),
+
+    // Writes a string to the output without a trailing newline
+    WriteString(Box<Exp>),
+
     // Get the value stored in a ref
     Deref(Box<Exp>),
 
+    // Applies func to the elements of the list args_list as its arguments
+    Apply {
+        func: Box<Exp>,
+        args_list: Box<Exp>,
+    },
+
     // Set the value stored in a mutable ref
     Set {
         lhs: Box<Exp>,
         rhs: Box<Exp>,
     },
 
+    // Like set, but resolves to the value stored before rather than the new one
+    GetAndSet {
+        lhs: Box<Exp>,
+        rhs: Box<Exp>,
+    },
+
     // Print the value of a num or bool to stdout
     Display(Box<Exp>),
 
     // Print the result of Exp.pp to stdout
     Debug(Box<Exp>),
+
+    // Print the value of an Int to stdout in hexadecimal
+    DisplayHex(Box<Exp>),
+
+    // Print how long the expression took to evaluate to stderr; resolves to its value
+    Time(Box<Exp>),
+
+    // The empty list
+    Nil,
+
+    // Construct a pair; cdr is usually another pair or nil, but can be any value
+    Cons {
+        car: Box<Exp>,
+        cdr: Box<Exp>,
+    },
+
+    // Get the first element of a pair
+    Car(Box<Exp>),
+
+    // Get the second element of a pair
+    Cdr(Box<Exp>),
+
+    // Check if a value is a pair; resolves to a Bool
+    IsPair(Box<Exp>),
+
+    // Check if a value is the empty list; resolves to a Bool
+    IsNull(Box<Exp>),
+
+    // Character literal, written #\A; #\space and #\newline name the whitespace characters
+    Char(char),
+
+    // Unicode scalar value of a Char as an Int
+    CharToInt(Box<Exp>),
+
+    // Char with the given Unicode scalar value
+    IntToChar(Box<Exp>),
+
+    // Builds a list from the values of each expression
+    List(Vec<Exp>),
+
+    // Multiple values; only valid where the result is bound by let-values or returned
+    Values(Vec<Exp>),
+
+    // Binds name to value for the rest of the enclosing begin; resolves to Unit. The value
+    // can't refer to name, so recursive helpers need letrec
+    Define {
+        name: String,
+        value: Box<Exp>,
+    },
+
+    // Binds each name to the corresponding value produced by value, then evaluates body
+    LetValues {
+        names: Vec<String>,
+        value: Box<Exp>,
+        body: Box<Exp>,
+    },
+
+    // Binds each name to its lambda so that every lambda can refer to all of the names, then
+    // evaluates body
+    LetRecMany {
+        bindings: Vec<(String, Exp)>,
+        body: Box<Exp>,
+    },
+
+    // Integer division truncating toward zero; lhs and rhs must resolve to Ints
+    Quotient {
+        lhs: Box<Exp>,
+        rhs: Box<Exp>,
+    },
+
+    // Remainder of integer division; has the same sign as lhs
+    Remainder {
+        lhs: Box<Exp>,
+        rhs: Box<Exp>,
+    },
+
+    // Greatest common divisor of two Ints; never negative, and (gcd 0 0) is 0
+    Gcd {
+        lhs: Box<Exp>,
+        rhs: Box<Exp>,
+    },
+
+    // Least common multiple of two Ints; never negative, and 0 if either is 0
+    Lcm {
+        lhs: Box<Exp>,
+        rhs: Box<Exp>,
+    },
+
+    // Wraps a single-argument closure so repeated calls with the same argument are cached
+    Memoize(Box<Exp>),
+
+    // Wraps a function so each call logs its arguments and result under name. The name is the
+    // traced identifier, the letrec binding being defined, or "lambda"
+    Trace {
+        name: String,
+        func: Box<Exp>,
+    },
+
+    // Integer division; resolves to the quotient and remainder as multiple values
+    DivMod {
+        lhs: Box<Exp>,
+        rhs: Box<Exp>,
+    },
+
+    // Renders a number right-aligned in a field of width characters, padded with spaces.
+    // Numbers wider than the field aren't truncated
+    FormatWidth {
+        value: Box<Exp>,
+        width: Box<Exp>,
+    },
+
+    // Checks that exp evaluates to a value of type ty; resolves to that value
+    Ann {
+        exp: Box<Exp>,
+        ty: Type,
+    },
+
+    // Builds data from a template; lists in the template become List and Unquote subforms are
+    // evaluated
+    Quasiquote(Box<Exp>),
+
+    // Evaluates exp inside a quasiquote template
+    Unquote(Box<Exp>),
+
+    // Records where exp came from in the source; only produced when the parser tracks spans
+    Spanned {
+        span: Span,
+        exp: Box<Exp>,
+    },
+}
+
+// Runtime types that can be named in a type annotation. Fn covers closures and primitives
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+    Char,
+    Fn,
+    Box,
+    Ref,
+    MutRef,
+    Pair,
+    Nil,
+    Unit,
+    List,
+    Bytes,
+    Vector,
+}
+
+impl Type {
+    fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "Int" => Some(Type::Int),
+            "Float" => Some(Type::Float),
+            "Bool" => Some(Type::Bool),
+            "Str" => Some(Type::Str),
+            "Char" => Some(Type::Char),
+            "Fn" => Some(Type::Fn),
+            "Box" => Some(Type::Box),
+            "Ref" => Some(Type::Ref),
+            "MutRef" => Some(Type::MutRef),
+            "Pair" => Some(Type::Pair),
+            "Nil" => Some(Type::Nil),
+            "Unit" => Some(Type::Unit),
+            "List" => Some(Type::List),
+            "Bytes" => Some(Type::Bytes),
+            "Vector" => Some(Type::Vector),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
 impl fmt::Debug for Exp {
@@ -120,19 +464,62 @@ impl fmt::Debug for Exp {
         match self {
             Exp::Int(n) => write!(f, "Int({})", n),
             Exp::Float(n) => write!(f, "Float({})", n),
+            Exp::Str(s) => write!(f, "Str({:?})", s),
+            Exp::Char(c) => write!(f, "Char({:?})", c),
+            Exp::CharToInt(e) => write!(f, "CharToInt({:?})", e),
+            Exp::IntToChar(e) => write!(f, "IntToChar({:?})", e),
             Exp::Id(s) => write!(f, "Id({})", s),
             Exp::Add { lhs, rhs } => write!(f, "Add({:?}, {:?})", lhs, rhs),
             Exp::Sub { lhs, rhs } => write!(f, "Sub({:?}, {:?})", lhs, rhs),
             Exp::Mult { lhs, rhs } => write!(f, "Mult({:?}, {:?})", lhs, rhs),
             Exp::Div { lhs, rhs } => write!(f, "Div({:?}, {:?})", lhs, rhs),
-            Exp::Lambda { arg: symbol, body } => write!(f, "Lambda({}, {:?})", symbol, body),
-            Exp::App { func, arg } => write!(f, "App({:?}, {:?})", func, arg),
+            Exp::Lambda { params, rest, body } => match (&params[..], rest) {
+                ([param], None) => write!(f, "Lambda({}, {:?})", param, body),
+                (_, None) => write!(f, "Lambda([{}], {:?})", params.join(", "), body),
+                (_, Some(rest)) => {
+                    write!(f, "Lambda([{}], {}, {:?})", params.join(", "), rest, body)
+                }
+            },
+            Exp::App { func, args } => {
+                write!(f, "App({:?}", func)?;
+                for arg in args {
+                    write!(f, ", {:?}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Exp::Arity(e) => write!(f, "Arity({:?})", e),
             Exp::If { cond, lhs, rhs } => write!(f, "If({:?}, {:?}, {:?})", cond, lhs, rhs),
             Exp::Eq { lhs, rhs } => write!(f, "Eq({:?}, {:?})", lhs, rhs),
             Exp::Gt { lhs, rhs } => write!(f, "Gt({:?}, {:?})", lhs, rhs),
             Exp::Ge { lhs, rhs } => write!(f, "Ge({:?}, {:?})", lhs, rhs),
             Exp::Lt { lhs, rhs } => write!(f, "Lt({:?}, {:?})", lhs, rhs),
             Exp::Le { lhs, rhs } => write!(f, "Le({:?}, {:?})", lhs, rhs),
+            Exp::Nth { list, index } => write!(f, "Nth({:?}, {:?})", list, index),
+            Exp::StringRef { string, index } => write!(f, "StringRef({:?}, {:?})", string, index),
+            Exp::StringToList(e) => write!(f, "StringToList({:?})", e),
+            Exp::StringSplit { string, sep } => write!(f, "StringSplit({:?}, {:?})", string, sep),
+            Exp::Range { start, end, step } => {
+                write!(f, "Range({:?}, {:?}, {:?})", start, end, step)
+            }
+            Exp::RoundTo { value, places } => write!(f, "RoundTo({:?}, {:?})", value, places),
+            Exp::Contains { list, value } => write!(f, "Contains({:?}, {:?})", list, value),
+            Exp::NumberToString { value, precision } => match precision {
+                Some(precision) => write!(f, "NumberToString({:?}, {:?})", value, precision),
+                None => write!(f, "NumberToString({:?})", value),
+            },
+            Exp::StringJoin { list, sep } => write!(f, "StringJoin({:?}, {:?})", list, sep),
+            Exp::ReadBytes(e) => write!(f, "ReadBytes({:?})", e),
+            Exp::BytesLength(e) => write!(f, "BytesLength({:?})", e),
+            Exp::BytesRef { bytes, index } => write!(f, "BytesRef({:?}, {:?})", bytes, index),
+            Exp::MakeVector { len, init } => write!(f, "MakeVector({:?}, {:?})", len, init),
+            Exp::VectorRef { vector, index } => write!(f, "VectorRef({:?}, {:?})", vector, index),
+            Exp::VectorSet {
+                vector,
+                index,
+                value,
+            } => write!(f, "VectorSet({:?}, {:?}, {:?})", vector, index, value),
+            Exp::Ann { exp, ty } => write!(f, "Ann({:?}, {})", exp, ty),
+            Exp::Repeat { count, body } => write!(f, "Repeat({:?}, {:?})", count, body),
             Exp::Begin(exprs) => {
                 write!(f, "Begin(")?;
                 let mut iter = exprs.iter();
@@ -149,10 +536,795 @@ impl fmt::Debug for Exp {
             Exp::MutRef(r) => write!(f, "MutRef({:?})", r),
             Exp::Box(b) => write!(f, "Box({:?})", b),
             Exp::Unbox(u) => write!(f, "Unbox({:?})", u),
+            Exp::Gc => write!(f, "Gc"),
+            Exp::ReadLine => write!(f, "ReadLine"),
+            Exp::Flush => write!(f, "Flush"),
+            Exp::Exit(e) => write!(f, "Exit({:?})", e),
+            Exp::Not(e) => write!(f, "Not({:?})", e),
+            Exp::Sum(e) => write!(f, "Sum({:?})", e),
+            Exp::Product(e) => write!(f, "Product({:?})", e),
+            Exp::Read(e) => write!(f, "Read({:?})", e),
+            Exp::Eval(e) => write!(f, "Eval({:?})", e),
+            Exp::FunctionToString(e) => write!(f, "FunctionToString({:?})", e),
+            Exp::AssertEq { actual, expected } => {
+                write!(f, "AssertEq({:?}, {:?})", actual, expected)
+            }
+            Exp::AssertError(e) => write!(f, "AssertError({:?})", e),
+            Exp::WriteString(e) => write!(f, "WriteString({:?})", e),
             Exp::Deref(d) => write!(f, "Deref({:?})", d),
             Exp::Set { lhs, rhs } => write!(f, "Set({:?}, {:?})", lhs, rhs),
+            Exp::GetAndSet { lhs, rhs } => write!(f, "GetAndSet({:?}, {:?})", lhs, rhs),
+            Exp::Apply { func, args_list } => write!(f, "Apply({:?}, {:?})", func, args_list),
             Exp::Display(d) => write!(f, "Display({:?})", d),
             Exp::Debug(d) => write!(f, "Debug({:?})", d),
+            Exp::DisplayHex(d) => write!(f, "DisplayHex({:?})", d),
+            Exp::Time(e) => write!(f, "Time({:?})", e),
+            Exp::Nil => write!(f, "Nil"),
+            Exp::Cons { car, cdr } => write!(f, "Cons({:?}, {:?})", car, cdr),
+            Exp::Car(p) => write!(f, "Car({:?})", p),
+            Exp::Cdr(p) => write!(f, "Cdr({:?})", p),
+            Exp::IsPair(e) => write!(f, "IsPair({:?})", e),
+            Exp::IsNull(e) => write!(f, "IsNull({:?})", e),
+            Exp::List(exprs) => {
+                write!(f, "List(")?;
+                let mut iter = exprs.iter();
+                if let Some(first) = iter.next() {
+                    write!(f, "{:?}", first)?;
+                    for expr in iter {
+                        write!(f, ", {:?}", expr)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Exp::Values(exprs) => {
+                write!(f, "Values(")?;
+                let mut iter = exprs.iter();
+                if let Some(first) = iter.next() {
+                    write!(f, "{:?}", first)?;
+                    for expr in iter {
+                        write!(f, ", {:?}", expr)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Exp::And(exprs) | Exp::Or(exprs) => {
+                write!(
+                    f,
+                    "{}(",
+                    if matches!(self, Exp::And(_)) {
+                        "And"
+                    } else {
+                        "Or"
+                    }
+                )?;
+                let mut iter = exprs.iter();
+                if let Some(first) = iter.next() {
+                    write!(f, "{:?}", first)?;
+                    for expr in iter {
+                        write!(f, ", {:?}", expr)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Exp::Define { name, value } => write!(f, "Define({}, {:?})", name, value),
+            Exp::LetValues { names, value, body } => {
+                write!(
+                    f,
+                    "LetValues([{}], {:?}, {:?})",
+                    names.join(", "),
+                    value,
+                    body
+                )
+            }
+            Exp::LetRecMany { bindings, body } => {
+                write!(f, "LetRecMany([")?;
+                for (i, (name, exp)) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "({}, {:?})", name, exp)?;
+                }
+                write!(f, "], {:?})", body)
+            }
+            Exp::DivMod { lhs, rhs } => write!(f, "DivMod({:?}, {:?})", lhs, rhs),
+            Exp::FormatWidth { value, width } => {
+                write!(f, "FormatWidth({:?}, {:?})", value, width)
+            }
+            Exp::Memoize(e) => write!(f, "Memoize({:?})", e),
+            Exp::Trace { name, func } => write!(f, "Trace({}, {:?})", name, func),
+            Exp::Quotient { lhs, rhs } => write!(f, "Quotient({:?}, {:?})", lhs, rhs),
+            Exp::Remainder { lhs, rhs } => write!(f, "Remainder({:?}, {:?})", lhs, rhs),
+            Exp::Gcd { lhs, rhs } => write!(f, "Gcd({:?}, {:?})", lhs, rhs),
+            Exp::Lcm { lhs, rhs } => write!(f, "Lcm({:?}, {:?})", lhs, rhs),
+            Exp::Quasiquote(e) => write!(f, "Quasiquote({:?})", e),
+            Exp::Unquote(e) => write!(f, "Unquote({:?})", e),
+            Exp::Spanned { span, exp } => {
+                write!(f, "Spanned({}..{}, {:?})", span.start, span.end, exp)
+            }
+        }
+    }
+}
+
+// Writes each expression preceded by a space
+fn write_all(f: &mut fmt::Formatter, exprs: &[Exp]) -> fmt::Result {
+    for expr in exprs {
+        write!(f, " {}", expr)?;
+    }
+    Ok(())
+}
+
+// Prints the expression as source text that parses back to an equal Exp
+impl fmt::Display for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exp::Int(n) => write!(f, "{}", n),
+            // Debug is shortest-round-trip and always keeps a '.' or exponent so it stays a Float
+            // Debug prints NaN, which the reader doesn't recognize as a float
+            Exp::Float(n) if n.is_nan() => write!(f, "nan"),
+            Exp::Float(n) => write!(f, "{:?}", n),
+            Exp::Str(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\0' => write!(f, "\\0")?,
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Exp::Id(s) => write!(f, "{}", s),
+            Exp::Char(' ') => write!(f, "#\\space"),
+            Exp::Char('\n') => write!(f, "#\\newline"),
+            Exp::Char(c) => write!(f, "#\\{}", c),
+            Exp::CharToInt(e) => write!(f, "(char->int {})", e),
+            Exp::IntToChar(e) => write!(f, "(int->char {})", e),
+            Exp::Bool(b) => write!(f, "{}", b),
+            Exp::Nil => write!(f, "nil"),
+            Exp::Gc => write!(f, "(gc)"),
+            Exp::ReadLine => write!(f, "(read-line)"),
+            Exp::Flush => write!(f, "(flush)"),
+            Exp::Exit(e) => write!(f, "(exit {})", e),
+            Exp::Not(e) => write!(f, "(not {})", e),
+            Exp::Sum(e) => write!(f, "(sum {})", e),
+            Exp::Product(e) => write!(f, "(product {})", e),
+            Exp::Read(e) => write!(f, "(read {})", e),
+            Exp::Eval(e) => write!(f, "(eval {})", e),
+            Exp::FunctionToString(e) => write!(f, "(function->string {})", e),
+            Exp::AssertEq { actual, expected } => {
+                write!(f, "(assert-eq {} {})", actual, expected)
+            }
+            Exp::AssertError(e) => write!(f, "(assert-error {})", e),
+            Exp::WriteString(e) => write!(f, "(write-string {})", e),
+            Exp::Add { lhs, rhs } => write!(f, "(+ {} {})", lhs, rhs),
+            Exp::Sub { lhs, rhs } => write!(f, "(- {} {})", lhs, rhs),
+            Exp::Mult { lhs, rhs } => write!(f, "(* {} {})", lhs, rhs),
+            Exp::Div { lhs, rhs } => write!(f, "(/ {} {})", lhs, rhs),
+            Exp::Eq { lhs, rhs } => write!(f, "(= {} {})", lhs, rhs),
+            Exp::Gt { lhs, rhs } => write!(f, "(> {} {})", lhs, rhs),
+            Exp::Ge { lhs, rhs } => write!(f, "(>= {} {})", lhs, rhs),
+            Exp::Lt { lhs, rhs } => write!(f, "(< {} {})", lhs, rhs),
+            Exp::Le { lhs, rhs } => write!(f, "(<= {} {})", lhs, rhs),
+            Exp::DivMod { lhs, rhs } => write!(f, "(divmod {} {})", lhs, rhs),
+            Exp::FormatWidth { value, width } => write!(f, "(format-width {} {})", value, width),
+            Exp::Quotient { lhs, rhs } => write!(f, "(quotient {} {})", lhs, rhs),
+            Exp::Remainder { lhs, rhs } => write!(f, "(remainder {} {})", lhs, rhs),
+            Exp::Gcd { lhs, rhs } => write!(f, "(gcd {} {})", lhs, rhs),
+            Exp::Lcm { lhs, rhs } => write!(f, "(lcm {} {})", lhs, rhs),
+            Exp::Set { lhs, rhs } => write!(f, "(set {} {})", lhs, rhs),
+            Exp::GetAndSet { lhs, rhs } => write!(f, "(get-and-set {} {})", lhs, rhs),
+            Exp::Apply { func, args_list } => write!(f, "(apply {} {})", func, args_list),
+            Exp::Cons { car, cdr } => write!(f, "(cons {} {})", car, cdr),
+            Exp::Lambda { params, rest, body } => match (&params[..], rest) {
+                ([param], None) => write!(f, "(lambda {} {})", param, body),
+                (_, None) => write!(f, "(lambda ({}) {})", params.join(" "), body),
+                ([], Some(rest)) => write!(f, "(lambda (. {}) {})", rest, body),
+                (_, Some(rest)) => {
+                    write!(f, "(lambda ({} . {}) {})", params.join(" "), rest, body)
+                }
+            },
+            Exp::App { func, args } => {
+                write!(f, "({}", func)?;
+                write_all(f, args)?;
+                write!(f, ")")
+            }
+            Exp::Arity(e) => write!(f, "(arity {})", e),
+            Exp::If { cond, lhs, rhs } => write!(f, "(if {} {} {})", cond, lhs, rhs),
+            Exp::Nth { list, index } => write!(f, "(nth {} {})", list, index),
+            Exp::StringRef { string, index } => write!(f, "(string-ref {} {})", string, index),
+            Exp::StringToList(e) => write!(f, "(string->list {})", e),
+            Exp::StringSplit { string, sep } => write!(f, "(string-split {} {})", string, sep),
+            Exp::Range { start, end, step } => write!(f, "(range {} {} {})", start, end, step),
+            Exp::RoundTo { value, places } => write!(f, "(round-to {} {})", value, places),
+            Exp::Contains { list, value } => write!(f, "(contains? {} {})", list, value),
+            Exp::NumberToString { value, precision } => match precision {
+                Some(precision) => write!(f, "(number->string {} {})", value, precision),
+                None => write!(f, "(number->string {})", value),
+            },
+            Exp::StringJoin { list, sep } => write!(f, "(string-join {} {})", list, sep),
+            Exp::ReadBytes(e) => write!(f, "(read-bytes {})", e),
+            Exp::BytesLength(e) => write!(f, "(bytes-length {})", e),
+            Exp::BytesRef { bytes, index } => write!(f, "(bytes-ref {} {})", bytes, index),
+            Exp::MakeVector { len, init } => write!(f, "(make-vector {} {})", len, init),
+            Exp::VectorRef { vector, index } => write!(f, "(vector-ref {} {})", vector, index),
+            Exp::VectorSet {
+                vector,
+                index,
+                value,
+            } => write!(f, "(vector-set! {} {} {})", vector, index, value),
+            Exp::Ann { exp, ty } => write!(f, "(: {} {})", exp, ty),
+            Exp::Repeat { count, body } => write!(f, "(repeat {} {})", count, body),
+            Exp::Begin(exprs) => {
+                write!(f, "(begin")?;
+                write_all(f, exprs)?;
+                write!(f, ")")
+            }
+            Exp::List(exprs) => {
+                write!(f, "(list")?;
+                write_all(f, exprs)?;
+                write!(f, ")")
+            }
+            Exp::Values(exprs) => {
+                write!(f, "(values")?;
+                write_all(f, exprs)?;
+                write!(f, ")")
+            }
+            Exp::And(exprs) => {
+                write!(f, "(and")?;
+                write_all(f, exprs)?;
+                write!(f, ")")
+            }
+            Exp::Or(exprs) => {
+                write!(f, "(or")?;
+                write_all(f, exprs)?;
+                write!(f, ")")
+            }
+            Exp::Define { name, value } => write!(f, "(define {} {})", name, value),
+            Exp::LetValues { names, value, body } => {
+                write!(f, "(let-values (({}) {}) {})", names.join(" "), value, body)
+            }
+            Exp::LetRecMany { bindings, body } => {
+                write!(f, "(letrec (")?;
+                for (i, (name, exp)) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "({} {})", name, exp)?;
+                }
+                write!(f, ") {})", body)
+            }
+            Exp::Ref(e) => write!(f, "(ref {})", e),
+            Exp::MutRef(e) => write!(f, "(mut-ref {})", e),
+            Exp::Box(e) => write!(f, "(box {})", e),
+            Exp::Unbox(e) => write!(f, "(unbox {})", e),
+            Exp::Deref(e) => write!(f, "(deref {})", e),
+            Exp::Display(e) => write!(f, "(display {})", e),
+            Exp::Debug(e) => write!(f, "(debug {})", e),
+            Exp::DisplayHex(e) => write!(f, "(display-hex {})", e),
+            Exp::Time(e) => write!(f, "(time {})", e),
+            Exp::Car(e) => write!(f, "(car {})", e),
+            Exp::Cdr(e) => write!(f, "(cdr {})", e),
+            Exp::IsPair(e) => write!(f, "(pair? {})", e),
+            Exp::IsNull(e) => write!(f, "(null? {})", e),
+            Exp::Memoize(e) => write!(f, "(memoize {})", e),
+            Exp::Trace { func, .. } => write!(f, "(trace {})", func),
+            Exp::Quasiquote(e) => {
+                write!(f, "(quasiquote ")?;
+                write_template(f, e)?;
+                write!(f, ")")
+            }
+            Exp::Unquote(e) => write!(f, "(unquote {})", e),
+            Exp::Spanned { exp, .. } => write!(f, "{}", exp),
+        }
+    }
+}
+
+// Writes a quasiquote template, where lists are written without the list keyword
+fn write_template(f: &mut fmt::Formatter, exp: &Exp) -> fmt::Result {
+    match exp {
+        Exp::List(exprs) => {
+            write!(f, "(")?;
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write_template(f, expr)?;
+            }
+            write!(f, ")")
+        }
+        exp => write!(f, "{}", exp),
+    }
+}
+
+impl Exp {
+    // Immediate subexpressions, in evaluation order
+    pub fn children(&self) -> Vec<&Exp> {
+        use Exp::*;
+        match self {
+            Int(_) | Float(_) | Str(_) | Char(_) | Id(_) | Bool(_) | Nil | Gc | ReadLine
+            | Flush => vec![],
+            Add { lhs, rhs }
+            | Sub { lhs, rhs }
+            | Mult { lhs, rhs }
+            | Div { lhs, rhs }
+            | Eq { lhs, rhs }
+            | Gt { lhs, rhs }
+            | Ge { lhs, rhs }
+            | Lt { lhs, rhs }
+            | Le { lhs, rhs }
+            | Set { lhs, rhs }
+            | GetAndSet { lhs, rhs }
+            | DivMod { lhs, rhs }
+            | FormatWidth {
+                value: lhs,
+                width: rhs,
+            }
+            | Quotient { lhs, rhs }
+            | Remainder { lhs, rhs }
+            | Gcd { lhs, rhs }
+            | Lcm { lhs, rhs } => vec![lhs, rhs],
+            Cons { car, cdr } => vec![car, cdr],
+            Nth { list, index } => vec![list, index],
+            StringRef { string, index } => vec![string, index],
+            StringSplit { string, sep } => vec![string, sep],
+            Range { start, end, step } => vec![start, end, step],
+            RoundTo { value, places } => vec![value, places],
+            Contains { list, value } => vec![list, value],
+            NumberToString { value, precision } => std::iter::once(&**value)
+                .chain(precision.as_deref())
+                .collect(),
+            StringJoin { list, sep } => vec![list, sep],
+            AssertEq { actual, expected } => vec![actual, expected],
+            BytesRef { bytes, index } => vec![bytes, index],
+            MakeVector { len, init } => vec![len, init],
+            VectorRef { vector, index } => vec![vector, index],
+            VectorSet {
+                vector,
+                index,
+                value,
+            } => vec![vector, index, value],
+            Repeat { count, body } => vec![count, body],
+            Apply { func, args_list } => vec![func, args_list],
+            Lambda { body, .. } | Spanned { exp: body, .. } | Trace { func: body, .. } => {
+                vec![body]
+            }
+            App { func, args } => std::iter::once(&**func).chain(args).collect(),
+            If { cond, lhs, rhs } => vec![cond, lhs, rhs],
+            Begin(exprs) | List(exprs) | Values(exprs) | And(exprs) | Or(exprs) => {
+                exprs.iter().collect()
+            }
+            Ref(e)
+            | MutRef(e)
+            | Box(e)
+            | Unbox(e)
+            | Deref(e)
+            | Display(e)
+            | Debug(e)
+            | DisplayHex(e)
+            | Car(e)
+            | Cdr(e)
+            | IsPair(e)
+            | IsNull(e)
+            | Memoize(e)
+            | Time(e)
+            | CharToInt(e)
+            | IntToChar(e)
+            | Arity(e)
+            | Ann { exp: e, .. }
+            | StringToList(e)
+            | ReadBytes(e)
+            | BytesLength(e)
+            | WriteString(e)
+            | Quasiquote(e)
+            | Unquote(e)
+            | Exit(e)
+            | Not(e)
+            | Sum(e)
+            | Product(e)
+            | Read(e)
+            | Eval(e)
+            | FunctionToString(e)
+            | AssertError(e) => {
+                vec![e]
+            }
+            Define { value, .. } => vec![value],
+            LetValues { value, body, .. } => vec![value, body],
+            LetRecMany { bindings, body } => bindings
+                .iter()
+                .map(|(_, exp)| exp)
+                .chain(std::iter::once(&**body))
+                .collect(),
+        }
+    }
+
+    // Name of this expression's variant, such as "Add" for (+ 1 2)
+    pub fn kind(&self) -> &'static str {
+        use Exp::*;
+        match self {
+            Int(..) => "Int",
+            Float(..) => "Float",
+            Str(..) => "Str",
+            Id(..) => "Id",
+            Add { .. } => "Add",
+            Sub { .. } => "Sub",
+            Mult { .. } => "Mult",
+            Div { .. } => "Div",
+            Lambda { .. } => "Lambda",
+            App { .. } => "App",
+            Arity(..) => "Arity",
+            If { .. } => "If",
+            Eq { .. } => "Eq",
+            Gt { .. } => "Gt",
+            Ge { .. } => "Ge",
+            Lt { .. } => "Lt",
+            Le { .. } => "Le",
+            Begin(..) => "Begin",
+            Not(..) => "Not",
+            Sum(..) => "Sum",
+            Product(..) => "Product",
+            And(..) => "And",
+            Or(..) => "Or",
+            Nth { .. } => "Nth",
+            StringRef { .. } => "StringRef",
+            StringToList(..) => "StringToList",
+            StringSplit { .. } => "StringSplit",
+            Range { .. } => "Range",
+            RoundTo { .. } => "RoundTo",
+            Contains { .. } => "Contains",
+            NumberToString { .. } => "NumberToString",
+            StringJoin { .. } => "StringJoin",
+            ReadBytes(..) => "ReadBytes",
+            BytesLength(..) => "BytesLength",
+            BytesRef { .. } => "BytesRef",
+            MakeVector { .. } => "MakeVector",
+            VectorRef { .. } => "VectorRef",
+            VectorSet { .. } => "VectorSet",
+            Repeat { .. } => "Repeat",
+            Bool(..) => "Bool",
+            Ref(..) => "Ref",
+            MutRef(..) => "MutRef",
+            Box(..) => "Box",
+            Unbox(..) => "Unbox",
+            Gc => "Gc",
+            ReadLine => "ReadLine",
+            Flush => "Flush",
+            Exit(..) => "Exit",
+            Read(..) => "Read",
+            Eval(..) => "Eval",
+            FunctionToString(..) => "FunctionToString",
+            AssertEq { .. } => "AssertEq",
+            AssertError(..) => "AssertError",
+            WriteString(..) => "WriteString",
+            Deref(..) => "Deref",
+            Apply { .. } => "Apply",
+            Set { .. } => "Set",
+            GetAndSet { .. } => "GetAndSet",
+            Display(..) => "Display",
+            Debug(..) => "Debug",
+            DisplayHex(..) => "DisplayHex",
+            Time(..) => "Time",
+            Nil => "Nil",
+            Cons { .. } => "Cons",
+            Car(..) => "Car",
+            Cdr(..) => "Cdr",
+            IsPair(..) => "IsPair",
+            IsNull(..) => "IsNull",
+            Char(..) => "Char",
+            CharToInt(..) => "CharToInt",
+            IntToChar(..) => "IntToChar",
+            List(..) => "List",
+            Values(..) => "Values",
+            Define { .. } => "Define",
+            LetValues { .. } => "LetValues",
+            LetRecMany { .. } => "LetRecMany",
+            Quotient { .. } => "Quotient",
+            Remainder { .. } => "Remainder",
+            Gcd { .. } => "Gcd",
+            Lcm { .. } => "Lcm",
+            Memoize(..) => "Memoize",
+            Trace { .. } => "Trace",
+            DivMod { .. } => "DivMod",
+            FormatWidth { .. } => "FormatWidth",
+            Ann { .. } => "Ann",
+            Quasiquote(..) => "Quasiquote",
+            Unquote(..) => "Unquote",
+            Spanned { .. } => "Spanned",
+        }
+    }
+
+    // The expression with every span removed, so that the same code parsed from different
+    // places compares equal
+    pub fn without_spans(self) -> Exp {
+        match self {
+            Exp::Spanned { exp, .. } => exp.without_spans(),
+            exp => exp.map_children(Exp::without_spans),
+        }
+    }
+
+    // Name bound by this expression if it's a define
+    pub fn defined_name(&self) -> Option<&String> {
+        match self {
+            Exp::Define { name, .. } => Some(name),
+            Exp::Spanned { exp, .. } => exp.defined_name(),
+            _ => None,
+        }
+    }
+
+    // Whether this expression contains an eval, whose code can refer to any name in scope
+    pub fn uses_eval(&self) -> bool {
+        matches!(self, Exp::Eval(_)) || self.children().into_iter().any(Exp::uses_eval)
+    }
+
+    // Identifiers referenced in this expression that aren't bound inside it
+    pub fn free_vars(&self) -> HashSet<String> {
+        use Exp::*;
+        let without = |mut vars: HashSet<String>, names: &mut dyn Iterator<Item = &String>| {
+            for name in names {
+                vars.remove(name);
+            }
+            vars
+        };
+        match self {
+            Id(s) => HashSet::from([s.clone()]),
+            Lambda { params, rest, body } => {
+                without(body.free_vars(), &mut params.iter().chain(rest))
+            }
+            LetValues { names, value, body } => {
+                let mut vars = without(body.free_vars(), &mut names.iter());
+                vars.extend(value.free_vars());
+                vars
+            }
+            // A define only binds its name for the forms after it, so uses before it
+            // (including in its own value) refer to an outer binding
+            Begin(es) => {
+                let mut defined = HashSet::new();
+                let mut vars = HashSet::new();
+                for e in es {
+                    vars.extend(without(e.free_vars(), &mut defined.iter()));
+                    defined.extend(e.defined_name().cloned());
+                }
+                vars
+            }
+            // Every binding is in scope in every lambda as well as the body
+            LetRecMany { bindings, .. } => without(
+                self.children()
+                    .into_iter()
+                    .flat_map(Exp::free_vars)
+                    .collect(),
+                &mut bindings.iter().map(|(name, _)| name),
+            ),
+            _ => self
+                .children()
+                .into_iter()
+                .flat_map(Exp::free_vars)
+                .collect(),
+        }
+    }
+
+    // Rebuilds this expression with f applied to each immediate subexpression
+    pub fn map_children<F>(self, mut f: F) -> Exp
+    where
+        F: FnMut(Exp) -> Exp,
+    {
+        use std::boxed::Box as B;
+        use Exp::*;
+        let mut g = |e: B<Exp>| B::new(f(*e));
+        match self {
+            Int(_) | Float(_) | Str(_) | Char(_) | Id(_) | Bool(_) | Nil | Gc | ReadLine
+            | Flush => self,
+            Add { lhs, rhs } => Add {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Sub { lhs, rhs } => Sub {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Mult { lhs, rhs } => Mult {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Div { lhs, rhs } => Div {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Eq { lhs, rhs } => Eq {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Gt { lhs, rhs } => Gt {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Ge { lhs, rhs } => Ge {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Lt { lhs, rhs } => Lt {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Le { lhs, rhs } => Le {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Set { lhs, rhs } => Set {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            GetAndSet { lhs, rhs } => GetAndSet {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Apply { func, args_list } => Apply {
+                func: g(func),
+                args_list: g(args_list),
+            },
+            DivMod { lhs, rhs } => DivMod {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            FormatWidth { value, width } => FormatWidth {
+                value: g(value),
+                width: g(width),
+            },
+            Quotient { lhs, rhs } => Quotient {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Remainder { lhs, rhs } => Remainder {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Gcd { lhs, rhs } => Gcd {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Lcm { lhs, rhs } => Lcm {
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Cons { car, cdr } => Cons {
+                car: g(car),
+                cdr: g(cdr),
+            },
+            Lambda { params, rest, body } => Lambda {
+                params,
+                rest,
+                body: g(body),
+            },
+            App { func, args } => App {
+                func: g(func),
+                args: args.into_iter().map(f).collect(),
+            },
+            If { cond, lhs, rhs } => If {
+                cond: g(cond),
+                lhs: g(lhs),
+                rhs: g(rhs),
+            },
+            Ann { exp, ty } => Ann { exp: g(exp), ty },
+            Nth { list, index } => Nth {
+                list: g(list),
+                index: g(index),
+            },
+            StringRef { string, index } => StringRef {
+                string: g(string),
+                index: g(index),
+            },
+            StringToList(e) => StringToList(g(e)),
+            NumberToString { value, precision } => NumberToString {
+                value: g(value),
+                precision: precision.map(g),
+            },
+            Contains { list, value } => Contains {
+                list: g(list),
+                value: g(value),
+            },
+            RoundTo { value, places } => RoundTo {
+                value: g(value),
+                places: g(places),
+            },
+            Range { start, end, step } => Range {
+                start: g(start),
+                end: g(end),
+                step: g(step),
+            },
+            StringSplit { string, sep } => StringSplit {
+                string: g(string),
+                sep: g(sep),
+            },
+            StringJoin { list, sep } => StringJoin {
+                list: g(list),
+                sep: g(sep),
+            },
+            ReadBytes(e) => ReadBytes(g(e)),
+            WriteString(e) => WriteString(g(e)),
+            BytesLength(e) => BytesLength(g(e)),
+            BytesRef { bytes, index } => BytesRef {
+                bytes: g(bytes),
+                index: g(index),
+            },
+            MakeVector { len, init } => MakeVector {
+                len: g(len),
+                init: g(init),
+            },
+            VectorRef { vector, index } => VectorRef {
+                vector: g(vector),
+                index: g(index),
+            },
+            VectorSet {
+                vector,
+                index,
+                value,
+            } => VectorSet {
+                vector: g(vector),
+                index: g(index),
+                value: g(value),
+            },
+            Repeat { count, body } => Repeat {
+                count: g(count),
+                body: g(body),
+            },
+            Begin(exprs) => Begin(exprs.into_iter().map(f).collect()),
+            And(exprs) => And(exprs.into_iter().map(f).collect()),
+            Or(exprs) => Or(exprs.into_iter().map(f).collect()),
+            List(exprs) => List(exprs.into_iter().map(f).collect()),
+            Values(exprs) => Values(exprs.into_iter().map(f).collect()),
+            Ref(e) => Ref(g(e)),
+            MutRef(e) => MutRef(g(e)),
+            Box(e) => Box(g(e)),
+            Unbox(e) => Unbox(g(e)),
+            Deref(e) => Deref(g(e)),
+            Display(e) => Display(g(e)),
+            Debug(e) => Debug(g(e)),
+            DisplayHex(e) => DisplayHex(g(e)),
+            Time(e) => Time(g(e)),
+            Car(e) => Car(g(e)),
+            Cdr(e) => Cdr(g(e)),
+            IsPair(e) => IsPair(g(e)),
+            IsNull(e) => IsNull(g(e)),
+            CharToInt(e) => CharToInt(g(e)),
+            IntToChar(e) => IntToChar(g(e)),
+            Arity(e) => Arity(g(e)),
+            Memoize(e) => Memoize(g(e)),
+            Quasiquote(e) => Quasiquote(g(e)),
+            Exit(e) => Exit(g(e)),
+            Not(e) => Not(g(e)),
+            Sum(e) => Sum(g(e)),
+            Product(e) => Product(g(e)),
+            Read(e) => Read(g(e)),
+            Eval(e) => Eval(g(e)),
+            FunctionToString(e) => FunctionToString(g(e)),
+            AssertEq { actual, expected } => AssertEq {
+                actual: g(actual),
+                expected: g(expected),
+            },
+            AssertError(e) => AssertError(g(e)),
+            Unquote(e) => Unquote(g(e)),
+            Trace { name, func } => Trace {
+                name,
+                func: g(func),
+            },
+            Spanned { span, exp } => Spanned { span, exp: g(exp) },
+            Define { name, value } => Define {
+                name,
+                value: g(value),
+            },
+            LetValues { names, value, body } => LetValues {
+                names,
+                value: g(value),
+                body: g(body),
+            },
+            LetRecMany { bindings, body } => LetRecMany {
+                body: g(body),
+                bindings: bindings
+                    .into_iter()
+                    .map(|(name, exp)| (name, f(exp)))
+                    .collect(),
+            },
         }
     }
 }
@@ -160,7 +1332,7 @@ impl fmt::Debug for Exp {
 impl TryFrom<&str> for Exp {
     type Error = ParseError;
     fn try_from(item: &str) -> Result<Self, Self::Error> {
-        parse(sexp::parse(item)?)
+        Parser::default().parse(item)
     }
 }
 
@@ -174,107 +1346,752 @@ pub enum ParseError {
     SexpError(#[from] Box<sexp::Error>),
     #[error("Let assignment expressions must have the structure (<symbol> <body>)")]
     MalformedAssignment,
+    #[error("let-values assignments must have the structure ((<symbol>*) <body>)")]
+    MalformedValuesAssignment,
+    #[error("define-syntax must have the structure (define-syntax (<name> <symbol>*) <template>)")]
+    MalformedMacro,
+    #[error("define-syntax can only appear directly inside a begin")]
+    MisplacedMacroDefinition,
+    #[error("define can only appear at the top level or directly inside a begin")]
+    MisplacedDefinition,
+    #[error("Macro '{0}' expects {1} arguments")]
+    MacroArity(String, usize),
+    #[error("letrec bindings must each be a (name (lambda ...)) pair")]
+    MalformedLetRec,
+    #[error("Lambda parameters must be symbols")]
+    MalformedParameters,
+    #[error("Unknown character literal '{0}'")]
+    UnknownCharacter(String),
+    #[error("Unknown type '{0}'")]
+    UnknownType(String),
+    #[error("'{0}' is a constant and can't be bound")]
+    ReservedName(String),
+    #[error("if chains must have the structure (if <cond> <exp> [elif <cond> <exp>]* else <exp>)")]
+    MalformedIf,
+    #[error("unquote can only appear inside a quasiquote")]
+    MisplacedUnquote,
+    #[error("Symbol '{0}' in a quasiquote must be unquoted")]
+    QuotedSymbol(String),
+}
+
+// Words that introduce special forms; matched regardless of case when the parser is lenient
+static KEYWORDS: phf::Set<&'static str> = phf_set! {
+    ":", "and", "apply", "arity", "assert-eq", "assert-error", "begin", "box", "bytes-length",
+    "bytes-ref", "car", "cdr", "char->int", "cons", "contains?", "debug", "define", "define-syntax",
+    "deref", "display", "display-hex", "divmod", "eval", "exit", "flush", "format-width",
+    "function->string", "gc", "gcd", "get-and-set", "if", "int->char", "lambda", "lcm", "let",
+    "let-values", "letrec", "list", "make-vector", "memoize", "mut-ref", "not", "nth", "null?",
+    "number->string", "or", "pair?", "product", "quasiquote", "quotient", "range", "read",
+    "read-bytes", "read-line", "ref", "remainder", "repeat", "round-to", "set", "string->list",
+    "string-join", "string-ref", "string-split", "sum", "time", "trace", "unbox", "unquote",
+    "values", "vector-ref", "vector-set!", "write-string",
+};
+
+// Lazily parses each top-level form of src
+pub fn parse_forms(src: &str) -> impl Iterator<Item = Result<Exp, ParseError>> + '_ {
+    Parser::default().into_forms(src)
+}
+
+// Lazily parses each top-level form of src with parser, registering macros on it as they're
+// defined
+fn forms_with<'a, P: BorrowMut<Parser> + 'a>(
+    mut parser: P,
+    src: &'a str,
+) -> impl Iterator<Item = Result<Exp, ParseError>> + 'a {
+    let mut forms = sexp::forms(src);
+    std::iter::from_fn(move || loop {
+        let parser = parser.borrow_mut();
+        let (s_exp, spans) = match forms.next()? {
+            Ok(form) => form,
+            Err(e) => return Some(Err(e.into())),
+        };
+        match parser.define_macro(&s_exp) {
+            Ok(true) => continue,
+            Ok(false) => return Some(parser.parse_top(s_exp, spans)),
+            Err(e) => return Some(Err(e)),
+        }
+    })
+}
+
+// Parses s-expressions into Exps, expanding macros registered with define-syntax as it goes
+#[derive(Default)]
+pub struct Parser {
+    macros: HashMap<String, Macro>,
+    // Recognize keywords such as IF or Lambda as their lowercase forms; identifiers are
+    // always case-sensitive
+    pub case_insensitive_keywords: bool,
+    // Wrap each parsed list in Exp::Spanned so runtime errors can point at the source
+    pub track_spans: bool,
+    // For each list being parsed, its elements and their elements paired with their spans;
+    // children are matched against these as they're parsed
+    span_frames: Vec<Vec<(Sexp, SpanTree)>>,
+}
+
+// Non-hygienic pattern substitution; every occurrence of a param in the template is replaced
+struct Macro {
+    params: Vec<String>,
+    template: Sexp,
+}
+
+impl Macro {
+    fn expand(&self, name: &str, args: &[Sexp]) -> Result<Sexp, ParseError> {
+        if args.len() != self.params.len() {
+            return Err(ParseError::MacroArity(name.to_string(), self.params.len()));
+        }
+        let bindings: HashMap<&str, &Sexp> =
+            self.params.iter().map(String::as_str).zip(args).collect();
+        Ok(substitute(&self.template, &bindings))
+    }
+}
+
+// Named float constants. inf, -inf and nan are already read as floats by the sexp reader.
+// These are parsed as literals, so they can't be shadowed; binding one is a parse error
+fn float_constant(name: &str) -> Option<f64> {
+    match name {
+        "pi" => Some(std::f64::consts::PI),
+        "e" => Some(std::f64::consts::E),
+        _ => None,
+    }
+}
+
+// Name introduced by a binding form, which can't be one of the float constants
+fn bound_name(name: &str) -> Result<String, ParseError> {
+    match float_constant(name) {
+        Some(_) => Err(ParseError::ReservedName(name.to_string())),
+        None => Ok(name.to_string()),
+    }
+}
+
+// Checks that every define in exp is where its name can be scoped: at the top level, when
+// allowed, or directly inside a begin. Runs after macros are expanded so their defines count
+fn check_defines(exp: &Exp, allowed: bool) -> Result<(), ParseError> {
+    match exp {
+        Exp::Spanned { exp, .. } => check_defines(exp, allowed),
+        Exp::Define { value, .. } if allowed => check_defines(value, false),
+        Exp::Define { .. } => Err(ParseError::MisplacedDefinition),
+        Exp::Begin(es) => es.iter().try_for_each(|e| check_defines(e, true)),
+        exp => exp
+            .children()
+            .into_iter()
+            .try_for_each(|e| check_defines(e, false)),
+    }
+}
+
+// Whether exp is a begin whose forms can replace it in the begin containing it, giving the
+// same result. A begin with defines keeps its own scope, and an empty begin resolves to Unit,
+// so neither is flattened. Boxes allocated by the inner forms are freed when the outer begin
+// ends rather than the inner one
+fn splices_into_begin(exp: &Exp) -> bool {
+    match exp {
+        Exp::Spanned { exp, .. } => splices_into_begin(exp),
+        Exp::Begin(es) => !es.is_empty() && es.iter().all(|e| e.defined_name().is_none()),
+        _ => false,
+    }
 }
 
-fn parse(s_exp: Sexp) -> Result<Exp, ParseError> {
-    use sexp::Atom::{F, I, S};
-    use sexp::Sexp::{Atom, List};
-    use Exp::*;
-    match s_exp {
-        Atom(I(i)) => Ok(Int(i)),
-        Atom(F(f)) => Ok(Float(f)),
-        Atom(S(s)) if s == "true" => Ok(Bool(true)),
-        Atom(S(s)) if s == "false" => Ok(Bool(false)),
-        Atom(S(s)) => Ok(Id(s)),
-        List(l) => parse_list(l),
+// Forms of a begin, dropping its span since each form has its own
+fn begin_forms(exp: Exp) -> Vec<Exp> {
+    match exp {
+        Exp::Spanned { exp, .. } => begin_forms(*exp),
+        Exp::Begin(es) => es,
+        exp => vec![exp],
     }
 }
 
-fn parse_list(list: Vec<Sexp>) -> Result<Exp, ParseError> {
-    use sexp::Atom::S;
-    use sexp::Sexp::Atom;
-    use sexp::Sexp::List;
-    use std::boxed::Box;
-    use Exp::*;
-    let first = list.first().ok_or(ParseError::NotImplemented)?;
-    match (first, &list[1..]) {
-        (Atom(S(func)), [lhs, rhs]) if func == "+" => Ok(Add {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [lhs, rhs]) if func == "-" => Ok(Sub {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [lhs, rhs]) if func == "*" => Ok(Mult {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [lhs, rhs]) if func == "/" => Ok(Div {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [lhs, rhs]) if func == "=" => Ok(Eq {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [lhs, rhs]) if func == "<" => Ok(Lt {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [lhs, rhs]) if func == ">" => Ok(Gt {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [lhs, rhs]) if func == "<=" => Ok(Le {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [lhs, rhs]) if func == ">=" => Ok(Ge {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [rest @ ..]) if func == "begin" => {
-            let parsed_exprs: Result<Vec<Exp>, ParseError> =
-                rest.iter().map(|expr| parse(expr.clone())).collect();
-            Ok(Exp::Begin(parsed_exprs?))
+fn substitute(template: &Sexp, bindings: &HashMap<&str, &Sexp>) -> Sexp {
+    match template {
+        Sexp::Atom(sexp::Atom::S(s)) => bindings
+            .get(s.as_str())
+            .map_or_else(|| template.clone(), |&arg| arg.clone()),
+        Sexp::Atom(_) => template.clone(),
+        Sexp::List(l) => Sexp::List(l.iter().map(|s| substitute(s, bindings)).collect()),
+    }
+}
+
+impl Parser {
+    pub fn parse(&mut self, input: &str) -> Result<Exp, ParseError> {
+        if !self.track_spans {
+            let exp = self.parse_sexp(sexp::parse(input)?)?;
+            check_defines(&exp, true)?;
+            return Ok(exp);
+        }
+        let (s_exp, spans) = sexp::parse_with_spans(input)?;
+        self.parse_top(s_exp, spans)
+    }
+
+    // Parses each top-level form of src as it's requested. Macros defined by a top-level
+    // define-syntax are available to the forms after it
+    pub fn into_forms(self, src: &str) -> impl Iterator<Item = Result<Exp, ParseError>> + '_ {
+        forms_with(self, src)
+    }
+
+    // Like into_forms, but macros defined in src stay registered on this parser afterwards,
+    // so a session can expand them in later inputs
+    pub fn forms<'a>(
+        &'a mut self,
+        src: &'a str,
+    ) -> impl Iterator<Item = Result<Exp, ParseError>> + 'a {
+        forms_with(self, src)
+    }
+
+    fn parse_top(&mut self, s_exp: Sexp, spans: SpanTree) -> Result<Exp, ParseError> {
+        let exp = if self.track_spans {
+            self.span_frames = vec![vec![(s_exp.clone(), spans)]];
+            let result = self.parse_sexp(s_exp);
+            self.span_frames.clear();
+            result?
+        } else {
+            self.parse_sexp(s_exp)?
+        };
+        check_defines(&exp, true)?;
+        Ok(exp)
+    }
+
+    // Finds and consumes the span of s_exp among the elements of the list being parsed.
+    // Equal siblings are matched in order, which is the order the parse arms visit them
+    fn take_span(&mut self, s_exp: &Sexp) -> Option<SpanTree> {
+        let frame = self.span_frames.last_mut()?;
+        let index = frame.iter().position(|(s, _)| s == s_exp)?;
+        Some(frame.remove(index).1)
+    }
+
+    fn parse_sexp(&mut self, s_exp: Sexp) -> Result<Exp, ParseError> {
+        use sexp::Atom::{Str, F, I, S};
+        use sexp::Sexp::{Atom, List};
+        use Exp::*;
+        let tree = match &s_exp {
+            List(_) => self.take_span(&s_exp),
+            Atom(_) => None,
+        };
+        if let (List(l), Some(tree)) = (&s_exp, tree) {
+            let mut frame: Vec<(Sexp, SpanTree)> = l
+                .iter()
+                .cloned()
+                .zip(tree.children.iter().cloned())
+                .collect();
+            // Elements of nested binding lists like (x 1) in let are parsed directly, so they
+            // are candidates too
+            for (s, t) in l.iter().zip(&tree.children) {
+                if let List(inner) = s {
+                    frame.extend(inner.iter().cloned().zip(t.children.iter().cloned()));
+                }
+            }
+            self.span_frames.push(frame);
+            let result = self.parse_list(l.clone());
+            self.span_frames.pop();
+            return Ok(Spanned {
+                span: tree.span,
+                exp: std::boxed::Box::new(result?),
+            });
+        }
+        match s_exp {
+            Atom(I(i)) => Ok(Int(i)),
+            Atom(F(f)) => Ok(Float(f)),
+            Atom(Str(s)) => Ok(Exp::Str(s)),
+            Atom(S(s)) if s == "true" || s == "#t" => Ok(Bool(true)),
+            Atom(S(s)) if s == "false" || s == "#f" => Ok(Bool(false)),
+            Atom(S(s)) if s == "nil" => Ok(Nil),
+            Atom(S(s)) if float_constant(&s).is_some() => Ok(Float(float_constant(&s).unwrap())),
+            Atom(S(s)) if s.starts_with("#\\") => match &s[2..] {
+                "space" => Ok(Char(' ')),
+                "newline" => Ok(Char('\n')),
+                name => {
+                    let mut chars = name.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => Ok(Char(c)),
+                        _ => Err(ParseError::UnknownCharacter(s)),
+                    }
+                }
+            },
+            Atom(S(s)) => Ok(Id(s)),
+            List(l) => self.parse_list(l),
+        }
+    }
+
+    fn parse_list(&mut self, mut list: Vec<Sexp>) -> Result<Exp, ParseError> {
+        use sexp::Atom::S;
+        use sexp::Sexp::Atom;
+        use sexp::Sexp::List;
+        use std::boxed::Box;
+        use Exp::*;
+        if let Some(Atom(S(name))) = list.first() {
+            if let Some(mac) = self.macros.get(name) {
+                let expanded = mac.expand(name, &list[1..])?;
+                return self.parse_sexp(expanded);
+            }
         }
-        (Atom(S(func)), [Atom(S(symbol)), body]) if func == "lambda" => Ok(Lambda {
-            arg: symbol.to_string(),
-            body: Box::new(parse(body.clone())?),
-        }),
-        (Atom(S(func)), [exp]) if func == "ref" => Ok(Ref(Box::new(parse(exp.clone())?))),
-        (Atom(S(func)), [exp]) if func == "mut-ref" => Ok(MutRef(Box::new(parse(exp.clone())?))),
-        (Atom(S(func)), [exp]) if func == "box" => Ok(Exp::Box(Box::new(parse(exp.clone())?))),
-        (Atom(S(func)), [exp]) if func == "unbox" => Ok(Unbox(Box::new(parse(exp.clone())?))),
-        (Atom(S(func)), [exp]) if func == "deref" => Ok(Deref(Box::new(parse(exp.clone())?))),
-        (Atom(S(func)), [exp]) if func == "display" => Ok(Display(Box::new(parse(exp.clone())?))),
-        (Atom(S(func)), [exp]) if func == "debug" => Ok(Debug(Box::new(parse(exp.clone())?))),
-        (Atom(S(func)), [lhs, rhs]) if func == "set" => Ok(Set {
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (func_exp, [arg]) if list.len() == 2 => Ok(App {
-            // TODO: Check if len check is
-            // necessary
-            func: Box::new(parse(func_exp.clone())?),
-            arg: Box::new(parse(arg.clone())?),
-        }),
-        (Atom(S(func)), [cond, lhs, rhs]) if func == "if" => Ok(If {
-            cond: Box::new(parse(cond.clone())?),
-            lhs: Box::new(parse(lhs.clone())?),
-            rhs: Box::new(parse(rhs.clone())?),
-        }),
-        (Atom(S(func)), [List(l), body]) if func == "let" => match &l[..] {
-            [Atom(S(arg)), val] => Ok(App {
-                func: Box::new(Lambda {
-                    arg: arg.to_string(),
-                    body: Box::new(parse(body.clone())?),
+        if let Some(Atom(S(name))) = list.first_mut() {
+            let lower = name.to_lowercase();
+            if self.case_insensitive_keywords && KEYWORDS.contains(lower.as_str()) {
+                *name = lower;
+            }
+        }
+        let first = list.first().ok_or(ParseError::NotImplemented)?;
+        match (first, &list[1..]) {
+            (Atom(S(func)), [lhs, rhs]) if func == "+" => Ok(Add {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "-" => Ok(Sub {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "*" => Ok(Mult {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "/" => Ok(Div {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "=" => Ok(Eq {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "<" => Ok(Lt {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == ">" => Ok(Gt {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "<=" => Ok(Le {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == ">=" => Ok(Ge {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "divmod" => Ok(DivMod {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [value, width]) if func == "format-width" => Ok(FormatWidth {
+                value: Box::new(self.parse_sexp(value.clone())?),
+                width: Box::new(self.parse_sexp(width.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "quotient" => Ok(Quotient {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "remainder" => Ok(Remainder {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "gcd" => Ok(Gcd {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "lcm" => Ok(Lcm {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [exp, Atom(S(ty))]) if func == ":" => Ok(Ann {
+                exp: Box::new(self.parse_sexp(exp.clone())?),
+                ty: Type::from_name(ty).ok_or_else(|| ParseError::UnknownType(ty.to_string()))?,
+            }),
+            (Atom(S(func)), [list, index]) if func == "nth" => Ok(Nth {
+                list: Box::new(self.parse_sexp(list.clone())?),
+                index: Box::new(self.parse_sexp(index.clone())?),
+            }),
+            (Atom(S(func)), [value]) if func == "number->string" => Ok(NumberToString {
+                value: Box::new(self.parse_sexp(value.clone())?),
+                precision: None,
+            }),
+            (Atom(S(func)), [value, precision]) if func == "number->string" => Ok(NumberToString {
+                value: Box::new(self.parse_sexp(value.clone())?),
+                precision: Some(Box::new(self.parse_sexp(precision.clone())?)),
+            }),
+            (Atom(S(func)), [list, value]) if func == "contains?" => Ok(Contains {
+                list: Box::new(self.parse_sexp(list.clone())?),
+                value: Box::new(self.parse_sexp(value.clone())?),
+            }),
+            (Atom(S(func)), [value, places]) if func == "round-to" => Ok(RoundTo {
+                value: Box::new(self.parse_sexp(value.clone())?),
+                places: Box::new(self.parse_sexp(places.clone())?),
+            }),
+            // The step defaults to counting up by one
+            (Atom(S(func)), [start, end]) if func == "range" => Ok(Range {
+                start: Box::new(self.parse_sexp(start.clone())?),
+                end: Box::new(self.parse_sexp(end.clone())?),
+                step: Box::new(Int(1)),
+            }),
+            (Atom(S(func)), [start, end, step]) if func == "range" => Ok(Range {
+                start: Box::new(self.parse_sexp(start.clone())?),
+                end: Box::new(self.parse_sexp(end.clone())?),
+                step: Box::new(self.parse_sexp(step.clone())?),
+            }),
+            (Atom(S(func)), [string, sep]) if func == "string-split" => Ok(StringSplit {
+                string: Box::new(self.parse_sexp(string.clone())?),
+                sep: Box::new(self.parse_sexp(sep.clone())?),
+            }),
+            (Atom(S(func)), [list, sep]) if func == "string-join" => Ok(StringJoin {
+                list: Box::new(self.parse_sexp(list.clone())?),
+                sep: Box::new(self.parse_sexp(sep.clone())?),
+            }),
+            (Atom(S(func)), [string, index]) if func == "string-ref" => Ok(StringRef {
+                string: Box::new(self.parse_sexp(string.clone())?),
+                index: Box::new(self.parse_sexp(index.clone())?),
+            }),
+            (Atom(S(func)), [exp]) if func == "read-bytes" => {
+                Ok(ReadBytes(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "bytes-length" => {
+                Ok(BytesLength(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [bytes, index]) if func == "bytes-ref" => Ok(BytesRef {
+                bytes: Box::new(self.parse_sexp(bytes.clone())?),
+                index: Box::new(self.parse_sexp(index.clone())?),
+            }),
+            (Atom(S(func)), [exp]) if func == "string->list" => {
+                Ok(StringToList(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [len, init]) if func == "make-vector" => Ok(MakeVector {
+                len: Box::new(self.parse_sexp(len.clone())?),
+                init: Box::new(self.parse_sexp(init.clone())?),
+            }),
+            (Atom(S(func)), [vector, index]) if func == "vector-ref" => Ok(VectorRef {
+                vector: Box::new(self.parse_sexp(vector.clone())?),
+                index: Box::new(self.parse_sexp(index.clone())?),
+            }),
+            (Atom(S(func)), [vector, index, value]) if func == "vector-set!" => Ok(VectorSet {
+                vector: Box::new(self.parse_sexp(vector.clone())?),
+                index: Box::new(self.parse_sexp(index.clone())?),
+                value: Box::new(self.parse_sexp(value.clone())?),
+            }),
+            (Atom(S(func)), [count, body]) if func == "repeat" => Ok(Repeat {
+                count: Box::new(self.parse_sexp(count.clone())?),
+                body: Box::new(self.parse_sexp(body.clone())?),
+            }),
+            (Atom(S(func)), rest) if func == "begin" => {
+                let mut parsed_exprs = Vec::new();
+                for expr in rest {
+                    if !self.define_macro(expr)? {
+                        let exp = self.parse_sexp(expr.clone())?;
+                        if splices_into_begin(&exp) {
+                            parsed_exprs.extend(begin_forms(exp));
+                        } else {
+                            parsed_exprs.push(exp);
+                        }
+                    }
+                }
+                Ok(Exp::Begin(parsed_exprs))
+            }
+            (Atom(S(func)), _) if func == "define-syntax" => {
+                Err(ParseError::MisplacedMacroDefinition)
+            }
+            (Atom(S(func)), rest) if func == "list" => {
+                let parsed_exprs: Result<Vec<Exp>, ParseError> = rest
+                    .iter()
+                    .map(|expr| self.parse_sexp(expr.clone()))
+                    .collect();
+                Ok(Exp::List(parsed_exprs?))
+            }
+            (Atom(S(func)), rest) if func == "and" || func == "or" => {
+                let parsed_exprs = rest
+                    .iter()
+                    .map(|expr| self.parse_sexp(expr.clone()))
+                    .collect::<Result<Vec<Exp>, ParseError>>()?;
+                Ok(if func == "and" {
+                    And(parsed_exprs)
+                } else {
+                    Or(parsed_exprs)
+                })
+            }
+            (Atom(S(func)), rest) if func == "values" => {
+                let parsed_exprs: Result<Vec<Exp>, ParseError> = rest
+                    .iter()
+                    .map(|expr| self.parse_sexp(expr.clone()))
+                    .collect();
+                Ok(Exp::Values(parsed_exprs?))
+            }
+            (Atom(S(func)), [Atom(S(symbol)), body]) if func == "lambda" => Ok(Lambda {
+                params: vec![bound_name(symbol)?],
+                rest: None,
+                body: Box::new(self.parse_sexp(body.clone())?),
+            }),
+            (Atom(S(func)), [List(params), body]) if func == "lambda" => {
+                let is_rest_marker = |p: &Sexp| matches!(p, Atom(S(s)) if s == "." || s == "&rest");
+                let (params, rest) = match params.iter().position(is_rest_marker) {
+                    Some(i) => match &params[i + 1..] {
+                        [Atom(S(rest))] => (&params[..i], Some(bound_name(rest)?)),
+                        _ => return Err(ParseError::MalformedParameters),
+                    },
+                    None => (&params[..], None),
+                };
+                Ok(Lambda {
+                    params: params
+                        .iter()
+                        .map(|param| match param {
+                            Atom(S(param)) => bound_name(param),
+                            _ => Err(ParseError::MalformedParameters),
+                        })
+                        .collect::<Result<Vec<String>, ParseError>>()?,
+                    rest,
+                    body: Box::new(self.parse_sexp(body.clone())?),
+                })
+            }
+            (Atom(S(func)), []) if func == "gc" => Ok(Gc),
+            (Atom(S(func)), []) if func == "read-line" => Ok(ReadLine),
+            (Atom(S(func)), []) if func == "flush" => Ok(Flush),
+            (Atom(S(func)), [actual, expected]) if func == "assert-eq" => Ok(AssertEq {
+                actual: Box::new(self.parse_sexp(actual.clone())?),
+                expected: Box::new(self.parse_sexp(expected.clone())?),
+            }),
+            (Atom(S(func)), [exp]) if func == "assert-error" => {
+                Ok(AssertError(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "not" => Ok(Not(Box::new(self.parse_sexp(exp.clone())?))),
+            (Atom(S(func)), [exp]) if func == "sum" => Ok(Sum(Box::new(self.parse_sexp(exp.clone())?))),
+            (Atom(S(func)), [exp]) if func == "product" => {
+                Ok(Product(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "read" => {
+                Ok(Read(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "eval" => {
+                Ok(Eval(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "function->string" => {
+                Ok(FunctionToString(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [code]) if func == "exit" => {
+                Ok(Exit(Box::new(self.parse_sexp(code.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "write-string" => {
+                Ok(WriteString(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "ref" => {
+                Ok(Ref(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "mut-ref" => {
+                Ok(MutRef(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "box" => {
+                Ok(Exp::Box(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "unbox" => {
+                Ok(Unbox(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "deref" => {
+                Ok(Deref(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "display" => {
+                Ok(Display(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "debug" => {
+                Ok(Debug(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "display-hex" => {
+                Ok(DisplayHex(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "time" => {
+                Ok(Time(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "car" => {
+                Ok(Car(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "cdr" => {
+                Ok(Cdr(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "pair?" => {
+                Ok(IsPair(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "null?" => {
+                Ok(IsNull(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "arity" => {
+                Ok(Arity(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "char->int" => {
+                Ok(CharToInt(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "int->char" => {
+                Ok(IntToChar(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [exp]) if func == "trace" => Ok(Trace {
+                name: match exp {
+                    Atom(S(name)) => name.to_string(),
+                    _ => "lambda".to_string(),
+                },
+                func: Box::new(self.parse_sexp(exp.clone())?),
+            }),
+            (Atom(S(func)), [template]) if func == "quasiquote" => {
+                Ok(Quasiquote(Box::new(self.parse_template(template)?)))
+            }
+            (Atom(S(func)), _) if func == "unquote" => Err(ParseError::MisplacedUnquote),
+            (Atom(S(func)), [exp]) if func == "memoize" => {
+                Ok(Memoize(Box::new(self.parse_sexp(exp.clone())?)))
+            }
+            (Atom(S(func)), [lhs, rhs]) if func == "set" => Ok(Set {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [lhs, rhs]) if func == "get-and-set" => Ok(GetAndSet {
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), [f, args_list]) if func == "apply" => Ok(Apply {
+                func: Box::new(self.parse_sexp(f.clone())?),
+                args_list: Box::new(self.parse_sexp(args_list.clone())?),
+            }),
+            (Atom(S(func)), [car, cdr]) if func == "cons" => Ok(Cons {
+                car: Box::new(self.parse_sexp(car.clone())?),
+                cdr: Box::new(self.parse_sexp(cdr.clone())?),
+            }),
+            (Atom(S(func)), [cond, lhs, rhs]) if func == "if" => Ok(If {
+                cond: Box::new(self.parse_sexp(cond.clone())?),
+                lhs: Box::new(self.parse_sexp(lhs.clone())?),
+                rhs: Box::new(self.parse_sexp(rhs.clone())?),
+            }),
+            (Atom(S(func)), clauses) if func == "if" && clauses.len() > 3 => {
+                self.parse_if_chain(clauses)
+            }
+            (Atom(S(func)), [List(l), body]) if func == "let" => match &l[..] {
+                [Atom(S(arg)), val] => Ok(App {
+                    func: Box::new(Lambda {
+                        params: vec![bound_name(arg)?],
+                        rest: None,
+                        body: Box::new(self.parse_sexp(body.clone())?),
+                    }),
+                    args: vec![self.parse_sexp(val.clone())?],
+                }),
+                _ => Err(ParseError::MalformedAssignment),
+            },
+            (Atom(S(func)), [Atom(S(name)), value]) if func == "define" => Ok(Define {
+                name: bound_name(name)?,
+                value: Box::new(self.parse_sexp(value.clone())?),
+            }),
+            (Atom(S(func)), [List(l), body]) if func == "let-values" => match &l[..] {
+                [List(names), val] => Ok(LetValues {
+                    names: names
+                        .iter()
+                        .map(|name| match name {
+                            Atom(S(name)) => bound_name(name),
+                            _ => Err(ParseError::MalformedValuesAssignment),
+                        })
+                        .collect::<Result<Vec<String>, ParseError>>()?,
+                    value: Box::new(self.parse_sexp(val.clone())?),
+                    body: Box::new(self.parse_sexp(body.clone())?),
                 }),
-                arg: Box::new(parse(val.clone())?),
+                _ => Err(ParseError::MalformedValuesAssignment),
+            },
+            (Atom(S(func)), [List(l), body]) if func == "letrec" => Ok(LetRecMany {
+                bindings: l
+                    .iter()
+                    .map(|binding| match binding {
+                        // Only lambdas are allowed so no binding needs another's value to be built
+                        List(pair) => match &pair[..] {
+                            [Atom(S(name)), List(lambda)]
+                                if matches!(lambda.first(), Some(Atom(S(head))) if head == "lambda") =>
+                            {
+                                Ok((bound_name(name)?, self.parse_sexp(pair[1].clone())?))
+                            }
+                            // A traced lambda, so recursive calls are traced too
+                            [Atom(S(name)), List(trace)]
+                                if matches!(&trace[..], [Atom(S(head)), List(lambda)]
+                                    if head == "trace"
+                                        && matches!(lambda.first(), Some(Atom(S(head))) if head == "lambda")) =>
+                            {
+                                Ok((
+                                    bound_name(name)?,
+                                    Trace {
+                                        name: name.to_string(),
+                                        func: Box::new(self.parse_sexp(trace[1].clone())?),
+                                    },
+                                ))
+                            }
+                            _ => Err(ParseError::MalformedLetRec),
+                        },
+                        _ => Err(ParseError::MalformedLetRec),
+                    })
+                    .collect::<Result<Vec<(String, Exp)>, ParseError>>()?,
+                body: Box::new(self.parse_sexp(body.clone())?),
             }),
-            _ => Err(ParseError::MalformedAssignment),
-        },
-        _ => Err(ParseError::ParseError),
+            // Keywords used with the wrong shape are errors rather than applications
+            (Atom(S(func)), _) if KEYWORDS.contains(func.as_str()) => Err(ParseError::ParseError),
+            (func_exp, args) => Ok(App {
+                func: Box::new(self.parse_sexp(func_exp.clone())?),
+                args: args
+                    .iter()
+                    .map(|arg| self.parse_sexp(arg.clone()))
+                    .collect::<Result<Vec<Exp>, ParseError>>()?,
+            }),
+        }
+    }
+
+    // Parses the clauses of (if <cond> <exp> elif <cond> <exp> ... else <exp>) into nested ifs
+    fn parse_if_chain(&mut self, clauses: &[Sexp]) -> Result<Exp, ParseError> {
+        use sexp::Atom::S;
+        use sexp::Sexp::Atom;
+        use std::boxed::Box;
+        let (cond, lhs, rhs) = match clauses {
+            [cond, lhs, Atom(S(keyword)), rhs] if keyword == "else" => {
+                (cond, lhs, self.parse_sexp(rhs.clone())?)
+            }
+            [cond, lhs, Atom(S(keyword)), rest @ ..] if keyword == "elif" => {
+                (cond, lhs, self.parse_if_chain(rest)?)
+            }
+            _ => return Err(ParseError::MalformedIf),
+        };
+        Ok(Exp::If {
+            cond: Box::new(self.parse_sexp(cond.clone())?),
+            lhs: Box::new(self.parse_sexp(lhs.clone())?),
+            rhs: Box::new(rhs),
+        })
+    }
+
+    // Parses a quasiquote template; lists become List and (unquote <exp>) forms are parsed as
+    // ordinary expressions
+    fn parse_template(&mut self, s_exp: &Sexp) -> Result<Exp, ParseError> {
+        use sexp::Atom::S;
+        use sexp::Sexp::{Atom, List};
+        match s_exp {
+            List(l) => match &l[..] {
+                [Atom(S(func)), exp] if func == "unquote" => {
+                    Ok(Exp::Unquote(Box::new(self.parse_sexp(exp.clone())?)))
+                }
+                _ => Ok(Exp::List(
+                    l.iter()
+                        .map(|s| self.parse_template(s))
+                        .collect::<Result<Vec<Exp>, ParseError>>()?,
+                )),
+            },
+            Atom(_) => match self.parse_sexp(s_exp.clone())? {
+                Exp::Id(name) => Err(ParseError::QuotedSymbol(name)),
+                exp => Ok(exp),
+            },
+        }
+    }
+
+    // Registers the macro if sexp is a (define-syntax (<name> <param>*) <template>) form
+    fn define_macro(&mut self, sexp: &Sexp) -> Result<bool, ParseError> {
+        use sexp::Atom::S;
+        use sexp::Sexp::{Atom, List};
+        let List(list) = sexp else {
+            return Ok(false);
+        };
+        match &list[..] {
+            [Atom(S(func)), List(pattern), template] if func == "define-syntax" => {
+                let mut symbols = pattern.iter().map(|s| match s {
+                    Atom(S(s)) => Ok(s.to_string()),
+                    _ => Err(ParseError::MalformedMacro),
+                });
+                let name = symbols.next().ok_or(ParseError::MalformedMacro)??;
+                let params = symbols.collect::<Result<Vec<String>, ParseError>>()?;
+                self.macros.insert(
+                    name,
+                    Macro {
+                        params,
+                        template: template.clone(),
+                    },
+                );
+                Ok(true)
+            }
+            [Atom(S(func)), ..] if func == "define-syntax" => Err(ParseError::MalformedMacro),
+            _ => Ok(false),
+        }
     }
 }
@@ -0,0 +1,231 @@
+use std::str::FromStr;
+use thiserror::Error;
+
+// Mirrors the shape of the sexp crate, with string literals kept separate from symbols
+#[derive(Clone, PartialEq, Debug)]
+pub enum Atom {
+    // Symbol
+    S(String),
+    // String literal
+    Str(String),
+    // Integer
+    I(i128),
+    // Float
+    F(f64),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Sexp {
+    Atom(Atom),
+    List(Vec<Sexp>),
+}
+
+// Byte offsets of an s-expression in the source, end exclusive
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+// Spans of an s-expression and, for lists, of each of its elements in order
+#[derive(Clone, PartialEq, Debug)]
+pub struct SpanTree {
+    pub span: Span,
+    pub children: Vec<SpanTree>,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{line}:{column}: {message}")]
+pub struct Error {
+    pub message: &'static str,
+    pub line: usize,
+    pub column: usize,
+    pub index: usize,
+}
+
+// Parse a string containing exactly one s-expression
+pub fn parse(s: &str) -> Result<Sexp, Box<Error>> {
+    parse_with_spans(s).map(|(sexp, _)| sexp)
+}
+
+// Like parse, but also returns where each s-expression came from
+pub fn parse_with_spans(s: &str) -> Result<(Sexp, SpanTree), Box<Error>> {
+    let mut reader = Reader { src: s, pos: 0 };
+    let parsed = reader.sexp()?;
+    reader.skip_whitespace();
+    if reader.pos == s.len() {
+        Ok(parsed)
+    } else {
+        Err(reader.error("unrecognized post-s-expression data"))
+    }
+}
+
+// Lazily reads each top-level s-expression in s; stops after the first error
+pub fn forms(s: &str) -> impl Iterator<Item = Result<(Sexp, SpanTree), Box<Error>>> + '_ {
+    let mut reader = Reader { src: s, pos: 0 };
+    let mut failed = false;
+    std::iter::from_fn(move || {
+        reader.skip_whitespace();
+        if failed || reader.pos == s.len() {
+            return None;
+        }
+        let form = reader.sexp();
+        failed = form.is_err();
+        Some(form)
+    })
+}
+
+struct Reader<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &'static str) -> Box<Error> {
+        let before = &self.src[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        Box::new(Error {
+            message,
+            line,
+            column,
+            index: self.pos,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    // Skips whitespace and ; line comments
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.next();
+            } else if c == ';' {
+                while !matches!(self.next(), Some('\n') | None) {}
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn sexp(&mut self) -> Result<(Sexp, SpanTree), Box<Error>> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let (sexp, children) = match self.peek() {
+            None => return Err(self.error("unexpected end of input")),
+            Some('(' | '[') => self.list()?,
+            Some(')') => return Err(self.error("unexpected closing paren")),
+            Some(']') => return Err(self.error("unexpected closing bracket")),
+            Some('"') => (self.string()?, Vec::new()),
+            Some('`') => self.prefixed("quasiquote")?,
+            Some(',') => self.prefixed("unquote")?,
+            Some(_) => (self.atom(), Vec::new()),
+        };
+        let span = Span {
+            start,
+            end: self.pos,
+        };
+        Ok((sexp, SpanTree { span, children }))
+    }
+
+    // Brackets can be used in place of parens, but a list must be closed by the kind it was
+    // opened with
+    fn list(&mut self) -> Result<(Sexp, Vec<SpanTree>), Box<Error>> {
+        let close = if self.next() == Some('[') { ']' } else { ')' };
+        let mut items = Vec::new();
+        let mut spans = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Err(self.error("unterminated list")),
+                Some(c) if c == close => {
+                    self.next();
+                    return Ok((Sexp::List(items), spans));
+                }
+                Some(')' | ']') => return Err(self.error("mismatched closing delimiter")),
+                Some(_) => {
+                    let (item, span) = self.sexp()?;
+                    items.push(item);
+                    spans.push(span);
+                }
+            }
+        }
+    }
+
+    // Reads `x and ,x as (quasiquote x) and (unquote x); the keyword takes the prefix's span
+    fn prefixed(&mut self, keyword: &str) -> Result<(Sexp, Vec<SpanTree>), Box<Error>> {
+        let start = self.pos;
+        self.next();
+        let prefix = SpanTree {
+            span: Span {
+                start,
+                end: self.pos,
+            },
+            children: Vec::new(),
+        };
+        let (item, span) = self.sexp()?;
+        let keyword = Sexp::Atom(Atom::S(keyword.to_string()));
+        Ok((Sexp::List(vec![keyword, item]), vec![prefix, span]))
+    }
+
+    fn string(&mut self) -> Result<Sexp, Box<Error>> {
+        self.next();
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => return Ok(Sexp::Atom(Atom::Str(s))),
+                Some('\\') => match self.next() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('0') => s.push('\0'),
+                    Some(c @ ('"' | '\\')) => s.push(c),
+                    _ => return Err(self.error("unknown escape character")),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Sexp {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '"' | ';' | '`' | ',') {
+                break;
+            }
+            self.next();
+        }
+        Sexp::Atom(atom_of_string(&self.src[start..self.pos]))
+    }
+}
+
+fn atom_of_string(s: &str) -> Atom {
+    if let Some(i) = int_of_string(s) {
+        return Atom::I(i);
+    }
+    if let Ok(f) = f64::from_str(s) {
+        return Atom::F(f);
+    }
+    Atom::S(s.to_string())
+}
+
+// Ints are decimal even with leading zeros, so 010 is ten rather than eight as in C. Octal must
+// be asked for with a 0o prefix, as in 0o10 or -0o10
+fn int_of_string(s: &str) -> Option<i128> {
+    let (sign, unsigned) = s.strip_prefix('-').map_or(("", s), |rest| ("-", rest));
+    match unsigned.strip_prefix("0o") {
+        Some(octal) if !octal.starts_with(['+', '-']) => {
+            i128::from_str_radix(&format!("{}{}", sign, octal), 8).ok()
+        }
+        _ => i128::from_str(s).ok(),
+    }
+}
@@ -0,0 +1,93 @@
+use crate::args::{parse_args, parse_flags, Command, Flags};
+use crate::interp::Strictness;
+
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_no_args_is_repl() {
+    assert_eq!(parse_args(&args(&[])), Ok(Command::Repl));
+    assert_eq!(parse_args(&args(&["repl"])), Ok(Command::Repl));
+}
+
+#[test]
+fn test_run() {
+    assert_eq!(
+        parse_args(&args(&["run", "prog.txt"])),
+        Ok(Command::Run("prog.txt".to_string()))
+    );
+}
+
+#[test]
+fn test_file_without_command_runs() {
+    assert_eq!(
+        parse_args(&args(&["input.lisp"])),
+        Ok(Command::Run("input.lisp".to_string()))
+    );
+}
+
+#[test]
+fn test_check() {
+    assert_eq!(
+        parse_args(&args(&["check", "prog.txt"])),
+        Ok(Command::Check("prog.txt".to_string()))
+    );
+}
+
+#[test]
+fn test_eval() {
+    assert_eq!(
+        parse_args(&args(&["eval", "(+ 1 2)"])),
+        Ok(Command::Eval("(+ 1 2)".to_string()))
+    );
+}
+
+#[test]
+fn test_ast() {
+    assert_eq!(
+        parse_args(&args(&["ast", "prog.txt"])),
+        Ok(Command::Ast("prog.txt".to_string()))
+    );
+}
+
+#[test]
+fn test_missing_operand() {
+    assert!(parse_args(&args(&["run"])).is_err());
+    assert!(parse_args(&args(&["eval", "1", "2"])).is_err());
+    assert!(parse_args(&args(&["repl", "x"])).is_err());
+}
+
+#[test]
+fn test_unknown() {
+    assert_eq!(
+        parse_args(&args(&["--json"])),
+        Err("Unknown flag '--json'".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["evla", "1"])),
+        Err("Unknown command 'evla'".to_string())
+    );
+}
+
+#[test]
+fn test_strict_flags() {
+    let list = args(&["--strict", "run", "prog.txt"]);
+    let (flags, rest) = parse_flags(&list);
+    assert_eq!((flags.strict, rest), (Some(Strictness::Warn), &list[1..]));
+    let list = args(&["--strict=error", "check", "prog.txt"]);
+    let (flags, rest) = parse_flags(&list);
+    assert_eq!((flags.strict, rest), (Some(Strictness::Error), &list[1..]));
+    let list = args(&["run", "--strict"]);
+    assert_eq!(parse_flags(&list), (Flags::default(), &list[..]));
+}
+
+#[test]
+fn test_fold_flag() {
+    let list = args(&["--fold", "--strict", "run", "prog.txt"]);
+    let expected = Flags {
+        strict: Some(Strictness::Warn),
+        fold: true,
+    };
+    assert_eq!(parse_flags(&list), (expected, &list[2..]));
+}
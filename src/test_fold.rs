@@ -0,0 +1,127 @@
+use crate::fold::{fold_constants, fold_constants_for};
+use crate::interp::{self, IntWidth, Interpreter};
+use crate::parse::{self, Exp::*};
+use std::boxed::Box;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+
+fn fold(input: &str) -> Result<parse::Exp, parse::ParseError> {
+    Ok(fold_constants(parse::Exp::try_from(input)?))
+}
+
+macro_rules! fold_testcase {
+    ($input:expr, $output:expr, $testname:ident) => {
+        #[test]
+        fn $testname() -> Result<(), parse::ParseError> {
+            assert_eq!(fold($input)?, $output);
+            Ok(())
+        }
+    };
+}
+
+fold_testcase!("(+ 1 (* 2 3))", Int(7), test_fold_arithmetic);
+fold_testcase!("(/ 7.5 2.5)", Float(3.0), test_fold_float);
+fold_testcase!("(if (< 1 2) (+ 1 1) 0)", Int(2), test_fold_if);
+fold_testcase!(
+    "(lambda x (+ x (* 2 3)))",
+    Lambda {
+        params: vec!["x".to_string()],
+        rest: None,
+        body: Box::new(Add {
+            lhs: Box::new(Id("x".to_string())),
+            rhs: Box::new(Int(6)),
+        }),
+    },
+    test_fold_inside_lambda
+);
+fold_testcase!(
+    "(+ x (+ 1 2))",
+    Add {
+        lhs: Box::new(Id("x".to_string())),
+        rhs: Box::new(Int(3)),
+    },
+    test_no_fold_id
+);
+fold_testcase!(
+    "(+ 1 (display 2))",
+    Add {
+        lhs: Box::new(Int(1)),
+        rhs: Box::new(Display(Box::new(Int(2)))),
+    },
+    test_no_fold_display
+);
+fold_testcase!(
+    "(unbox (box (+ 1 2)))",
+    Unbox(Box::new(Box(Box::new(Int(3))))),
+    test_no_fold_box
+);
+fold_testcase!(
+    "(/ 1 0)",
+    Div {
+        lhs: Box::new(Int(1)),
+        rhs: Box::new(Int(0)),
+    },
+    test_no_fold_error
+);
+
+#[test]
+fn test_fold_same_result() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("((lambda x (* x (- 10 (/ 8 2)))) (+ 1 2))")?;
+    let folded = interp::interp(fold_constants(exp.clone()), &mut HashMap::new())?;
+    let unfolded = interp::interp(exp, &mut HashMap::new())?;
+    assert_eq!(format!("{:?}", folded), format!("{:?}", unfolded));
+    Ok(())
+}
+
+#[test]
+fn test_fold_follows_int_width() -> Result<(), parse::ParseError> {
+    let mut options = Interpreter::new(Box::new(io::sink()));
+    options.int_width = IntWidth::I32;
+    let exp = parse::Exp::try_from("(+ 2147483647 1)")?;
+    assert_eq!(fold_constants_for(exp.clone(), &options), exp);
+    Ok(())
+}
+
+#[test]
+fn test_fold_follows_ieee_float_div() -> Result<(), parse::ParseError> {
+    let mut options = Interpreter::new(Box::new(io::sink()));
+    options.ieee_float_div = true;
+    let exp = parse::Exp::try_from("(/ 1.0 0.0)")?;
+    assert_eq!(fold_constants_for(exp, &options), Float(f64::INFINITY));
+    Ok(())
+}
+
+#[test]
+fn test_fold_follows_truthy_conditions() -> Result<(), parse::ParseError> {
+    assert_eq!(
+        fold("(if 1 2 3)")?,
+        If {
+            cond: Box::new(Int(1)),
+            lhs: Box::new(Int(2)),
+            rhs: Box::new(Int(3)),
+        }
+    );
+    let mut options = Interpreter::new(Box::new(io::sink()));
+    options.truthy_conditions = true;
+    let exp = parse::Exp::try_from("(if 1 2 3)")?;
+    assert_eq!(fold_constants_for(exp, &options), Int(2));
+    Ok(())
+}
+
+#[test]
+fn test_interpreter_folds_top_level_forms() -> Result<(), Box<dyn Error>> {
+    let mut parser = parse::Parser::default();
+    parser.track_spans = true;
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.fold_constants = true;
+    interpreter.profile = Some(interp::Profile::default());
+    let exp = parser.parse("((lambda x (* x (- 10 (/ 8 2)))) (+ 1 2))")?;
+    let value = interpreter.interp_top(exp, &mut HashMap::new())?;
+    assert_eq!(format!("{:?}", value), "Int(18)");
+    let profile = interpreter.profile.as_ref().unwrap();
+    assert_eq!(profile.count("Mult"), 1);
+    assert_eq!(profile.count("Sub"), 0);
+    assert_eq!(profile.count("Add"), 0);
+    Ok(())
+}
@@ -0,0 +1,2962 @@
+use crate::interp::{self, IntWidth, InterpError, Interpreter, Strictness, Value, WarningKind};
+use crate::parse;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Output sink that can be read back after the interpreter has written to it
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+// Evaluates the input and returns everything it printed
+fn interp_output(input: &str) -> Result<String, Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    interpreter.interp(parse::Exp::try_from(input)?, &mut HashMap::new())?;
+    interpreter.flush()?;
+    Ok(output.contents())
+}
+
+macro_rules! interp_testcase {
+    ($input:expr, $output:expr, $testname:ident) => {
+        #[test]
+        fn $testname() -> Result<(), Box<dyn Error>> {
+            let exp = parse::Exp::try_from($input)?;
+            let value = interp::interp(exp, &mut HashMap::new())?;
+            assert_eq!(format!("{:?}", value), $output);
+            Ok(())
+        }
+    };
+}
+
+macro_rules! output_testcase {
+    ($input:expr, $output:expr, $testname:ident) => {
+        #[test]
+        fn $testname() -> Result<(), Box<dyn Error>> {
+            assert_eq!(interp_output($input)?, $output);
+            Ok(())
+        }
+    };
+}
+
+macro_rules! interp_error_testcase {
+    ($input:expr, $error:pat, $testname:ident) => {
+        #[test]
+        fn $testname() -> Result<(), Box<dyn Error>> {
+            let exp = parse::Exp::try_from($input)?;
+            let result = interp::interp(exp, &mut HashMap::new());
+            assert!(matches!(result, Err($error)), "got {:?}", result);
+            Ok(())
+        }
+    };
+}
+
+interp_testcase!("(+ 4 7)", "Int(11)", test_add);
+interp_testcase!("(if (< 1 2) 1.5 2.5)", "Float(1.5)", test_if);
+interp_error_testcase!("(/ 1 0)", InterpError::DivisionByZero, test_div_by_zero);
+interp_error_testcase!(
+    "(/ 1.0 0.0)",
+    InterpError::DivisionByZero,
+    test_float_div_by_zero
+);
+
+interp_testcase!("nil", "Nil", test_nil);
+interp_testcase!(
+    "(cons 1 (cons 2 nil))",
+    "Pair(Int(1), Pair(Int(2), Nil))",
+    test_cons_list
+);
+interp_testcase!("(cons 1 2)", "Pair(Int(1), Int(2))", test_cons_improper);
+interp_testcase!("(car (cons 1 (cons 2 nil)))", "Int(1)", test_car);
+interp_testcase!("(cdr (cons 1 (cons 2 nil)))", "Pair(Int(2), Nil)", test_cdr);
+interp_testcase!("(null? nil)", "Bool(true)", test_null_nil);
+interp_testcase!("(null? (cons 1 nil))", "Bool(false)", test_null_pair);
+interp_testcase!("(null? 0)", "Bool(false)", test_null_int);
+interp_testcase!("(pair? (cons 1 nil))", "Bool(true)", test_pair_pair);
+interp_testcase!("(pair? nil)", "Bool(false)", test_pair_nil);
+interp_testcase!("(pair? true)", "Bool(false)", test_pair_bool);
+interp_testcase!("(if true nil (cons 1 nil))", "Nil", test_if_list_branches);
+interp_error_testcase!("(car nil)", InterpError::NotAPair, test_car_nil);
+interp_error_testcase!("(cdr 5)", InterpError::NotAPair, test_cdr_int);
+
+interp_testcase!(r#""a\tb""#, r#"Str("a\tb")"#, test_str);
+output_testcase!("(display 5)", "5\n", test_display_int);
+output_testcase!(
+    r#"(display "a\tb \"c\"")"#,
+    "a\tb \"c\"\n",
+    test_display_str_raw
+);
+output_testcase!(
+    r#"(debug "a\tb \"c\"")"#,
+    r#"Str("a\tb \"c\"")"#,
+    test_debug_str_escaped
+);
+
+interp_testcase!("(divmod 7 2)", "Values(Int(3), Int(1))", test_divmod);
+interp_testcase!(
+    "(values 1 true 2.5)",
+    "Values(Int(1), Bool(true), Float(2.5))",
+    test_values
+);
+interp_testcase!("(values 1)", "Int(1)", test_values_single);
+interp_testcase!(
+    "(let-values ((q r) (divmod 17 5)) (+ (* q 10) r))",
+    "Int(32)",
+    test_let_values_divmod
+);
+interp_testcase!(
+    "(let-values ((a b) ((lambda x (values x (* x 2))) 4)) (- b a))",
+    "Int(4)",
+    test_let_values_from_function
+);
+interp_error_testcase!(
+    "(+ 1 (divmod 7 2))",
+    InterpError::UnexpectedMultipleValues,
+    test_multiple_values_in_single_context
+);
+interp_error_testcase!(
+    "(let-values ((a b c) (divmod 7 2)) a)",
+    InterpError::ValueCountMismatch {
+        expected: 3,
+        got: 2
+    },
+    test_let_values_count_mismatch
+);
+interp_error_testcase!(
+    "(divmod 7 0)",
+    InterpError::DivisionByZero,
+    test_divmod_by_zero
+);
+interp_error_testcase!(
+    "(divmod -9223372036854775808 -1)",
+    InterpError::IntegerOverflow,
+    test_divmod_overflow
+);
+
+output_testcase!(
+    "(let (f (memoize (lambda x (display (* x 10))))) (+ (f 1) (+ (f 2) (+ (f 1) (f 2)))))",
+    "10\n20\n",
+    test_memoize_runs_once_per_argument
+);
+output_testcase!(
+    "(let (f (memoize (lambda x (display x)))) (+ (f 1.5) (f 1.5)))",
+    "1.5\n1.5\n",
+    test_memoize_bypasses_unhashable_argument
+);
+interp_testcase!(
+    "(let (f (memoize (lambda x (* x x)))) (+ (f 3) (f 3)))",
+    "Int(18)",
+    test_memoize_result
+);
+interp_error_testcase!(
+    "(memoize 5)",
+    InterpError::NotAClosure,
+    test_memoize_not_closure
+);
+
+interp_testcase!("(quotient 7 2)", "Int(3)", test_quotient);
+interp_testcase!("(quotient -7 2)", "Int(-3)", test_quotient_negative);
+interp_testcase!("(remainder 7 2)", "Int(1)", test_remainder);
+interp_testcase!("(remainder -7 2)", "Int(-1)", test_remainder_negative);
+interp_error_testcase!(
+    "(quotient 7 0)",
+    InterpError::DivisionByZero,
+    test_quotient_by_zero
+);
+interp_error_testcase!(
+    "(remainder 7 0)",
+    InterpError::DivisionByZero,
+    test_remainder_by_zero
+);
+interp_error_testcase!(
+    "(quotient -9223372036854775808 -1)",
+    InterpError::IntegerOverflow,
+    test_quotient_overflow
+);
+interp_error_testcase!(
+    "(remainder -9223372036854775808 -1)",
+    InterpError::IntegerOverflow,
+    test_remainder_overflow
+);
+interp_error_testcase!(
+    "(quotient 7.0 2)",
+    InterpError::NotAnInteger,
+    test_quotient_float
+);
+interp_error_testcase!(
+    "(remainder 7 2.0)",
+    InterpError::NotAnInteger,
+    test_remainder_float
+);
+interp_error_testcase!(
+    "(quotient true 2)",
+    InterpError::NotANumber,
+    test_quotient_bool
+);
+interp_testcase!("(gcd 12 18)", "Int(6)", test_gcd);
+interp_testcase!("(gcd -12 18)", "Int(6)", test_gcd_negative);
+interp_testcase!("(gcd 0 5)", "Int(5)", test_gcd_zero);
+interp_testcase!("(gcd 0 0)", "Int(0)", test_gcd_both_zero);
+interp_testcase!("(lcm 4 6)", "Int(12)", test_lcm);
+interp_testcase!("(lcm -4 6)", "Int(12)", test_lcm_negative);
+interp_testcase!("(lcm 0 6)", "Int(0)", test_lcm_zero);
+interp_error_testcase!(
+    "(lcm 9223372036854775807 2)",
+    InterpError::IntegerOverflow,
+    test_lcm_overflow
+);
+interp_error_testcase!("(gcd 12 1.5)", InterpError::NotANumber, test_gcd_float);
+interp_error_testcase!("(lcm true 2)", InterpError::NotANumber, test_lcm_bool);
+
+interp_testcase!("(format-width 42 5)", r#"Str("   42")"#, test_format_width);
+interp_testcase!(
+    "(format-width 1.5 6)",
+    r#"Str("   1.5")"#,
+    test_format_width_float
+);
+interp_testcase!(
+    "(format-width 123456 3)",
+    r#"Str("123456")"#,
+    test_format_width_too_narrow
+);
+interp_error_testcase!(
+    "(format-width 42 -1)",
+    InterpError::NegativeWidth,
+    test_format_width_negative
+);
+interp_error_testcase!(
+    "(format-width true 5)",
+    InterpError::NotANumber,
+    test_format_width_bool
+);
+interp_error_testcase!(
+    "(format-width 42 2.0)",
+    InterpError::NotAnInteger,
+    test_format_width_float_width
+);
+
+output_testcase!(
+    r#"(repeat 3 (display "hi"))"#,
+    "hi\nhi\nhi\n",
+    test_repeat_display
+);
+interp_testcase!(
+    "(let (b (box 0)) (repeat 4 (begin (set b (+ (unbox b) 2)) (unbox b))))",
+    "Int(8)",
+    test_repeat_last_value
+);
+interp_testcase!(r#"(repeat 0 (display "hi"))"#, "Unit", test_repeat_zero);
+interp_error_testcase!(
+    "(repeat -1 5)",
+    InterpError::NegativeCount,
+    test_repeat_negative
+);
+interp_error_testcase!(
+    "(repeat 1.5 5)",
+    InterpError::NotAnInteger,
+    test_repeat_float_count
+);
+
+interp_testcase!(r#"(string-ref "abc" 1)"#, "Char('b')", test_string_ref);
+interp_testcase!(
+    r#"(string-ref "cafés" 3)"#,
+    "Char('é')",
+    test_string_ref_multibyte
+);
+interp_testcase!(
+    r#"(string-ref "cafés" 4)"#,
+    "Char('s')",
+    test_string_ref_after_multibyte
+);
+interp_error_testcase!(
+    r#"(string-ref "café" 4)"#,
+    InterpError::IndexOutOfBounds { index: 4, len: 4 },
+    test_string_ref_out_of_bounds
+);
+interp_error_testcase!(
+    r#"(string-ref "abc" -1)"#,
+    InterpError::IndexOutOfBounds { index: -1, len: 3 },
+    test_string_ref_negative
+);
+interp_error_testcase!(
+    "(string-ref 5 0)",
+    InterpError::NotAString,
+    test_string_ref_not_string
+);
+interp_testcase!(
+    r#"(string->list "ab")"#,
+    "List(Char('a'), Char('b'))",
+    test_string_to_list
+);
+interp_testcase!(
+    r#"(string->list "é😀")"#,
+    "List(Char('é'), Char('😀'))",
+    test_string_to_list_multibyte
+);
+interp_testcase!(r#"(string->list "")"#, "List()", test_string_to_list_empty);
+
+interp_testcase!("(: (+ 1 2) Int)", "Int(3)", test_annotation);
+interp_testcase!("((: (lambda x x) Fn) 4)", "Int(4)", test_annotation_fn);
+interp_error_testcase!(
+    "(: true Int)",
+    InterpError::AnnotationMismatch(parse::Type::Int),
+    test_annotation_mismatch
+);
+
+interp_testcase!("(begin 1 2 3)", "Int(3)", test_begin);
+interp_testcase!("(nth (list 10 20 30) 1)", "Int(20)", test_nth);
+interp_testcase!("(nth (list 10 20 30) 2)", "Int(30)", test_nth_last);
+interp_error_testcase!(
+    "(nth (list 10 20 30) 3)",
+    InterpError::IndexOutOfBounds { index: 3, len: 3 },
+    test_nth_out_of_bounds
+);
+interp_error_testcase!(
+    "(nth (list 10 20 30) -1)",
+    InterpError::IndexOutOfBounds { index: -1, len: 3 },
+    test_nth_negative
+);
+interp_error_testcase!(
+    "(nth (cons 1 nil) 0)",
+    InterpError::NotAList,
+    test_nth_not_list
+);
+interp_error_testcase!(
+    "(nth (list 1) true)",
+    InterpError::NotAnInteger,
+    test_nth_bool_index
+);
+
+output_testcase!(
+    "(begin (display 1) (display 2))",
+    "1\n2\n",
+    test_begin_order
+);
+interp_testcase!("(begin)", "Unit", test_begin_empty);
+interp_testcase!("(begin (+ 1 2))", "Int(3)", test_begin_single);
+interp_testcase!("(begin 1 2 (+ 1 2))", "Int(3)", test_begin_multiple);
+
+output_testcase!(
+    "(begin (define-syntax (twice x) (begin x x)) (twice (display 1)))",
+    "1\n1\n",
+    test_macro_twice
+);
+output_testcase!(
+    "(begin
+       (define-syntax (twice x) (begin x x))
+       (define-syntax (quad x) (twice (twice x)))
+       (quad (display 2)))",
+    "2\n2\n2\n2\n",
+    test_macro_nested_definition
+);
+output_testcase!(
+    "(begin (define-syntax (twice x) (begin x x)) (twice (twice (display 3))))",
+    "3\n3\n3\n3\n",
+    test_macro_nested_call
+);
+interp_testcase!(
+    "(begin (define-syntax (swap-sub a b) (- b a)) (swap-sub 1 10))",
+    "Int(9)",
+    test_macro_multiple_params
+);
+
+output_testcase!("(display-hex 255)", "0xff\n", test_display_hex);
+output_testcase!("(display-hex 0)", "0x0\n", test_display_hex_zero);
+output_testcase!(
+    "(display-hex -1)",
+    "0xffffffffffffffff\n",
+    test_display_hex_negative
+);
+interp_testcase!(
+    "(+ 1 (display-hex 255))",
+    "Int(256)",
+    test_display_hex_returns_value
+);
+interp_error_testcase!(
+    "(display-hex 1.5)",
+    InterpError::NotAnInteger,
+    test_display_hex_float
+);
+
+interp_testcase!("(time (* 6 7))", "Int(42)", test_time_returns_value);
+interp_testcase!(
+    "(time (divmod 7 2))",
+    "Values(Int(3), Int(1))",
+    test_time_returns_values
+);
+output_testcase!("(time (display 1))", "1\n", test_time_keeps_output);
+
+interp_testcase!("(unbox (box 5))", "Int(5)", test_unbox);
+interp_testcase!(
+    "((unbox (box (lambda x (+ x 1)))) 4)",
+    "Int(5)",
+    test_unbox_closure
+);
+interp_testcase!(
+    "(unbox (unbox (box (box 5))))",
+    "Int(5)",
+    test_unbox_nested_box
+);
+interp_testcase!("(unbox (box (ref 5)))", "Ref(Int(5))", test_unbox_ref);
+interp_testcase!(
+    "(let (b (box 5)) ((lambda x (unbox x)) b))",
+    "Int(5)",
+    test_box_moved_into_call
+);
+interp_error_testcase!(
+    "(let (b (box 5)) (begin ((lambda x (unbox x)) b) (unbox b)))",
+    InterpError::UseAfterMove(_),
+    test_use_after_move
+);
+interp_testcase!(
+    "(let (n 5) (begin ((lambda x x) n) n))",
+    "Int(5)",
+    test_non_box_argument_not_moved
+);
+
+#[test]
+fn test_box_closure() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("(unbox (box (lambda x x)))")?;
+    let value = interp::interp(exp, &mut HashMap::new())?;
+    assert!(matches!(value, interp::Value::Closure { .. }));
+    Ok(())
+}
+interp_testcase!("(box 5)", "Box(0)", test_box);
+interp_error_testcase!("(unbox 5)", InterpError::NotABox, test_unbox_not_box);
+
+#[test]
+fn test_max_allocations() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.max_allocations = Some(2);
+    let exp = parse::Exp::try_from("(cons (box 1) (box 2))")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    let exp = parse::Exp::try_from("(box 3)")?;
+    let result = interpreter.interp(exp, &mut HashMap::new());
+    assert!(matches!(result, Err(InterpError::HeapExhausted)));
+    Ok(())
+}
+
+#[test]
+fn test_max_allocations_exceeded_in_program() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.max_allocations = Some(2);
+    let exp = parse::Exp::try_from("(begin (box 1) (box 2) (box 3))")?;
+    let result = interpreter.interp(exp, &mut HashMap::new());
+    assert!(matches!(result, Err(InterpError::HeapExhausted)));
+    Ok(())
+}
+
+#[test]
+fn test_gc_frees_unreachable() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(cons (box 1) (box 2))")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    assert_eq!(interpreter.live_count(), 2);
+    assert_eq!(interpreter.gc(&HashMap::new()), 2);
+    assert_eq!(interpreter.live_count(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_gc_makes_room() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.max_allocations = Some(1);
+    let exp = parse::Exp::try_from("(box 1)")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    interpreter.gc(&HashMap::new());
+    let exp = parse::Exp::try_from("(unbox (box 2))")?;
+    assert_eq!(
+        format!("{:?}", interpreter.interp(exp, &mut HashMap::new())?),
+        "Int(2)"
+    );
+    Ok(())
+}
+
+interp_testcase!(
+    "(begin (define b (box 1)) (box 2) (gc))",
+    "Int(1)",
+    test_gc_keeps_bound_boxes
+);
+interp_testcase!(
+    "(begin (define b (box (box 1))) (gc) (unbox (unbox b)))",
+    "Int(1)",
+    test_gc_follows_nested_boxes
+);
+// Below the top level, enclosing calls and half-evaluated forms hold values env can't see
+interp_testcase!(
+    "(let (b (box 1)) (begin ((lambda x (gc)) 0) (unbox b)))",
+    "Int(1)",
+    test_gc_keeps_boxes_held_by_callers
+);
+interp_testcase!(
+    "(unbox (car (cons (box 1) (begin (gc) 0))))",
+    "Int(1)",
+    test_gc_keeps_evaluated_arguments
+);
+interp_testcase!(
+    "(begin (box 2) ((lambda x (gc)) 0))",
+    "Int(0)",
+    test_gc_below_top_level_frees_nothing
+);
+
+interp_testcase!(
+    "(let (inc (lambda x (+ x 1))) (let (dec (lambda x (- x 1))) ((if true inc dec) 5)))",
+    "Int(6)",
+    test_apply_if_result
+);
+interp_testcase!(
+    "(let (inc (lambda x (+ x 1))) (let (dec (lambda x (- x 1))) ((if false inc dec) 5)))",
+    "Int(4)",
+    test_apply_if_result_else
+);
+
+#[test]
+fn test_unused_value_warning() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(begin 5 6)")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    let warnings = interpreter.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, WarningKind::UnusedValue);
+    assert_eq!(warnings[0].form, Some(parse::Exp::Int(5)));
+    Ok(())
+}
+
+#[test]
+fn test_side_effect_not_warned() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(begin (display 5) 6)")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    assert!(interpreter.warnings().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_strict_unused_parameter_warning() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.strict = Some(Strictness::Warn);
+    let exp = parse::Exp::try_from("(lambda (x y) (+ y 5))")?;
+    interpreter.check_strict(&exp)?;
+    let warnings = interpreter.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, WarningKind::UnusedParameter);
+    assert_eq!(warnings[0].message, "Parameter 'x' is never used");
+    Ok(())
+}
+
+#[test]
+fn test_strict_unused_parameter_error() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.strict = Some(Strictness::Error);
+    let exp = parse::Exp::try_from("(let (f (lambda x 5)) (f 1))")?;
+    let result = interpreter.check_strict(&exp);
+    assert!(
+        matches!(&result, Err(InterpError::UnusedParameter(p)) if p == "x"),
+        "got {:?}",
+        result
+    );
+    Ok(())
+}
+
+#[test]
+fn test_strict_used_parameter() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.strict = Some(Strictness::Error);
+    let exp = parse::Exp::try_from("(lambda x (lambda y (+ x y)))")?;
+    interpreter.check_strict(&exp)?;
+    assert!(interpreter.warnings().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_strict_off_by_default() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(lambda x 5)")?;
+    interpreter.check_strict(&exp)?;
+    assert!(interpreter.warnings().is_empty());
+    Ok(())
+}
+
+interp_testcase!("(deref (box 5))", "Int(5)", test_deref_box);
+interp_testcase!("(deref (box (box 5)))", "Int(5)", test_deref_nested_box);
+interp_testcase!(
+    "(let (b (box 1)) (begin (set b 2) (unbox b)))",
+    "Int(2)",
+    test_set_box
+);
+interp_error_testcase!(
+    "(let (b (box (box 0))) (begin (set b b) (deref b)))",
+    InterpError::RefCycleDetected,
+    test_deref_cycle
+);
+
+#[test]
+fn test_render_box_shows_contents() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(box (box 5))")?;
+    let value = interpreter.interp(exp, &mut HashMap::new())?;
+    assert_eq!(interpreter.render(&value), "Box(Box(Int(5)))");
+    assert_eq!(format!("{:?}", value), "Box(1)");
+    Ok(())
+}
+
+#[test]
+fn test_render_box_cycle() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(let (b (box (box 0))) (begin (set b b) b))")?;
+    let value = interpreter.interp(exp, &mut HashMap::new())?;
+    assert_eq!(interpreter.render(&value), "Box(Box(...))");
+    Ok(())
+}
+
+output_testcase!("(display (box 5))", "Box(Int(5))\n", test_display_box);
+
+interp_testcase!("(list 1 (+ 1 1))", "List(Int(1), Int(2))", test_list);
+interp_testcase!(r#"(+ "foo" "bar")"#, r#"Str("foobar")"#, test_add_strings);
+interp_testcase!(
+    "(+ (list 1) (list 2))",
+    "List(Int(1), Int(2))",
+    test_add_lists
+);
+interp_testcase!("(+ 1.5 2.5)", "Float(4)", test_add_floats_still_numeric);
+interp_error_testcase!(
+    r#"(+ "foo" 1)"#,
+    InterpError::IncompatibleTypes { .. },
+    test_add_string_int
+);
+interp_error_testcase!(
+    r#"(+ (list 1) "foo")"#,
+    InterpError::IncompatibleTypes { .. },
+    test_add_list_string
+);
+
+// Evaluates input with integers restricted to the given width
+fn interp_with_width(input: &str, width: IntWidth) -> Result<String, Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.int_width = width;
+    let exp = parse::Exp::try_from(input)?;
+    Ok(format!(
+        "{:?}",
+        interpreter.interp(exp, &mut HashMap::new())?
+    ))
+}
+
+#[test]
+fn test_i32_overflow() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_with_width("(+ 2147483646 1)", IntWidth::I32)?,
+        "Int(2147483647)"
+    );
+    for input in [
+        "(* 100000 100000)",
+        "(+ 2147483647 1)",
+        "(- -2147483648 1)",
+        "2147483648",
+        "(quotient -2147483648 -1)",
+        "(divmod -2147483648 -1)",
+        "(gcd -2147483648 0)",
+        "(lcm 65536 65537)",
+    ] {
+        let result = interp_with_width(input, IntWidth::I32);
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(InterpError::IntegerOverflow)
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_i64_overflow() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_with_width("(* 100000 100000)", IntWidth::I64)?,
+        "Int(10000000000)"
+    );
+    for input in [
+        "9223372036854775808",
+        "(+ 9223372036854775807 1)",
+        "(* 9223372036854775807 2)",
+        "(/ -9223372036854775808 -1)",
+    ] {
+        let result = interp_with_width(input, IntWidth::I64);
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(InterpError::IntegerOverflow)
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_i128_overflow() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_with_width("(* 9223372036854775807 2)", IntWidth::I128)?,
+        "Int(18446744073709551614)"
+    );
+    assert_eq!(
+        interp_with_width(
+            "(+ 170141183460469231731687303715884105726 1)",
+            IntWidth::I128
+        )?,
+        "Int(170141183460469231731687303715884105727)"
+    );
+    for input in [
+        "(+ 170141183460469231731687303715884105727 1)",
+        "(- -170141183460469231731687303715884105728 1)",
+        "(* 170141183460469231731687303715884105727 2)",
+        "(quotient -170141183460469231731687303715884105728 -1)",
+        "(gcd -170141183460469231731687303715884105728 0)",
+    ] {
+        let result = interp_with_width(input, IntWidth::I128);
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(InterpError::IntegerOverflow)
+        ));
+    }
+    Ok(())
+}
+
+interp_testcase!(
+    "(* 100000 100000)",
+    "Int(10000000000)",
+    test_default_width_is_i64
+);
+
+interp_testcase!(
+    "(apply (lambda x (* x 2)) (list 5))",
+    "Int(10)",
+    test_apply_closure
+);
+interp_testcase!("(apply + (list 1 2 3))", "Int(6)", test_apply_primitive);
+interp_testcase!("(apply < (list 1 2))", "Bool(true)", test_apply_comparison);
+interp_error_testcase!(
+    "(apply (lambda x x) (list 1 2))",
+    InterpError::ArityMismatch {
+        expected: 1,
+        got: 2,
+        ..
+    },
+    test_apply_closure_arity
+);
+interp_error_testcase!(
+    "(apply / (list 1 2 3))",
+    InterpError::ArityMismatch {
+        expected: 2,
+        got: 3,
+        ..
+    },
+    test_apply_primitive_arity
+);
+interp_error_testcase!("(apply + 5)", InterpError::NotAList, test_apply_not_a_list);
+
+// Evaluates input parsed with source spans
+fn interp_spanned(input: &str) -> Result<interp::Value, InterpError> {
+    let mut parser = parse::Parser::default();
+    parser.track_spans = true;
+    let exp = parser.parse(input).expect("parse failed");
+    Interpreter::new(Box::new(io::sink())).interp(exp, &mut HashMap::new())
+}
+
+#[test]
+fn test_error_span() {
+    let result = interp_spanned("(begin 1 (* 2 (+ 1 true)))");
+    match result {
+        Err(InterpError::At { span, source }) => {
+            assert_eq!((span.start, span.end), (14, 24));
+            assert!(matches!(*source, InterpError::NotANumber));
+        }
+        other => panic!("expected a spanned error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_error_span_in_let_value() {
+    let result = interp_spanned("(let (x (/ 1 0)) x)");
+    match result {
+        Err(InterpError::At { span, .. }) => assert_eq!((span.start, span.end), (8, 15)),
+        other => panic!("expected a spanned error, got {:?}", other),
+    }
+}
+
+interp_testcase!("(char->int #\\A)", "Int(65)", test_char_to_int);
+interp_testcase!("(int->char 65)", "Char('A')", test_int_to_char);
+interp_testcase!(
+    "(int->char (char->int #\\λ))",
+    "Char('λ')",
+    test_char_round_trip
+);
+interp_error_testcase!(
+    "(int->char 55296)",
+    InterpError::ConversionOutOfRange,
+    test_int_to_char_surrogate
+);
+interp_error_testcase!(
+    "(int->char -1)",
+    InterpError::ConversionOutOfRange,
+    test_int_to_char_negative
+);
+interp_error_testcase!(
+    "(char->int 65)",
+    InterpError::NotAChar,
+    test_char_to_int_not_char
+);
+output_testcase!("(display #\\A)", "A\n", test_display_char);
+
+#[test]
+fn test_symbol_suggestion() {
+    let exp = parse::Exp::try_from("(let (fact 5) fack)").unwrap();
+    let err = interp::interp(exp, &mut HashMap::new()).unwrap_err();
+    assert!(matches!(&err, InterpError::SymbolNotFound(_, Some(s)) if s == "fact"));
+    assert_eq!(
+        err.to_string(),
+        "Cannot find symbol 'fack'; did you mean 'fact'?"
+    );
+}
+
+#[test]
+fn test_symbol_no_suggestion() {
+    let exp = parse::Exp::try_from("(let (fact 5) zebra)").unwrap();
+    let err = interp::interp(exp, &mut HashMap::new()).unwrap_err();
+    assert!(matches!(&err, InterpError::SymbolNotFound(_, None)));
+    assert_eq!(err.to_string(), "Cannot find symbol 'zebra'");
+}
+
+interp_testcase!(
+    "(if true (list 1) (list 2 3))",
+    "List(Int(1))",
+    test_if_list_lengths_differ
+);
+interp_testcase!(
+    "(if true (list) (list 2 3))",
+    "List()",
+    test_if_empty_list_branch
+);
+interp_error_testcase!(
+    "(if true (list 1) (list true))",
+    InterpError::BranchTypeMismatch,
+    test_if_list_element_mismatch
+);
+interp_testcase!(
+    "(deref (if true (ref 1) (ref 2)))",
+    "Int(1)",
+    test_if_ref_branches
+);
+interp_error_testcase!(
+    "(if true (ref 1) (ref false))",
+    InterpError::BranchTypeMismatch,
+    test_if_ref_pointee_mismatch
+);
+
+interp_testcase!(
+    "((lambda (x y) (- x y)) 5 3)",
+    "Int(2)",
+    test_multi_arg_lambda
+);
+interp_testcase!("(+ 1 2 3)", "Int(6)", test_variadic_primitive_app);
+interp_error_testcase!(
+    "((lambda (x y) x) 1 2 3)",
+    InterpError::ArityMismatch {
+        expected: 2,
+        got: 3,
+        ..
+    },
+    test_multi_arg_arity_mismatch
+);
+interp_testcase!(
+    "(((lambda (x y) (+ x y)) 3) 4)",
+    "Int(7)",
+    test_partial_application
+);
+interp_testcase!(
+    "(arity ((lambda (x y z) x) 1))",
+    "Int(2)",
+    test_partial_application_arity
+);
+interp_testcase!(
+    "(let (add3 (lambda (x y z) (+ x (+ y z)))) (((add3 1) 2) 3))",
+    "Int(6)",
+    test_partial_application_chained
+);
+interp_testcase!(
+    "((lambda (x y) (+ x y)) 3 4)",
+    "Int(7)",
+    test_full_application
+);
+interp_error_testcase!(
+    "((lambda (x y) x))",
+    InterpError::ArityMismatch {
+        expected: 2,
+        got: 0,
+        ..
+    },
+    test_application_no_args
+);
+interp_testcase!("(arity (lambda (x y) (+ x y)))", "Int(2)", test_arity_two);
+interp_testcase!("(arity (lambda x x))", "Int(1)", test_arity_one);
+interp_testcase!("(arity (memoize (lambda x x)))", "Int(1)", test_arity_memo);
+interp_testcase!("(arity -)", "Int(2)", test_arity_primitive);
+interp_error_testcase!(
+    "(arity 5)",
+    InterpError::NotAClosure,
+    test_arity_not_function
+);
+
+interp_error_testcase!(
+    "(let (b (box 1)) (set b true))",
+    InterpError::RefTypeMismatch,
+    test_set_different_type
+);
+
+output_testcase!("(display -0.0)", "-0\n", test_display_negative_zero);
+output_testcase!("(display 1e308)", "1e308\n", test_display_huge_float);
+output_testcase!("(display 5e-324)", "5e-324\n", test_display_subnormal);
+output_testcase!(
+    "(display 123456.25)",
+    "123456.25\n",
+    test_display_ordinary_float
+);
+
+interp_testcase!(
+    "(letrec ((even? (lambda n (if (= n 0) true (odd? (- n 1)))))
+              (odd? (lambda n (if (= n 0) false (even? (- n 1))))))
+       (even? 10))",
+    "Bool(true)",
+    test_letrec_mutual_recursion
+);
+interp_testcase!(
+    "(letrec ((fact (lambda n (if (= n 0) 1 (* n (fact (- n 1))))))) (fact 5))",
+    "Int(120)",
+    test_letrec_self_recursion
+);
+interp_testcase!(
+    "(let (unused (range 0 10 1)) (let (k 10) (letrec ((f (lambda n (if (= n 0) k (f (- n 1)))))) (f 3))))",
+    "Int(10)",
+    test_letrec_sees_outer_names
+);
+interp_testcase!(
+    "(let (k 10) (letrec ((f (lambda n (eval (read \"k\"))))) (f 0)))",
+    "Int(10)",
+    test_letrec_eval_sees_whole_env
+);
+output_testcase!(
+    "(if true (display 1) (display 2))",
+    "1\n",
+    test_if_only_runs_taken_branch
+);
+
+interp_testcase!("((lambda () 42))", "Int(42)", test_thunk_call);
+interp_testcase!(
+    "(let (f (lambda () (+ 1 2))) (f))",
+    "Int(3)",
+    test_thunk_bound
+);
+interp_error_testcase!(
+    "((lambda () 42) 1)",
+    InterpError::ArityMismatch {
+        expected: 0,
+        got: 1,
+        ..
+    },
+    test_thunk_given_argument
+);
+
+#[test]
+fn test_begin_frees_temporary_boxes() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(begin (box 1) (unbox (box 2)))")?;
+    let value = interpreter.interp(exp, &mut HashMap::new())?;
+    assert_eq!(format!("{:?}", value), "Int(2)");
+    assert_eq!(interpreter.live_count(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_begin_keeps_escaping_boxes() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(begin (box 1) (box (box 2)))")?;
+    let value = interpreter.interp(exp, &mut HashMap::new())?;
+    assert_eq!(interpreter.render(&value), "Box(Box(Int(2)))");
+    assert_eq!(interpreter.live_count(), 2);
+    Ok(())
+}
+
+interp_testcase!(
+    "(let (outer (box (box 0))) (begin (begin (set outer (box 5)) 0) (deref outer)))",
+    "Int(5)",
+    test_begin_keeps_boxes_stored_in_older_boxes
+);
+interp_testcase!(
+    "(let (v (make-vector 1 (box 0))) (begin (begin (vector-set! v 0 (box 5)) 0) (unbox (vector-ref v 0))))",
+    "Int(5)",
+    test_begin_keeps_boxes_stored_in_older_vectors
+);
+
+#[test]
+fn test_begin_without_boxes_keeps_older_boxes() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let mut env = HashMap::new();
+    let b = interpreter.interp(parse::Exp::try_from("(box 1)")?, &mut env)?;
+    env.insert("b".to_string(), b);
+    let exp = parse::Exp::try_from("(repeat 3 (begin (box 2) (unbox b)))")?;
+    let value = interpreter.interp(exp, &mut env)?;
+    assert_eq!(format!("{:?}", value), "Int(1)");
+    assert_eq!(interpreter.live_count(), 1);
+    Ok(())
+}
+
+// Evaluates input with IEEE float division enabled
+fn interp_ieee(input: &str) -> Result<interp::Value, Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.ieee_float_div = true;
+    let exp = parse::Exp::try_from(input)?;
+    Ok(interpreter.interp(exp, &mut HashMap::new())?)
+}
+
+#[test]
+fn test_ieee_float_div_by_zero() -> Result<(), Box<dyn Error>> {
+    assert!(matches!(interp_ieee("(/ 1.0 0.0)")?, interp::Value::Float(f) if f == f64::INFINITY));
+    assert!(
+        matches!(interp_ieee("(/ -1.0 0.0)")?, interp::Value::Float(f) if f == f64::NEG_INFINITY)
+    );
+    assert!(matches!(interp_ieee("(/ 0.0 0.0)")?, interp::Value::Float(f) if f.is_nan()));
+    assert!(
+        matches!(interp_ieee("(apply / (list 1.0 0.0))")?, interp::Value::Float(f) if f.is_infinite())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_ieee_int_div_by_zero() {
+    assert!(matches!(
+        interp_ieee("(/ 1 0)").map_err(|e| e.downcast::<InterpError>().map(|e| *e)),
+        Err(Ok(InterpError::DivisionByZero))
+    ));
+}
+
+// Evaluates input and returns everything traced functions logged
+fn interp_trace(input: &str) -> Result<String, Box<dyn Error>> {
+    let trace = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.trace_out = Box::new(trace.clone());
+    interpreter.interp(parse::Exp::try_from(input)?, &mut HashMap::new())?;
+    Ok(trace.contents())
+}
+
+#[test]
+fn test_trace_two_calls() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_trace("(let (double (lambda x (* x 2))) (let (f (trace double)) (+ (f 1) (f 2))))")?,
+        "call double(Int(1))\ndouble(Int(1)) => Int(2)\ncall double(Int(2))\ndouble(Int(2)) => Int(4)\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_trace_recursive_calls_indented() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_trace(
+            "(letrec ((fact (trace (lambda n (if (= n 0) 1 (* n (fact (- n 1)))))))) (fact 2))"
+        )?,
+        "call fact(Int(2))\n  call fact(Int(1))\n    call fact(Int(0))\n    fact(Int(0)) => Int(1)\n  fact(Int(1)) => Int(1)\nfact(Int(2)) => Int(2)\n"
+    );
+    Ok(())
+}
+
+interp_testcase!(
+    "((trace (lambda (x y) (- x y))) 5 3)",
+    "Int(2)",
+    test_trace_returns_result
+);
+interp_error_testcase!(
+    "(trace 5)",
+    InterpError::NotAClosure,
+    test_trace_not_function
+);
+
+#[test]
+fn test_error_codes_unique_and_stable() {
+    let errors = [
+        (
+            InterpError::NotImplemented("x".to_string()),
+            "E_NOT_IMPLEMENTED",
+        ),
+        (InterpError::NotANumber, "E_NOT_A_NUMBER"),
+        (InterpError::NotAnInteger, "E_NOT_AN_INTEGER"),
+        (
+            InterpError::IncompatibleTypes {
+                left: "Int",
+                right: "Float",
+            },
+            "E_INCOMPATIBLE_TYPES",
+        ),
+        (
+            InterpError::ConditionNotBoolean("Int"),
+            "E_CONDITION_NOT_BOOLEAN",
+        ),
+        (InterpError::BranchTypeMismatch, "E_BRANCH_TYPE_MISMATCH"),
+        (InterpError::IntegerOverflow, "E_INTEGER_OVERFLOW"),
+        (InterpError::DivisionByZero, "E_DIV_ZERO"),
+        (InterpError::CantDisplay, "E_CANT_DISPLAY"),
+        (
+            InterpError::SymbolNotFound("x".to_string(), None),
+            "E_SYMBOL_NOT_FOUND",
+        ),
+        (InterpError::NotAClosure, "E_NOT_A_CLOSURE"),
+        (InterpError::NotAPair, "E_NOT_A_PAIR"),
+        (InterpError::NotAChar, "E_NOT_A_CHAR"),
+        (
+            InterpError::ConversionOutOfRange,
+            "E_CONVERSION_OUT_OF_RANGE",
+        ),
+        (InterpError::NegativeWidth, "E_NEGATIVE_WIDTH"),
+        (InterpError::NegativeCount, "E_NEGATIVE_COUNT"),
+        (
+            InterpError::UnusedParameter("x".to_string()),
+            "E_UNUSED_PARAMETER",
+        ),
+        (InterpError::NotAList, "E_NOT_A_LIST"),
+        (InterpError::NotAString, "E_NOT_A_STRING"),
+        (InterpError::NotABool, "E_NOT_A_BOOL"),
+        (InterpError::NotCode, "E_NOT_CODE"),
+        (InterpError::ReadError("x".to_string()), "E_READ"),
+        (InterpError::NotBytes, "E_NOT_BYTES"),
+        (InterpError::EmptySeparator, "E_EMPTY_SEPARATOR"),
+        (
+            InterpError::ForwardReference("x".to_string()),
+            "E_FORWARD_REFERENCE",
+        ),
+        (InterpError::InvalidPlace("Int"), "E_INVALID_PLACE"),
+        (InterpError::ZeroStep, "E_ZERO_STEP"),
+        (InterpError::NegativePlaces, "E_NEGATIVE_PLACES"),
+        (InterpError::TooManyPlaces, "E_TOO_MANY_PLACES"),
+        (
+            InterpError::AssertionFailed {
+                actual: "1".to_string(),
+                expected: "2".to_string(),
+            },
+            "E_ASSERTION_FAILED",
+        ),
+        (
+            InterpError::ExpectedError("x".to_string()),
+            "E_EXPECTED_ERROR",
+        ),
+        (InterpError::Exit(0), "E_EXIT"),
+        (InterpError::NotAVector, "E_NOT_A_VECTOR"),
+        (InterpError::NegativeLength, "E_NEGATIVE_LENGTH"),
+        (InterpError::IoError("x".to_string()), "E_IO"),
+        (
+            InterpError::IndexOutOfBounds { index: 3, len: 3 },
+            "E_INDEX_OUT_OF_BOUNDS",
+        ),
+        (
+            InterpError::ArityMismatch {
+                func: "f".to_string(),
+                expected: 1,
+                at_least: false,
+                got: 2,
+            },
+            "E_ARITY_MISMATCH",
+        ),
+        (InterpError::NotABox, "E_NOT_A_BOX"),
+        (
+            InterpError::AnnotationMismatch(parse::Type::Int),
+            "E_ANNOTATION_MISMATCH",
+        ),
+        (
+            InterpError::UseAfterMove("x".to_string()),
+            "E_USE_AFTER_MOVE",
+        ),
+        (InterpError::RefTypeMismatch, "E_REF_TYPE_MISMATCH"),
+        (InterpError::RefCycleDetected, "E_REF_CYCLE"),
+        (InterpError::FreedBox, "E_FREED_BOX"),
+        (InterpError::HeapExhausted, "E_HEAP_EXHAUSTED"),
+        (InterpError::OutputError, "E_OUTPUT"),
+        (
+            InterpError::UnexpectedMultipleValues,
+            "E_UNEXPECTED_MULTIPLE_VALUES",
+        ),
+        (
+            InterpError::ValueCountMismatch {
+                expected: 1,
+                got: 2,
+            },
+            "E_VALUE_COUNT_MISMATCH",
+        ),
+    ];
+    let mut seen = std::collections::HashSet::new();
+    for (error, code) in &errors {
+        assert_eq!(error.code(), *code);
+        assert!(seen.insert(error.code()), "duplicate code {}", code);
+    }
+}
+
+#[test]
+fn test_spanned_error_code() {
+    let err = interp_spanned("(+ 1 (/ 1 0))").unwrap_err();
+    assert!(matches!(err, InterpError::At { .. }));
+    assert_eq!(err.code(), "E_DIV_ZERO");
+}
+
+#[test]
+fn test_restore_snapshot() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let mut env = HashMap::new();
+    let value = interpreter.interp(parse::Exp::try_from("(box 1)")?, &mut env)?;
+    env.insert("b".to_string(), value);
+    let snapshot = interpreter.snapshot(&env);
+    let result = interpreter.interp(
+        parse::Exp::try_from("(begin (set b 2) (+ (unbox b) true))")?,
+        &mut env,
+    );
+    assert!(result.is_err());
+    let read = parse::Exp::try_from("(unbox b)")?;
+    assert_eq!(
+        format!("{:?}", interpreter.interp(read.clone(), &mut env)?),
+        "Int(2)"
+    );
+    interpreter.restore(snapshot, &mut env);
+    assert_eq!(
+        format!("{:?}", interpreter.interp(read, &mut env)?),
+        "Int(1)"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_restore_drops_new_boxes() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let mut env = HashMap::new();
+    let snapshot = interpreter.snapshot(&env);
+    let value = interpreter.interp(parse::Exp::try_from("(box 1)")?, &mut env)?;
+    env.insert("b".to_string(), value);
+    assert_eq!(interpreter.live_count(), 1);
+    interpreter.restore(snapshot, &mut env);
+    assert_eq!(interpreter.live_count(), 0);
+    assert!(env.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_restore_keeps_new_boxes_freed() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let mut env = HashMap::new();
+    let snapshot = interpreter.snapshot(&env);
+    let b = interpreter.interp(parse::Exp::try_from("(box 1)")?, &mut env)?;
+    interpreter.restore(snapshot, &mut env);
+    env.insert("b".to_string(), b);
+    let result = interpreter.interp(parse::Exp::try_from("(unbox b)")?, &mut env);
+    assert!(matches!(result, Err(InterpError::FreedBox)));
+    assert_eq!(interpreter.render(&env["b"]), "Box(<freed>)");
+    Ok(())
+}
+
+#[test]
+fn test_undo_history() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let mut env = HashMap::new();
+    let mut history = interp::UndoHistory::new(10);
+    for (name, input) in [("a", "(box 1)"), ("b", "(box 2)")] {
+        history.record(&interpreter, &env);
+        let value = interpreter.interp(parse::Exp::try_from(input)?, &mut env)?;
+        env.insert(name.to_string(), value);
+    }
+    assert!(history.undo(&mut interpreter, &mut env));
+    assert!(env.contains_key("a") && !env.contains_key("b"));
+    assert_eq!(interpreter.live_count(), 1);
+    assert!(history.undo(&mut interpreter, &mut env));
+    assert!(env.is_empty());
+    assert!(!history.undo(&mut interpreter, &mut env));
+    Ok(())
+}
+
+#[test]
+fn test_undo_history_bounded() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let mut env = HashMap::new();
+    let mut history = interp::UndoHistory::new(1);
+    history.record(&interpreter, &env);
+    history.record(&interpreter, &env);
+    assert!(history.undo(&mut interpreter, &mut env));
+    assert!(!history.undo(&mut interpreter, &mut env));
+    Ok(())
+}
+
+fn parse_all(src: &str) -> Vec<parse::Exp> {
+    parse::parse_forms(src)
+        .collect::<Result<_, _>>()
+        .expect("parse failed")
+}
+
+#[test]
+fn test_eval_many_continues_after_error() {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    let forms = parse_all("(+ 1 2) (/ 1 0) (display 3)");
+    let results = interpreter.eval_many(forms, &mut HashMap::new(), false);
+    assert_eq!(results.len(), 3);
+    assert!(matches!(results[0], Ok(interp::Value::Int(3))));
+    assert!(matches!(results[1], Err(InterpError::DivisionByZero)));
+    assert!(matches!(results[2], Ok(interp::Value::Int(3))));
+    interpreter.flush().unwrap();
+    assert_eq!(output.contents(), "3\n");
+}
+
+#[test]
+fn test_eval_many_stops_on_error() {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    let forms = parse_all("(+ 1 2) (/ 1 0) (display 3)");
+    let results = interpreter.eval_many(forms, &mut HashMap::new(), true);
+    assert_eq!(results.len(), 2);
+    assert!(matches!(results[1], Err(InterpError::DivisionByZero)));
+    interpreter.flush().unwrap();
+    assert_eq!(output.contents(), "");
+}
+
+// Evaluates input with truthy conditions enabled
+fn interp_truthy(input: &str) -> Result<String, Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.truthy_conditions = true;
+    let exp = parse::Exp::try_from(input)?;
+    Ok(format!(
+        "{:?}",
+        interpreter.interp(exp, &mut HashMap::new())?
+    ))
+}
+
+#[test]
+fn test_truthy_conditions() -> Result<(), Box<dyn Error>> {
+    assert_eq!(interp_truthy("(if 0 1 2)")?, "Int(2)");
+    assert_eq!(interp_truthy("(if -3 1 2)")?, "Int(1)");
+    assert_eq!(interp_truthy("(if 0.0 1 2)")?, "Int(2)");
+    assert_eq!(interp_truthy("(if 0.5 1 2)")?, "Int(1)");
+    assert_eq!(interp_truthy(r#"(if "" 1 2)"#)?, "Int(2)");
+    assert_eq!(interp_truthy(r#"(if "a" 1 2)"#)?, "Int(1)");
+    assert_eq!(interp_truthy("(if (list) 1 2)")?, "Int(2)");
+    assert_eq!(interp_truthy("(if (list 0) 1 2)")?, "Int(1)");
+    assert_eq!(interp_truthy("(if nil 1 2)")?, "Int(2)");
+    assert_eq!(interp_truthy("(if (lambda x x) 1 2)")?, "Int(1)");
+    assert_eq!(interp_truthy("(if false 1 2)")?, "Int(2)");
+    Ok(())
+}
+
+interp_error_testcase!(
+    "(if 0 1 2)",
+    InterpError::ConditionNotBoolean(_),
+    test_strict_conditions_reject_int
+);
+interp_error_testcase!(
+    r#"(if "a" 1 2)"#,
+    InterpError::ConditionNotBoolean(_),
+    test_strict_conditions_reject_str
+);
+
+#[test]
+fn test_pi() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("(* 2.0 pi)")?;
+    match interp::interp(exp, &mut HashMap::new())? {
+        interp::Value::Float(f) => assert_eq!(f, std::f64::consts::TAU),
+        v => panic!("expected a float, got {:?}", v),
+    }
+    Ok(())
+}
+
+// Constants are Floats, and ints aren't converted to combine with them
+interp_error_testcase!(
+    "(* 2 pi)",
+    InterpError::IncompatibleTypes { .. },
+    test_pi_int_operand
+);
+
+interp_testcase!("(- 5)", "Int(-5)", test_unary_minus);
+interp_testcase!("(- 1.5)", "Float(-1.5)", test_unary_minus_float);
+interp_testcase!("(- 10 3)", "Int(7)", test_binary_minus);
+interp_testcase!("(- 10 3 2)", "Int(5)", test_minus_folds_left);
+interp_testcase!(
+    "(- 10.0 0.5 0.25)",
+    "Float(9.25)",
+    test_minus_folds_left_float
+);
+interp_error_testcase!(
+    "(- -9223372036854775808)",
+    InterpError::IntegerOverflow,
+    test_unary_minus_overflow
+);
+interp_error_testcase!("(- true)", InterpError::NotANumber, test_unary_minus_bool);
+interp_error_testcase!(
+    "(apply - (list))",
+    InterpError::ArityMismatch {
+        expected: 1,
+        got: 0,
+        ..
+    },
+    test_minus_no_operands
+);
+
+// Writes contents to a fresh temp file and returns its path as a string literal
+fn temp_file(name: &str, contents: &[u8]) -> String {
+    let path = std::env::temp_dir().join(format!("cmpsc-470-final-{}", name));
+    std::fs::write(&path, contents).unwrap();
+    format!("{:?}", path.to_str().unwrap())
+}
+
+#[test]
+fn test_read_bytes() -> Result<(), Box<dyn Error>> {
+    let path = temp_file("read-bytes", &[1, 2, 255]);
+    let exp = parse::Exp::try_from(format!("(read-bytes {})", path).as_str())?;
+    let value = interp::interp(exp, &mut HashMap::new())?;
+    assert_eq!(format!("{:?}", value), "Bytes([1, 2, 255])");
+    Ok(())
+}
+
+#[test]
+fn test_bytes_length_and_ref() -> Result<(), Box<dyn Error>> {
+    let path = temp_file("bytes-length", b"hello");
+    let src = format!(
+        "(let (b (read-bytes {})) (values (bytes-length b) (bytes-ref b 1)))",
+        path
+    );
+    let value = interp::interp(parse::Exp::try_from(src.as_str())?, &mut HashMap::new())?;
+    assert_eq!(format!("{:?}", value), "Values(Int(5), Int(101))");
+    Ok(())
+}
+
+#[test]
+fn test_bytes_ref_out_of_bounds() -> Result<(), Box<dyn Error>> {
+    let path = temp_file("bytes-ref", b"hi");
+    let src = format!("(bytes-ref (read-bytes {}) 2)", path);
+    let result = interp::interp(parse::Exp::try_from(src.as_str())?, &mut HashMap::new());
+    assert!(matches!(
+        result,
+        Err(InterpError::IndexOutOfBounds { index: 2, len: 2 })
+    ));
+    Ok(())
+}
+
+interp_error_testcase!(
+    r#"(read-bytes "/nonexistent/cmpsc-470-final")"#,
+    InterpError::IoError(_),
+    test_read_bytes_missing_file
+);
+interp_error_testcase!(
+    "(bytes-length (list 1 2))",
+    InterpError::NotBytes,
+    test_bytes_length_not_bytes
+);
+
+// Evaluates input reading from lines and returns the result and everything printed
+fn interp_with_input(input: &str, lines: &str) -> Result<(String, String), Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    interpreter.input = Box::new(io::Cursor::new(lines.to_string()));
+    let value = interpreter.interp(parse::Exp::try_from(input)?, &mut HashMap::new())?;
+    interpreter.flush()?;
+    Ok((format!("{:?}", value), output.contents()))
+}
+
+#[test]
+fn test_read_line_in_order() -> Result<(), Box<dyn Error>> {
+    let (value, _) = interp_with_input("(list (read-line) (read-line))", "first\nsecond\r\n")?;
+    assert_eq!(value, r#"List(Str("first"), Str("second"))"#);
+    Ok(())
+}
+
+#[test]
+fn test_read_line_eof() -> Result<(), Box<dyn Error>> {
+    let (value, _) = interp_with_input("(cons (read-line) (read-line))", "last")?;
+    assert_eq!(value, r#"Pair(Str("last"), Nil)"#);
+    Ok(())
+}
+
+#[test]
+fn test_echo() -> Result<(), Box<dyn Error>> {
+    let (_, output) = interp_with_input(
+        r#"(begin (write-string "> ") (write-string (read-line)))"#,
+        "hello\n",
+    )?;
+    assert_eq!(output, "> hello");
+    Ok(())
+}
+
+interp_error_testcase!(
+    "(write-string 5)",
+    InterpError::NotAString,
+    test_write_string_not_string
+);
+
+interp_testcase!(
+    "((lambda (. xs) (apply + xs)) 1 2 3)",
+    "Int(6)",
+    test_rest_params_sum
+);
+interp_testcase!(
+    "((lambda (. xs) (apply + xs)))",
+    "Int(0)",
+    test_rest_params_no_args
+);
+interp_testcase!(
+    "((lambda (x &rest r) r) 1 2 3)",
+    "List(Int(2), Int(3))",
+    test_rest_params_collect_extra
+);
+interp_testcase!("((lambda (x . r) r) 1)", "List()", test_rest_params_empty);
+interp_testcase!(
+    "(arity (lambda (x y . r) x))",
+    "Int(2)",
+    test_rest_params_arity
+);
+interp_error_testcase!(
+    "((lambda (x y . r) x) 1)",
+    InterpError::ArityMismatch {
+        expected: 2,
+        at_least: true,
+        got: 1,
+        ..
+    },
+    test_rest_params_too_few
+);
+
+#[test]
+fn test_rest_params_arity_message() -> Result<(), Box<dyn Error>> {
+    let message = interp_error_message("((lambda (x y . r) x) 1)")?;
+    assert_eq!(
+        message,
+        "'(lambda (x y . r) x)' expects at least 2 arguments but got 1"
+    );
+    Ok(())
+}
+
+interp_testcase!(
+    "`(1 ,(+ 1 1) 3)",
+    "List(Int(1), Int(2), Int(3))",
+    test_quasiquote
+);
+interp_testcase!(
+    "(let (x 5) (quasiquote (0 (unquote x))))",
+    "List(Int(0), Int(5))",
+    test_quasiquote_long_form
+);
+interp_testcase!(
+    "(let (x 5) `(1 (2 ,(* x 2)) ,(list x)))",
+    "List(Int(1), List(Int(2), Int(10)), List(Int(5)))",
+    test_quasiquote_nested_unquote
+);
+interp_testcase!("`,(+ 2 3)", "Int(5)", test_quasiquote_unquoted_atom);
+
+#[test]
+fn test_long_begin() -> Result<(), Box<dyn Error>> {
+    let src = format!(
+        "(let (b (box 0)) (begin {} (unbox b)))",
+        "(set b (+ (unbox b) 1)) ".repeat(10000)
+    );
+    let value = interp::interp(parse::Exp::try_from(src.as_str())?, &mut HashMap::new())?;
+    assert_eq!(format!("{:?}", value), "Int(10000)");
+    Ok(())
+}
+
+#[test]
+fn test_output_buffered_until_flush() -> Result<(), Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    let exp = parse::Exp::try_from("(begin (display 1) (display 2))")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    assert_eq!(output.contents(), "");
+    interpreter.flush()?;
+    assert_eq!(output.contents(), "1\n2\n");
+    Ok(())
+}
+
+#[test]
+fn test_flush_forces_output() -> Result<(), Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    let exp = parse::Exp::try_from("(begin (display 1) (flush) (display 2))")?;
+    assert!(matches!(
+        interpreter.interp(exp, &mut HashMap::new())?,
+        interp::Value::Int(2)
+    ));
+    assert_eq!(output.contents(), "1\n");
+    drop(interpreter);
+    assert_eq!(output.contents(), "1\n2\n");
+    Ok(())
+}
+
+interp_testcase!("(flush)", "Unit", test_flush_unit);
+
+interp_testcase!(
+    "(let (x 1) (begin (let (x 2) x) x))",
+    "Int(1)",
+    test_shadowing_restored_after_let
+);
+interp_testcase!(
+    "(let (x 1) (let (x 2) x))",
+    "Int(2)",
+    test_shadowing_inner_wins
+);
+interp_testcase!(
+    "(let (x 1) (let (x (+ x 10)) (let (x (* x 2)) x)))",
+    "Int(22)",
+    test_shadowing_nested_uses_outer
+);
+interp_testcase!(
+    "(let (x 1) (+ (let (x 2) (let (x 3) x)) x))",
+    "Int(4)",
+    test_shadowing_deep_then_outer
+);
+interp_testcase!(
+    "(let (x 1) (let (f (lambda y x)) (let (x 2) (f 0))))",
+    "Int(1)",
+    test_shadowing_closure_keeps_definition_scope
+);
+interp_testcase!(
+    "(let (x 1) (begin ((lambda x x) 5) x))",
+    "Int(1)",
+    test_shadowing_by_parameter
+);
+interp_testcase!(
+    "(let (x 1) (let-values ((x y) (values 2 3)) (+ x y)))",
+    "Int(5)",
+    test_shadowing_by_let_values
+);
+interp_testcase!(
+    "(let (x 1) (begin (let-values ((x y) (values 2 3)) x) x))",
+    "Int(1)",
+    test_shadowing_restored_after_let_values
+);
+interp_testcase!(
+    "(let (x 1) (begin (letrec ((x (lambda y y))) 0) x))",
+    "Int(1)",
+    test_shadowing_restored_after_letrec
+);
+
+#[test]
+fn test_arity_error_names_function() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("(let (inc (lambda x (+ x 1))) (inc 1 2))")?;
+    let err = interp::interp(exp, &mut HashMap::new()).unwrap_err();
+    assert!(matches!(&err, InterpError::ArityMismatch { func, .. } if func == "inc"));
+    assert_eq!(err.to_string(), "'inc' expects 1 arguments but got 2");
+    Ok(())
+}
+
+#[test]
+fn test_arity_error_names_lambda() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("((lambda (x y) x) 1 2 3)")?;
+    let err = interp::interp(exp, &mut HashMap::new()).unwrap_err();
+    assert!(matches!(&err, InterpError::ArityMismatch { func, .. } if func == "(lambda (x y) x)"));
+    Ok(())
+}
+
+#[test]
+fn test_arity_error_names_primitive() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("(apply / (list 1 2 3))")?;
+    let err = interp::interp(exp, &mut HashMap::new()).unwrap_err();
+    assert!(matches!(&err, InterpError::ArityMismatch { func, .. } if func == "/"));
+    Ok(())
+}
+
+#[test]
+fn test_arity_error_inside_body_names_inner_call() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("(let (g (lambda x x)) (let (f (lambda y (g y y))) (f 1)))")?;
+    let err = interp::interp(exp, &mut HashMap::new()).unwrap_err();
+    assert!(matches!(&err, InterpError::ArityMismatch { func, .. } if func == "g"));
+    Ok(())
+}
+
+interp_testcase!(
+    "(make-vector 3 0)",
+    "Vector(Int(0), Int(0), Int(0))",
+    test_make_vector
+);
+interp_testcase!("(make-vector 0 0)", "Vector()", test_make_vector_empty);
+interp_testcase!(
+    "(vector-ref (make-vector 2 7) 1)",
+    "Int(7)",
+    test_vector_ref
+);
+interp_testcase!(
+    "(let (v (make-vector 3 0)) (begin (vector-set! v 1 5) v))",
+    "Vector(Int(0), Int(5), Int(0))",
+    test_vector_set
+);
+interp_testcase!(
+    "(vector-set! (make-vector 1 0) 0 1)",
+    "Unit",
+    test_vector_set_returns_unit
+);
+interp_testcase!(
+    "(let (v (make-vector 2 0)) (let (w v) (begin (vector-set! w 0 9) (vector-ref v 0))))",
+    "Int(9)",
+    test_vector_set_shared
+);
+interp_testcase!(
+    "(let (v (make-vector 5 1)) (begin (repeat 4 (vector-set! v 0 (* (vector-ref v 0) 2))) v))",
+    "Vector(Int(16), Int(1), Int(1), Int(1), Int(1))",
+    test_vector_in_place_loop
+);
+interp_error_testcase!(
+    "(vector-ref (make-vector 2 0) 2)",
+    InterpError::IndexOutOfBounds { index: 2, len: 2 },
+    test_vector_ref_out_of_range
+);
+interp_error_testcase!(
+    "(vector-set! (make-vector 2 0) -1 5)",
+    InterpError::IndexOutOfBounds { index: -1, len: 2 },
+    test_vector_set_out_of_range
+);
+interp_testcase!(
+    "(let (v (make-vector 3 0)) (begin (set (vector-ref v 1) 5) v))",
+    "Vector(Int(0), Int(5), Int(0))",
+    test_set_vector_index
+);
+interp_testcase!(
+    "(set (vector-ref (make-vector 1 0) 0) 4)",
+    "Int(4)",
+    test_set_vector_index_returns_value
+);
+interp_error_testcase!(
+    "(set (vector-ref (make-vector 2 0) 2) 1)",
+    InterpError::IndexOutOfBounds { index: 2, len: 2 },
+    test_set_vector_index_out_of_range
+);
+interp_error_testcase!(
+    "(let (x 1) (set x 2))",
+    InterpError::InvalidPlace("Int"),
+    test_set_invalid_place
+);
+interp_error_testcase!(
+    "(make-vector -1 0)",
+    InterpError::NegativeLength,
+    test_make_vector_negative
+);
+interp_error_testcase!(
+    "(make-vector 100000000000000000 0)",
+    InterpError::HeapExhausted,
+    test_make_vector_too_large
+);
+interp_testcase!(
+    "(let (v (make-vector 1 0)) (begin (vector-set! v 0 v) 1))",
+    "Int(1)",
+    test_vector_containing_itself_dropped
+);
+interp_testcase!(
+    "(let (v (make-vector 2 0)) (begin (vector-set! v 0 v) v))",
+    "Vector(Vector(...), Int(0))",
+    test_vector_containing_itself_debug
+);
+
+#[test]
+fn test_vector_containing_itself_render() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp =
+        parse::Exp::try_from("(let (v (make-vector 1 0)) (begin (vector-set! v 0 (list v)) v))")?;
+    let v = interpreter.interp(exp, &mut HashMap::new())?;
+    assert_eq!(interpreter.render(&v), "Vector(List(Vector(...)))");
+    Ok(())
+}
+interp_error_testcase!(
+    "(vector-ref (list 1) 0)",
+    InterpError::NotAVector,
+    test_vector_ref_not_vector
+);
+interp_testcase!(
+    "(: (make-vector 1 0) Vector)",
+    "Vector(Int(0))",
+    test_vector_annotation
+);
+
+interp_testcase!(
+    "(if (< 5 3) 1 elif (< 3 5) 2 else 3)",
+    "Int(2)",
+    test_if_elif_middle_branch
+);
+interp_testcase!(
+    "(if false 1 elif false 2 else 3)",
+    "Int(3)",
+    test_if_elif_else_branch
+);
+
+interp_testcase!("(= 1.5 1.5)", "Bool(true)", test_eq_float);
+interp_testcase!("(= \"ab\" \"ab\")", "Bool(true)", test_eq_str);
+interp_testcase!("(= true false)", "Bool(false)", test_eq_bool);
+interp_testcase!("(= (list 1 2) (list 1 2))", "Bool(true)", test_eq_list);
+interp_testcase!(
+    "(= (list 1 2) (list 1))",
+    "Bool(false)",
+    test_eq_list_lengths
+);
+interp_testcase!(
+    "(let (b (box 1)) (= b b))",
+    "Bool(true)",
+    test_eq_box_identity
+);
+interp_testcase!("(= (box 1) (box 1))", "Bool(false)", test_eq_distinct_boxes);
+interp_testcase!("(= (ref 1) (ref 1))", "Bool(true)", test_eq_ref_pointee);
+interp_error_testcase!(
+    "(= 1 1.0)",
+    InterpError::IncompatibleTypes { .. },
+    test_eq_int_float
+);
+interp_error_testcase!(
+    "(= 1 \"1\")",
+    InterpError::IncompatibleTypes { .. },
+    test_eq_int_str
+);
+
+// if accepts two values as branches exactly when = can compare them
+#[test]
+fn test_if_and_eq_agree_on_types() -> Result<(), Box<dyn Error>> {
+    let values = [
+        "1",
+        "2.5",
+        "true",
+        "\"s\"",
+        "#\\a",
+        "nil",
+        "(cons 1 2)",
+        "(list 1 2)",
+        "(list true)",
+        "(list)",
+        "(ref 1)",
+        "(ref true)",
+        "(mut-ref 1)",
+        "(lambda x x)",
+        "(begin)",
+    ];
+    for a in values {
+        for b in values {
+            let if_ok = interp::interp(
+                parse::Exp::try_from(format!("(if true {} {})", a, b).as_str())?,
+                &mut HashMap::new(),
+            )
+            .is_ok();
+            let eq_ok = interp::interp(
+                parse::Exp::try_from(format!("(= {} {})", a, b).as_str())?,
+                &mut HashMap::new(),
+            )
+            .is_ok();
+            assert_eq!(if_ok, eq_ok, "{} and {}", a, b);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_profile_counts() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.profile = Some(interp::Profile::default());
+    let exp = parse::Exp::try_from(
+        "(letrec ((fact (lambda n (if (= n 0) 1 (* n (fact (- n 1))))))) (+ (fact 3) 1))",
+    )?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    let profile = interpreter.profile.as_ref().unwrap();
+    assert_eq!(profile.count("Add"), 1);
+    assert_eq!(profile.count("Mult"), 3);
+    assert_eq!(profile.count("Sub"), 3);
+    assert_eq!(profile.count("If"), 4);
+    assert_eq!(profile.count("Eq"), 4);
+    assert_eq!(profile.count("Div"), 0);
+    Ok(())
+}
+
+#[test]
+fn test_profile_report() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.profile = Some(interp::Profile::default());
+    let exp = parse::Exp::try_from("(+ (+ 1 2) (* 3 4))")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    let report = interpreter.profile.unwrap().report();
+    let mut lines = report.lines();
+    assert_eq!(
+        lines.next(),
+        Some(&format!("Int  4 {}", "#".repeat(40))[..])
+    );
+    assert_eq!(
+        lines.next(),
+        Some(&format!("Add  2 {}", "#".repeat(20))[..])
+    );
+    assert_eq!(
+        lines.next(),
+        Some(&format!("Mult 1 {}", "#".repeat(10))[..])
+    );
+    assert_eq!(lines.next(), None);
+    Ok(())
+}
+
+#[test]
+fn test_profile_off_by_default() {
+    assert!(Interpreter::new(Box::new(io::sink())).profile.is_none());
+}
+
+interp_testcase!(
+    "(string-split \"a,b,c\" \",\")",
+    r#"List(Str("a"), Str("b"), Str("c"))"#,
+    test_string_split
+);
+interp_testcase!(
+    "(string-split \",a,,b,\" \",\")",
+    r#"List(Str(""), Str("a"), Str(""), Str("b"), Str(""))"#,
+    test_string_split_empty_fields
+);
+interp_testcase!(
+    "(string-split \"abc\" \",\")",
+    r#"List(Str("abc"))"#,
+    test_string_split_no_match
+);
+interp_testcase!(
+    "(string-split \"a::b\" \"::\")",
+    r#"List(Str("a"), Str("b"))"#,
+    test_string_split_long_separator
+);
+interp_error_testcase!(
+    "(string-split \"abc\" \"\")",
+    InterpError::EmptySeparator,
+    test_string_split_empty_separator
+);
+interp_testcase!(
+    "(string-join (list \"a\" \"b\" \"c\") \", \")",
+    r#"Str("a, b, c")"#,
+    test_string_join
+);
+interp_testcase!(
+    "(string-join (list) \",\")",
+    r#"Str("")"#,
+    test_string_join_empty
+);
+interp_testcase!(
+    "(string-join (string-split \"a,b\" \",\") \",\")",
+    r#"Str("a,b")"#,
+    test_string_join_split_round_trip
+);
+interp_error_testcase!(
+    "(string-join (list \"a\" 1) \",\")",
+    InterpError::NotAString,
+    test_string_join_non_string
+);
+interp_error_testcase!(
+    "(string-join \"a\" \",\")",
+    InterpError::NotAList,
+    test_string_join_not_list
+);
+
+interp_error_testcase!(
+    "(< 1 2.0)",
+    InterpError::IncompatibleTypes { .. },
+    test_lt_int_float
+);
+interp_error_testcase!(
+    "(> 2.5 2)",
+    InterpError::IncompatibleTypes { .. },
+    test_gt_float_int
+);
+interp_error_testcase!(
+    "(<= 1 1.0)",
+    InterpError::IncompatibleTypes { .. },
+    test_le_int_float
+);
+interp_error_testcase!(
+    "(apply >= (list 2.0 1))",
+    InterpError::IncompatibleTypes { .. },
+    test_ge_primitive_float_int
+);
+interp_error_testcase!(
+    "(+ 1 2.0)",
+    InterpError::IncompatibleTypes { .. },
+    test_add_int_float_matches_comparison
+);
+interp_testcase!("(< 1.5 2.0)", "Bool(true)", test_lt_floats);
+interp_testcase!("(> 2.5 2.0)", "Bool(true)", test_gt_floats);
+
+interp_error_testcase!("(exit 2)", InterpError::Exit(2), test_exit);
+interp_error_testcase!(
+    "(begin (display 1) (exit (+ 1 1)) (display 2))",
+    InterpError::Exit(2),
+    test_exit_stops_program
+);
+interp_error_testcase!(
+    "(exit 4294967296)",
+    InterpError::ConversionOutOfRange,
+    test_exit_code_out_of_range
+);
+interp_error_testcase!("(exit true)", InterpError::NotAnInteger, test_exit_not_int);
+
+#[test]
+fn test_exit_not_wrapped_in_span() {
+    let err = interp_spanned("(begin (exit 1))").unwrap_err();
+    assert!(matches!(err, InterpError::Exit(1)));
+}
+
+// Evaluates input with lexical or dynamic scope
+fn interp_scoped(input: &str, dynamic_scope: bool) -> Result<String, Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.dynamic_scope = dynamic_scope;
+    let exp = parse::Exp::try_from(input)?;
+    Ok(format!(
+        "{:?}",
+        interpreter.interp(exp, &mut HashMap::new())?
+    ))
+}
+
+#[test]
+fn test_dynamic_scope_uses_caller_binding() -> Result<(), Box<dyn Error>> {
+    let src = "(let (x 1) (let (f (lambda y x)) (let (x 2) (f 0))))";
+    assert_eq!(interp_scoped(src, false)?, "Int(1)");
+    assert_eq!(interp_scoped(src, true)?, "Int(2)");
+    Ok(())
+}
+
+#[test]
+fn test_dynamic_scope_through_calls() -> Result<(), Box<dyn Error>> {
+    // g sees the n bound by whichever function called it
+    let src = "(let (n 0) (let (g (lambda y n)) (let (h (lambda n (g 0))) (h 5))))";
+    assert_eq!(interp_scoped(src, false)?, "Int(0)");
+    assert_eq!(interp_scoped(src, true)?, "Int(5)");
+    Ok(())
+}
+
+#[test]
+fn test_dynamic_scope_unbound_at_call() {
+    let src = "((let (x 1) (lambda y x)) 0)";
+    assert_eq!(interp_scoped(src, false).unwrap(), "Int(1)");
+    assert!(interp_scoped(src, true).is_err());
+}
+
+#[test]
+fn test_lexical_scope_by_default() {
+    assert!(!Interpreter::new(Box::new(io::sink())).dynamic_scope);
+}
+
+#[test]
+fn test_closure_captures_only_free_vars() -> Result<(), Box<dyn Error>> {
+    let exp =
+        parse::Exp::try_from("(let (a 1) (let (b 2) (let (c 3) (lambda (x y) (+ x (+ b c))))))")?;
+    let interp::Value::Closure { env, .. } = interp::interp(exp, &mut HashMap::new())? else {
+        panic!("expected a closure");
+    };
+    let mut names: Vec<&String> = env.keys().collect();
+    names.sort();
+    assert_eq!(names, vec!["b", "c"]);
+    Ok(())
+}
+
+#[test]
+fn test_closure_captures_nothing_when_closed() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("(let (a 1) (lambda x (* x x)))")?;
+    let interp::Value::Closure { env, .. } = interp::interp(exp, &mut HashMap::new())? else {
+        panic!("expected a closure");
+    };
+    assert!(env.is_empty());
+    Ok(())
+}
+
+interp_testcase!("(assert-eq (+ 1 2) 3)", "Unit", test_assert_eq_passes);
+interp_testcase!(
+    "(assert-eq (list 1 2) (list 1 2))",
+    "Unit",
+    test_assert_eq_lists
+);
+
+#[test]
+fn test_assert_eq_fails_showing_values() -> Result<(), Box<dyn Error>> {
+    let exp = parse::Exp::try_from("(assert-eq (+ 1 2) 4)")?;
+    let err = interp::interp(exp, &mut HashMap::new()).unwrap_err();
+    assert!(matches!(&err, InterpError::AssertionFailed { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Assertion failed: expected Int(4) but got Int(3)"
+    );
+    Ok(())
+}
+
+interp_error_testcase!(
+    "(assert-eq 1 \"1\")",
+    InterpError::AssertionFailed { .. },
+    test_assert_eq_different_types
+);
+interp_testcase!("(assert-error (/ 1 0))", "Unit", test_assert_error_catches);
+interp_error_testcase!(
+    "(assert-error (+ 1 2))",
+    InterpError::ExpectedError(_),
+    test_assert_error_no_error
+);
+interp_error_testcase!(
+    "(assert-error (exit 3))",
+    InterpError::Exit(3),
+    test_assert_error_lets_exit_through
+);
+
+interp_testcase!(
+    "(range 0 5)",
+    "List(Int(0), Int(1), Int(2), Int(3), Int(4))",
+    test_range
+);
+interp_testcase!("(range 5 5)", "List()", test_range_empty);
+interp_testcase!("(range 5 0)", "List()", test_range_start_after_end);
+interp_testcase!(
+    "(range 0 10 3)",
+    "List(Int(0), Int(3), Int(6), Int(9))",
+    test_range_step
+);
+interp_testcase!(
+    "(range 5 0 -2)",
+    "List(Int(5), Int(3), Int(1))",
+    test_range_negative_step
+);
+interp_error_testcase!("(range 0 5 0)", InterpError::ZeroStep, test_range_zero_step);
+interp_error_testcase!(
+    "(range 0 1.5)",
+    InterpError::NotAnInteger,
+    test_range_not_an_integer
+);
+
+interp_testcase!("(if #t 1 0)", "Int(1)", test_if_scheme_true);
+interp_testcase!("(if #f 1 0)", "Int(0)", test_if_scheme_false);
+output_testcase!("(display #t)", "true\n", test_display_bool_default);
+
+#[test]
+fn test_display_scheme_bools() -> Result<(), Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    interpreter.scheme_bools = true;
+    let exp = parse::Exp::try_from("(begin (display true) (display (= 1 2)))")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    interpreter.flush()?;
+    assert_eq!(output.contents(), "#t\n#f\n");
+    Ok(())
+}
+
+#[test]
+fn test_value_from_rust() {
+    assert_eq!(format!("{:?}", Value::from(5)), "Int(5)");
+    assert_eq!(format!("{:?}", Value::from(2.5)), "Float(2.5)");
+    assert_eq!(format!("{:?}", Value::from(true)), "Bool(true)");
+    assert_eq!(
+        format!("{:?}", Value::from("hi".to_string())),
+        r#"Str("hi")"#
+    );
+}
+
+#[test]
+fn test_value_into_rust() -> Result<(), Box<dyn Error>> {
+    assert_eq!(i64::try_from(Value::Int(5))?, 5);
+    assert_eq!(f64::try_from(Value::Float(2.5))?, 2.5);
+    assert!(bool::try_from(Value::Bool(true))?);
+    assert_eq!(String::try_from(Value::Str("hi".to_string()))?, "hi");
+    let value = interp::interp(parse::Exp::try_from("(+ 2 3)")?, &mut HashMap::new())?;
+    assert_eq!(i64::try_from(value)?, 5);
+    Ok(())
+}
+
+#[test]
+fn test_value_into_rust_mismatch() {
+    assert!(matches!(
+        i64::try_from(Value::Float(1.0)),
+        Err(InterpError::NotAnInteger)
+    ));
+    assert!(matches!(
+        f64::try_from(Value::Int(1)),
+        Err(InterpError::NotANumber)
+    ));
+    assert!(matches!(
+        bool::try_from(Value::Int(1)),
+        Err(InterpError::NotABool)
+    ));
+    assert!(matches!(
+        String::try_from(Value::Char('a')),
+        Err(InterpError::NotAString)
+    ));
+}
+
+interp_testcase!(
+    "(begin (define h (lambda x (* x 2))) (h 5))",
+    "Int(10)",
+    test_define_in_begin
+);
+interp_testcase!(
+    "(begin (define a 1) (define b (+ a 1)) (+ a b))",
+    "Int(3)",
+    test_define_uses_earlier_define
+);
+interp_testcase!(
+    "(let (x 1) (begin (begin (define x 2) x) x))",
+    "Int(1)",
+    test_define_shadows_only_inside_begin
+);
+interp_testcase!("(begin (define x 1))", "Unit", test_define_resolves_to_unit);
+interp_error_testcase!(
+    "(begin (begin (define h 1) h) h)",
+    InterpError::SymbolNotFound(..),
+    test_define_invisible_outside_begin
+);
+interp_testcase!(
+    "((lambda x (begin (define y (* x x)) (+ y 1))) 3)",
+    "Int(10)",
+    test_define_in_lambda_body
+);
+
+// Warnings raised by checking input for exponential recursion
+fn recursion_warnings(input: &str) -> Result<Vec<WarningKind>, Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    interpreter.recursion_hints = true;
+    interpreter.check_recursion(&parse::Exp::try_from(input)?);
+    Ok(interpreter
+        .warnings()
+        .iter()
+        .map(|w| w.kind.clone())
+        .collect())
+}
+
+#[test]
+fn test_recursion_hint_double_recursion() -> Result<(), Box<dyn Error>> {
+    let warnings = recursion_warnings(
+        "(letrec ((fib (lambda n (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))) (fib 10))",
+    )?;
+    assert_eq!(warnings, vec![WarningKind::ExponentialRecursion]);
+    Ok(())
+}
+
+#[test]
+fn test_recursion_hint_tail_recursion() -> Result<(), Box<dyn Error>> {
+    let warnings = recursion_warnings(
+        "(letrec ((total (lambda (n acc) (if (= n 0) acc (total (- n 1) (+ acc n)))))) (total 10 0))",
+    )?;
+    assert!(warnings.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_recursion_hint_one_call_per_branch() -> Result<(), Box<dyn Error>> {
+    let warnings =
+        recursion_warnings("(letrec ((f (lambda n (if (< n 2) (f 0) (f (- n 1)))))) (f 3))")?;
+    assert!(warnings.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_recursion_hints_off_by_default() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(letrec ((f (lambda n (+ (f n) (f n))))) 0)")?;
+    interpreter.check_recursion(&exp);
+    assert!(interpreter.warnings().is_empty());
+    Ok(())
+}
+
+interp_testcase!("(eval (read \"(+ 1 2)\"))", "Int(3)", test_read_then_eval);
+interp_testcase!(
+    "(read \"(+ 1 2)\")",
+    "Code(Add(Int(1), Int(2)))",
+    test_read_does_not_evaluate
+);
+interp_testcase!(
+    "(let (x 4) (eval (read \"(* x x)\")))",
+    "Int(16)",
+    test_eval_in_current_env
+);
+interp_testcase!(
+    "(let (x 4) ((lambda y (eval (read \"(* x y)\"))) 2))",
+    "Int(8)",
+    test_eval_in_closure_sees_lexical_vars
+);
+interp_error_testcase!(
+    "(begin (eval (read \"(define z 1)\")) z)",
+    InterpError::SymbolNotFound(..),
+    test_eval_define_stays_inside
+);
+interp_error_testcase!(
+    "(read \"(+ 1\")",
+    InterpError::ReadError(_),
+    test_read_parse_error
+);
+interp_error_testcase!("(eval 5)", InterpError::NotCode, test_eval_not_code);
+
+output_testcase!("(and false (display 1))", "", test_and_short_circuits);
+output_testcase!("(or true (display 1))", "", test_or_short_circuits);
+output_testcase!("(and true (display 1))", "1\n", test_and_evaluates_rest);
+output_testcase!("(or false (display 1))", "1\n", test_or_evaluates_rest);
+output_testcase!(
+    "(and (begin (display 1) true) (begin (display 2) false) (display 3))",
+    "1\n2\n",
+    test_and_evaluates_in_order
+);
+interp_testcase!("(and true (+ 1 2))", "Int(3)", test_and_result);
+interp_testcase!("(and true false true)", "Bool(false)", test_and_false);
+interp_testcase!("(or false (< 1 2))", "Bool(true)", test_or_result);
+interp_testcase!("(and)", "Bool(true)", test_and_empty);
+interp_testcase!("(or)", "Bool(false)", test_or_empty);
+interp_error_testcase!(
+    "(and 1 2)",
+    InterpError::ConditionNotBoolean(_),
+    test_and_non_bool_condition
+);
+
+interp_testcase!(
+    "(let (f (lambda x (+ x 1))) (function->string f))",
+    r#"Str("(lambda x (+ x 1))")"#,
+    test_function_to_string
+);
+interp_testcase!(
+    "(let (y 2) (function->string (lambda (x z) (* x y))))",
+    r#"Str("(lambda (x z) (* x y))")"#,
+    test_function_to_string_omits_env
+);
+interp_testcase!(
+    "(letrec ((f (lambda n (f n)))) (function->string f))",
+    r#"Str("(lambda n (f n))")"#,
+    test_function_to_string_letrec
+);
+interp_testcase!(
+    "(function->string (memoize (lambda x x)))",
+    r#"Str("(lambda x x)")"#,
+    test_function_to_string_memoized
+);
+interp_testcase!(
+    "((eval (read (function->string (lambda x (* x 3))))) 4)",
+    "Int(12)",
+    test_function_to_string_reads_back
+);
+interp_error_testcase!(
+    "(function->string 5)",
+    InterpError::NotAClosure,
+    test_function_to_string_not_a_function
+);
+
+interp_testcase!(
+    "(let (b (box 1)) (get-and-set b 2))",
+    "Int(1)",
+    test_get_and_set_returns_old
+);
+interp_testcase!(
+    "(let (b (box 1)) (begin (get-and-set b 2) (unbox b)))",
+    "Int(2)",
+    test_get_and_set_stores_new
+);
+interp_testcase!(
+    "(let (b (box 1)) (+ (get-and-set b 5) (get-and-set b 7)))",
+    "Int(6)",
+    test_get_and_set_twice
+);
+interp_error_testcase!(
+    "(let (b (box 1)) (get-and-set b true))",
+    InterpError::RefTypeMismatch,
+    test_get_and_set_type_mismatch
+);
+interp_error_testcase!(
+    "(get-and-set 1 2)",
+    InterpError::NotABox,
+    test_get_and_set_not_a_box
+);
+
+interp_testcase!("(round-to 3.14159 2)", "Float(3.14)", test_round_to_down);
+interp_testcase!("(round-to 2.71828 3)", "Float(2.718)", test_round_to_up);
+interp_testcase!("(round-to 2.5 0)", "Float(3)", test_round_to_zero_places);
+interp_testcase!("(round-to -1.25 1)", "Float(-1.3)", test_round_to_negative);
+interp_testcase!("(round-to 7 2)", "Float(7)", test_round_to_int);
+interp_testcase!(
+    "(round-to 0.5 400)",
+    "Float(0.5)",
+    test_round_to_many_places
+);
+interp_error_testcase!(
+    "(round-to 3.14 -1)",
+    InterpError::NegativePlaces,
+    test_round_to_negative_places
+);
+interp_error_testcase!(
+    "(round-to 3.14 -10000000000)",
+    InterpError::NegativePlaces,
+    test_round_to_hugely_negative_places
+);
+interp_error_testcase!(
+    "(round-to \"3.14\" 1)",
+    InterpError::NotANumber,
+    test_round_to_not_a_number
+);
+interp_error_testcase!(
+    "(round-to 3.14 1.5)",
+    InterpError::NotAnInteger,
+    test_round_to_places_not_an_integer
+);
+
+// Output of evaluating input as a top-level form with trace_begin set
+fn interp_traced_begin(input: &str) -> Result<String, Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    interpreter.trace_begin = true;
+    interpreter.interp_top(parse::Exp::try_from(input)?, &mut HashMap::new())?;
+    interpreter.flush()?;
+    Ok(output.contents())
+}
+
+#[test]
+fn test_trace_begin_prints_each_step() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_traced_begin("(begin (+ 1 2) (display 5) (* 2 3))")?,
+        "(+ 1 2) => Int(3)\n5\n(display 5) => Int(5)\n(* 2 3) => Int(6)\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_trace_begin_only_outermost() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_traced_begin("(begin (+ (begin 1 2) 1) 3)")?,
+        "(+ (begin 1 2) 1) => Int(3)\n3 => Int(3)\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_trace_begin_ignores_other_forms() -> Result<(), Box<dyn Error>> {
+    assert_eq!(interp_traced_begin("(+ 1 (begin 2 3))")?, "");
+    Ok(())
+}
+
+#[test]
+fn test_trace_begin_off_by_default() -> Result<(), Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    interpreter.interp_top(parse::Exp::try_from("(begin 1 2)")?, &mut HashMap::new())?;
+    interpreter.flush()?;
+    assert_eq!(output.contents(), "");
+    Ok(())
+}
+
+interp_testcase!("(not true)", "Bool(false)", test_not_true);
+interp_testcase!("(not false)", "Bool(true)", test_not_false);
+interp_testcase!("(not (< 1 2))", "Bool(false)", test_not_comparison);
+interp_testcase!("(not (> 3 2))", "Bool(false)", test_not_greater);
+interp_testcase!("(if (not (= 1 2)) 1 0)", "Int(1)", test_not_condition);
+interp_error_testcase!("(not 5)", InterpError::NotABool, test_not_non_bool);
+
+#[test]
+fn test_not_non_bool_with_truthy_conditions() -> Result<(), Box<dyn Error>> {
+    assert!(matches!(
+        interp_truthy("(not 5)"),
+        Err(e) if matches!(e.downcast_ref(), Some(InterpError::NotABool))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_lists_nested_calls() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from(
+        "(letrec ((f (lambda n (if (= n 0) (/ 1 n) (+ 1 (f (- n 1))))))) (+ 1 (f 2)))",
+    )?;
+    let result = interpreter.interp_top(exp, &mut HashMap::new());
+    assert!(matches!(result, Err(InterpError::DivisionByZero)));
+    assert_eq!(
+        interpreter.backtrace(),
+        ["(f Int(2))", "(f Int(1))", "(f Int(0))"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_tail_call_replaces_frame() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from(
+        "(letrec ((f (lambda n (if (= n 0) (/ 1 n) (f (- n 1)))))) (+ 1 (f 2)))",
+    )?;
+    let result = interpreter.interp_top(exp, &mut HashMap::new());
+    assert!(matches!(result, Err(InterpError::DivisionByZero)));
+    assert_eq!(interpreter.backtrace(), ["(f Int(0))"]);
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_begin_last_form_replaces_frame() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from(
+        "(letrec ((f (lambda n (begin (+ n 1) (if (= n 0) (/ 1 n) (f (- n 1))))))) (+ 1 (f 2)))",
+    )?;
+    let result = interpreter.interp_top(exp, &mut HashMap::new());
+    assert!(matches!(result, Err(InterpError::DivisionByZero)));
+    assert_eq!(interpreter.backtrace(), ["(f Int(0))"]);
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_shows_rest_arguments() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(letrec ((f (lambda (x . r) (/ x 0)))) (f 1 2 3))")?;
+    assert!(interpreter.interp_top(exp, &mut HashMap::new()).is_err());
+    assert_eq!(interpreter.backtrace(), ["(f Int(1) Int(2) Int(3))"]);
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_names_anonymous_functions() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("((lambda x (car x)) 5)")?;
+    assert!(interpreter.interp_top(exp, &mut HashMap::new()).is_err());
+    assert_eq!(interpreter.backtrace(), ["((lambda x (car x)) Int(5))"]);
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_empty_outside_calls() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(/ 1 0)")?;
+    assert!(interpreter.interp_top(exp, &mut HashMap::new()).is_err());
+    assert!(interpreter.backtrace().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_cleared_by_caught_error() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let exp = parse::Exp::try_from("(begin (assert-error ((lambda x (/ x 0)) 1)) (/ 1 0))")?;
+    assert!(interpreter.interp_top(exp, &mut HashMap::new()).is_err());
+    assert!(interpreter.backtrace().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_cleared_by_each_evaluation() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let mut env = HashMap::new();
+    let failing =
+        |name: &str| parse::Exp::try_from(format!("((lambda {} (/ 1 0)) 1)", name).as_str());
+    assert!(interpreter.interp_top(failing("a")?, &mut env).is_err());
+    assert!(interpreter.interp(failing("b")?, &mut env).is_err());
+    assert_eq!(interpreter.backtrace(), ["((lambda b (/ 1 0)) Int(1))"]);
+    assert!(interpreter.interp_values(failing("c")?, &mut env).is_err());
+    assert_eq!(interpreter.backtrace(), ["((lambda c (/ 1 0)) Int(1))"]);
+    interpreter.eval_many(vec![failing("d")?], &mut env, true);
+    assert_eq!(interpreter.backtrace(), ["((lambda d (/ 1 0)) Int(1))"]);
+    interpreter.interp(parse::Exp::try_from("1")?, &mut env)?;
+    assert!(interpreter.backtrace().is_empty());
+    Ok(())
+}
+
+interp_testcase!(
+    "(contains? (list 1 2 3) 2)",
+    "Bool(true)",
+    test_contains_present
+);
+interp_testcase!(
+    "(contains? (list 1 2 3) 4)",
+    "Bool(false)",
+    test_contains_absent
+);
+interp_testcase!("(contains? (list) 1)", "Bool(false)", test_contains_empty);
+interp_testcase!(
+    "(contains? (list (list 1 2) (list 3)) (list 3))",
+    "Bool(true)",
+    test_contains_nested_list
+);
+interp_testcase!(
+    "(contains? (list (list 1 2)) (list 1 3))",
+    "Bool(false)",
+    test_contains_nested_list_absent
+);
+interp_testcase!(
+    "(contains? (list 1 2) \"1\")",
+    "Bool(false)",
+    test_contains_other_type
+);
+interp_testcase!(
+    "(= (list (list 1) (list 2 3)) (list (list 1) (list 2 3)))",
+    "Bool(true)",
+    test_nested_list_equality
+);
+interp_error_testcase!(
+    "(contains? 5 5)",
+    InterpError::NotAList,
+    test_contains_not_a_list
+);
+
+output_testcase!(
+    "(display (lambda x x))",
+    "#<procedure>\n",
+    test_display_closure
+);
+output_testcase!(
+    "(display (memoize (lambda x x)))",
+    "#<procedure>\n",
+    test_display_memoized_closure
+);
+output_testcase!(
+    "(letrec ((f (lambda n (f n)))) (display f))",
+    "#<procedure>\n",
+    test_display_letrec_function
+);
+output_testcase!(
+    "(display (list (lambda x x)))",
+    "List(#<procedure>)\n",
+    test_display_closure_in_list
+);
+output_testcase!(
+    "(display (cons 1 (lambda x x)))",
+    "Pair(Int(1), #<procedure>)\n",
+    test_display_closure_in_pair
+);
+output_testcase!(
+    "(debug (lambda x x))",
+    "Closure(params: [\"x\"], body: Id(x), env: {}",
+    test_debug_closure_shows_details
+);
+
+#[test]
+fn test_display_closure_custom_placeholder() -> Result<(), Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    interpreter.procedure_display = "<fn>".to_string();
+    let exp = parse::Exp::try_from("(display (lambda x x))")?;
+    interpreter.interp(exp, &mut HashMap::new())?;
+    interpreter.flush()?;
+    assert_eq!(output.contents(), "<fn>\n");
+    Ok(())
+}
+
+interp_error_testcase!(
+    "(begin (define a (+ b 1)) (define b 2) a)",
+    InterpError::ForwardReference(_),
+    test_define_forward_reference
+);
+interp_error_testcase!(
+    "(begin (define f (lambda n (g n))) (define g (lambda n n)) (f 1))",
+    InterpError::ForwardReference(_),
+    test_define_forward_reference_in_lambda
+);
+interp_error_testcase!(
+    "(begin (define f (lambda n (f n))) 0)",
+    InterpError::ForwardReference(_),
+    test_define_self_reference
+);
+output_testcase!(
+    "(begin (assert-error (begin (display 1) (define a b) (define b 2) a)) (display 2))",
+    "2\n",
+    test_forward_reference_reported_before_evaluating
+);
+interp_testcase!(
+    "(letrec ((f (lambda n (g n))) (g (lambda n (* n 2)))) (f 4))",
+    "Int(8)",
+    test_letrec_forward_reference_allowed
+);
+interp_testcase!(
+    "(let (b 1) (begin (define a (+ b 1)) (define b 10) (+ a b)))",
+    "Int(12)",
+    test_define_outer_binding_not_forward_reference
+);
+interp_testcase!(
+    "((lambda y (let (b 1) (begin (define a (+ b 1)) (define b 10) (+ a b)))) 0)",
+    "Int(12)",
+    test_define_outer_binding_not_forward_reference_in_lambda
+);
+interp_testcase!(
+    "(let (x 1) ((lambda y (begin (define x (+ x 1)) x)) 0))",
+    "Int(2)",
+    test_define_from_outer_binding_in_lambda
+);
+
+// Message of the error raised by evaluating input
+fn interp_error_message(input: &str) -> Result<String, Box<dyn Error>> {
+    let exp = parse::Exp::try_from(input)?;
+    Ok(interp::interp(exp, &mut HashMap::new())
+        .unwrap_err()
+        .to_string())
+}
+
+#[test]
+fn test_incompatible_types_named_for_add() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_error_message("(+ 1 2.0)")?,
+        "Cannot perform operation on incompatible types Int and Float"
+    );
+    assert_eq!(
+        interp_error_message("(+ \"a\" 1)")?,
+        "Cannot perform operation on incompatible types Str and Int"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_incompatible_types_named_for_div() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_error_message("(/ 1.0 2)")?,
+        "Cannot perform operation on incompatible types Float and Int"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_incompatible_types_named_for_comparison() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_error_message("(< 1 2.0)")?,
+        "Cannot perform operation on incompatible types Int and Float"
+    );
+    assert!(matches!(
+        interp::interp(parse::Exp::try_from("(= true 1)")?, &mut HashMap::new()),
+        Err(InterpError::IncompatibleTypes {
+            left: "Bool",
+            right: "Int"
+        })
+    ));
+    Ok(())
+}
+
+interp_testcase!("(sum (list 1 2 3))", "Int(6)", test_sum);
+interp_testcase!("(sum (list 1.5 2.5))", "Float(4)", test_sum_floats);
+interp_testcase!("(product (list 2 3 4))", "Int(24)", test_product);
+interp_testcase!("(product (list 0.5 4.0))", "Float(2)", test_product_floats);
+interp_testcase!("(sum (list))", "Int(0)", test_sum_empty);
+interp_testcase!("(product (list))", "Int(1)", test_product_empty);
+interp_testcase!("(sum (range 0 101))", "Int(5050)", test_sum_range);
+interp_error_testcase!(
+    "(sum (list 1 \"2\"))",
+    InterpError::NotANumber,
+    test_sum_non_numeric
+);
+interp_error_testcase!(
+    "(product (list \"a\"))",
+    InterpError::NotANumber,
+    test_product_non_numeric_only
+);
+interp_error_testcase!(
+    "(sum (list 1 2.0))",
+    InterpError::IncompatibleTypes { .. },
+    test_sum_mixed_types
+);
+interp_error_testcase!(
+    "(product (list 9223372036854775807 2))",
+    InterpError::IntegerOverflow,
+    test_product_overflow
+);
+interp_error_testcase!("(sum 5)", InterpError::NotAList, test_sum_not_a_list);
+
+// Result of evaluating input with display_returns_unit set, with what it printed
+fn interp_display_unit(input: &str) -> Result<(String, String), Box<dyn Error>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(Box::new(output.clone()));
+    interpreter.display_returns_unit = true;
+    let value = interpreter.interp(parse::Exp::try_from(input)?, &mut HashMap::new())?;
+    interpreter.flush()?;
+    Ok((format!("{:?}", value), output.contents()))
+}
+
+interp_testcase!(
+    "(display 2)",
+    "Int(2)",
+    test_display_returns_value_by_default
+);
+interp_testcase!(
+    "(+ 1 (display 2))",
+    "Int(3)",
+    test_displayed_value_usable_by_default
+);
+
+#[test]
+fn test_display_returns_unit() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_display_unit("(display 2)")?,
+        ("Unit".to_string(), "2\n".to_string())
+    );
+    assert_eq!(interp_display_unit("(debug 2)")?.0, "Unit");
+    assert_eq!(interp_display_unit("(display-hex 255)")?.0, "Unit");
+    assert_eq!(interp_display_unit("(write-string \"a\")")?.0, "Unit");
+    Ok(())
+}
+
+#[test]
+fn test_displayed_value_unusable_when_unit() {
+    assert!(matches!(
+        interp_display_unit("(+ 1 (display 2))"),
+        Err(e) if matches!(e.downcast_ref(), Some(InterpError::NotANumber))
+    ));
+}
+
+#[test]
+fn test_condition_not_boolean_names_type() -> Result<(), Box<dyn Error>> {
+    assert_eq!(
+        interp_error_message("(if 5 1 2)")?,
+        "Condition must be a boolean, got Int"
+    );
+    assert_eq!(
+        interp_error_message("(if \"yes\" 1 2)")?,
+        "Condition must be a boolean, got Str"
+    );
+    assert_eq!(
+        interp_error_message("(if (list 1) 1 2)")?,
+        "Condition must be a boolean, got List"
+    );
+    assert_eq!(
+        interp_error_message("(and 1.5 true)")?,
+        "Condition must be a boolean, got Float"
+    );
+    assert_eq!(
+        interp_error_message("(or (lambda x x) true)")?,
+        "Condition must be a boolean, got Fn"
+    );
+    Ok(())
+}
+
+interp_testcase!(
+    "(number->string 42)",
+    r#"Str("42")"#,
+    test_number_to_string_int
+);
+interp_testcase!(
+    "(number->string 42 3)",
+    r#"Str("42")"#,
+    test_number_to_string_int_ignores_precision
+);
+interp_testcase!(
+    "(number->string 3.14159 3)",
+    r#"Str("3.142")"#,
+    test_number_to_string_precision
+);
+interp_testcase!(
+    "(number->string 2.5 0)",
+    r#"Str("2")"#,
+    test_number_to_string_zero_precision
+);
+interp_testcase!(
+    "(number->string 1.5 3)",
+    r#"Str("1.500")"#,
+    test_number_to_string_pads_precision
+);
+interp_testcase!(
+    "(number->string 3.14159)",
+    r#"Str("3.14159")"#,
+    test_number_to_string_float
+);
+interp_testcase!(
+    "(number->string 2.0)",
+    r#"Str("2")"#,
+    test_number_to_string_float_like_display
+);
+interp_error_testcase!(
+    "(number->string 1.5 -1)",
+    InterpError::NegativePlaces,
+    test_number_to_string_negative_precision
+);
+interp_error_testcase!(
+    "(number->string 1.5 70000)",
+    InterpError::TooManyPlaces,
+    test_number_to_string_precision_too_large
+);
+interp_error_testcase!(
+    "(number->string \"1\")",
+    InterpError::NotANumber,
+    test_number_to_string_not_a_number
+);
+
+interp_testcase!(
+    "(= (lambda x x) (lambda x x))",
+    "Bool(true)",
+    test_closures_equal
+);
+interp_testcase!(
+    "(let (y 1) (= (lambda x (+ x y)) (lambda x (+ x y))))",
+    "Bool(true)",
+    test_closures_equal_same_capture
+);
+interp_testcase!(
+    "(= (lambda x (+ x 1)) (lambda x (+ x 2)))",
+    "Bool(false)",
+    test_closures_differing_bodies
+);
+interp_testcase!(
+    "(= (let (y 1) (lambda x (+ x y))) (let (y 2) (lambda x (+ x y))))",
+    "Bool(false)",
+    test_closures_differing_captures
+);
+interp_testcase!(
+    "(= (lambda x x) (lambda y y))",
+    "Bool(false)",
+    test_closures_differing_param_names
+);
+
+#[test]
+fn test_closures_equal_from_different_places() -> Result<(), Box<dyn Error>> {
+    let mut parser = parse::Parser::default();
+    parser.track_spans = true;
+    let exp = parser.parse("(= (lambda x (+ x 1)) (lambda x (+ x 1)))")?;
+    let value = interp::interp(exp, &mut HashMap::new())?;
+    assert_eq!(format!("{:?}", value), "Bool(true)");
+    Ok(())
+}
+
+interp_testcase!(
+    "(letrec ((even? (lambda n (if (= n 0) true (odd? (- n 1))))) \
+              (odd? (lambda n (if (= n 0) false (even? (- n 1)))))) \
+       (even? 1000000))",
+    "Bool(true)",
+    test_deep_mutual_tail_recursion
+);
+interp_testcase!(
+    "(letrec ((count (lambda (n acc) (if (= n 0) acc (begin (- n 1) (count (- n 1) (+ acc 1))))))) \
+       (count 100000 0))",
+    "Int(100000)",
+    test_deep_tail_recursion_through_begin
+);
+// A call's result type isn't evident from its form, so the branches aren't compared
+interp_testcase!(
+    "(letrec ((f (lambda n (if (= n 0) 5 (f (- n 1))))) \
+              (g (lambda n (if (= n 0) true (f n))))) \
+       (g 3))",
+    "Int(5)",
+    test_tail_call_branch_not_checked
+);
+interp_testcase!(
+    "(letrec ((f (lambda (n l) (if (= n 0) l (f (- n 1) l))))) \
+       (nth (f 10000 (range 0 100 1)) 99))",
+    "Int(99)",
+    test_tail_call_list_branch_check_stays_small
+);
+interp_error_testcase!(
+    "(if true 1 (box 2))",
+    InterpError::BranchTypeMismatch,
+    test_if_evident_branch_types_checked
+);
+interp_error_testcase!(
+    "(if false (list 1 x) (list y true))",
+    InterpError::BranchTypeMismatch,
+    test_if_list_branch_checked_by_evident_elements
+);
+// The untaken branch isn't evaluated, so it can't fail
+interp_testcase!(
+    "(if true 1 ((lambda x x) false))",
+    "Int(1)",
+    test_if_untaken_call_not_made
+);
+interp_testcase!(
+    "(if true 1 undefined-name)",
+    "Int(1)",
+    test_if_untaken_branch_not_evaluated
+);
+
+#[test]
+fn test_undo_box_allocation() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new(Box::new(io::sink()));
+    let mut env = HashMap::new();
+    let mut history = interp::UndoHistory::new(10);
+    // Inputs as the REPL would evaluate them, recording history before each
+    for input in ["(define m (memoize (lambda x (box x))))", "(m 5)"] {
+        history.record(&interpreter, &env);
+        interpreter.eval_many(parse_all(input), &mut env, true);
+    }
+    assert!(history.undo(&mut interpreter, &mut env));
+    let result = interpreter.eval_many(parse_all("(unbox (m 5))"), &mut env, true);
+    assert!(matches!(&result[..], [Err(InterpError::FreedBox)]));
+    Ok(())
+}
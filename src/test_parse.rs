@@ -55,6 +55,283 @@ parse_testcase!(
     test_if
 );
 
+parse_testcase!(r#""hello""#, Str("hello".to_string()), test_str);
+parse_testcase!(
+    r#""a\tb\n\"c\"""#,
+    Str("a\tb\n\"c\"".to_string()),
+    test_str_escapes
+);
+parse_testcase!("hello", Id("hello".to_string()), test_symbol_not_str);
+
+#[test]
+fn test_str_unknown_escape() {
+    assert!(matches!(
+        parse::Exp::try_from(r#""a\qb""#),
+        Err(parse::ParseError::SexpError(_))
+    ));
+}
+
+#[test]
+fn debug_str_test() {
+    let exp = Str("a\tb".to_string());
+    assert_eq!(format!("{:?}", exp), r#"Str("a\tb")"#);
+}
+
+parse_testcase!(
+    "(display-hex 255)",
+    DisplayHex(Box::new(Int(255))),
+    test_display_hex
+);
+parse_testcase!(
+    "(time (+ 1 2))",
+    Time(Box::new(Add {
+        lhs: Box::new(Int(1)),
+        rhs: Box::new(Int(2)),
+    })),
+    test_time
+);
+parse_testcase!("nil", Nil, test_nil);
+parse_testcase!(
+    "(cons 1 (cons 2 nil))",
+    Cons {
+        car: Box::new(Int(1)),
+        cdr: Box::new(Cons {
+            car: Box::new(Int(2)),
+            cdr: Box::new(Nil),
+        }),
+    },
+    test_cons
+);
+parse_testcase!("(pair? nil)", IsPair(Box::new(Nil)), test_is_pair);
+parse_testcase!(
+    "(null? x)",
+    IsNull(Box::new(Id("x".to_string()))),
+    test_is_null
+);
+parse_testcase!("(car x)", Car(Box::new(Id("x".to_string()))), test_car);
+parse_testcase!("(cdr x)", Cdr(Box::new(Id("x".to_string()))), test_cdr);
+
+parse_testcase!(
+    "(let-values ((q r) (divmod 7 2)) q)",
+    LetValues {
+        names: vec!["q".to_string(), "r".to_string()],
+        value: Box::new(DivMod {
+            lhs: Box::new(Int(7)),
+            rhs: Box::new(Int(2)),
+        }),
+        body: Box::new(Id("q".to_string())),
+    },
+    test_let_values
+);
+
+parse_testcase!(
+    "(format-width x 5)",
+    FormatWidth {
+        value: Box::new(Id("x".to_string())),
+        width: Box::new(Int(5)),
+    },
+    test_format_width
+);
+
+parse_testcase!(
+    "(: (+ 1 2) Int)",
+    Ann {
+        exp: Box::new(Add {
+            lhs: Box::new(Int(1)),
+            rhs: Box::new(Int(2)),
+        }),
+        ty: parse::Type::Int,
+    },
+    test_annotation
+);
+
+#[test]
+fn test_annotation_unknown_type() {
+    assert!(matches!(
+        parse::Exp::try_from("(: 1 Integer)"),
+        Err(parse::ParseError::UnknownType(t)) if t == "Integer"
+    ));
+}
+
+#[test]
+fn test_annotation_round_trip() -> Result<(), parse::ParseError> {
+    assert_round_trip("(: (list 1 2) List)")
+}
+
+parse_testcase!(
+    r#"(string-ref "abc" 1)"#,
+    StringRef {
+        string: Box::new(Str("abc".to_string())),
+        index: Box::new(Int(1)),
+    },
+    test_string_ref
+);
+
+parse_testcase!(
+    "(nth xs 1)",
+    Nth {
+        list: Box::new(Id("xs".to_string())),
+        index: Box::new(Int(1)),
+    },
+    test_nth
+);
+
+parse_testcase!(
+    "(repeat 3 x)",
+    Repeat {
+        count: Box::new(Int(3)),
+        body: Box::new(Id("x".to_string())),
+    },
+    test_repeat
+);
+
+parse_testcase!(
+    "(quotient 7 2)",
+    Quotient {
+        lhs: Box::new(Int(7)),
+        rhs: Box::new(Int(2)),
+    },
+    test_quotient
+);
+parse_testcase!(
+    "(remainder 7 2)",
+    Remainder {
+        lhs: Box::new(Int(7)),
+        rhs: Box::new(Int(2)),
+    },
+    test_remainder
+);
+parse_testcase!(
+    "(gcd 12 18)",
+    Gcd {
+        lhs: Box::new(Int(12)),
+        rhs: Box::new(Int(18)),
+    },
+    test_gcd
+);
+parse_testcase!(
+    "(lcm 4 6)",
+    Lcm {
+        lhs: Box::new(Int(4)),
+        rhs: Box::new(Int(6)),
+    },
+    test_lcm
+);
+
+parse_testcase!(
+    "(begin (define-syntax (twice x) (begin x x)) (twice (display 1)))",
+    Begin(vec![Display(Box::new(Int(1))), Display(Box::new(Int(1)))]),
+    test_macro_expansion
+);
+
+#[test]
+fn test_macro_arity() {
+    assert!(matches!(
+        parse::Exp::try_from("(begin (define-syntax (twice x) (begin x x)) (twice 1 2))"),
+        Err(parse::ParseError::MacroArity(_, 1))
+    ));
+}
+
+#[test]
+fn test_macro_misplaced() {
+    assert!(matches!(
+        parse::Exp::try_from("(+ 1 (define-syntax (twice x) (begin x x)))"),
+        Err(parse::ParseError::MisplacedMacroDefinition)
+    ));
+}
+
+#[test]
+fn test_define_misplaced() {
+    for src in [
+        "(let (x 1) (begin (begin (if true (define x 2) (define x 3)) 0) x))",
+        "(+ 1 (define x 2))",
+        "(let (x 1) (define x 2))",
+        "(define x (define y 1))",
+    ] {
+        assert!(
+            matches!(
+                parse::Exp::try_from(src),
+                Err(parse::ParseError::MisplacedDefinition)
+            ),
+            "{}",
+            src
+        );
+    }
+}
+
+#[test]
+fn test_define_allowed_in_begin_and_top_level() -> Result<(), parse::ParseError> {
+    parse::Exp::try_from("(define x 1)")?;
+    parse::Exp::try_from("(lambda y (begin (define x y) (begin (define z x) z)))")?;
+    Ok(())
+}
+
+#[test]
+fn test_let_values_malformed() {
+    assert!(matches!(
+        parse::Exp::try_from("(let-values ((q 1) (divmod 7 2)) q)"),
+        Err(parse::ParseError::MalformedValuesAssignment)
+    ));
+}
+
+// Parses with keywords matched case-insensitively
+fn parse_lenient(input: &str) -> Result<parse::Exp, parse::ParseError> {
+    let mut parser = parse::Parser::default();
+    parser.case_insensitive_keywords = true;
+    parser.parse(input)
+}
+
+#[test]
+fn test_lenient_keywords() -> Result<(), parse::ParseError> {
+    assert_eq!(
+        parse_lenient("(IF true 1 2)")?,
+        If {
+            cond: Box::new(Bool(true)),
+            lhs: Box::new(Int(1)),
+            rhs: Box::new(Int(2)),
+        }
+    );
+    assert_eq!(
+        parse_lenient("(BEGIN (Display 1))")?,
+        Begin(vec![Display(Box::new(Int(1)))])
+    );
+    Ok(())
+}
+
+#[test]
+fn test_lenient_identifiers_keep_case() -> Result<(), parse::ParseError> {
+    assert_eq!(
+        parse_lenient("(Lambda X (F X))")?,
+        Lambda {
+            params: vec!["X".to_string()],
+            rest: None,
+            body: Box::new(App {
+                func: Box::new(Id("F".to_string())),
+                args: vec![Id("X".to_string())],
+            }),
+        }
+    );
+    Ok(())
+}
+
+// Without lenient keywords IF is an ordinary identifier being applied
+parse_testcase!(
+    "(IF true 1 2)",
+    App {
+        func: Box::new(Id("IF".to_string())),
+        args: vec![Bool(true), Int(1), Int(2)],
+    },
+    test_strict_keywords
+);
+
+parse_testcase!(
+    "(Display x)",
+    App {
+        func: Box::new(Id("Display".to_string())),
+        args: vec![Id("x".to_string())],
+    },
+    test_strict_keyword_is_app
+);
 
 #[test]
 fn debug_id_test() {
@@ -101,7 +378,8 @@ fn debug_lambda_test() {
     let symbol = String::from("Word");
     let body = Int(8);
     let lambda_exp = Lambda {
-        arg: symbol,
+        params: vec![symbol],
+        rest: None,
         body: Box::new(body),
     };
 
@@ -206,8 +484,602 @@ fn debug_app_test() {
     let arg = Int(5);
     let app_exp = App {
         func: Box::new(func),
-        arg: Box::new(arg),
+        args: vec![arg],
     };
 
     assert_eq!(format!("{:?}", app_exp), "App(Id(func), Int(5))");
 }
+
+parse_testcase!("(gc)", Gc, test_gc);
+
+// Checks that printing an expression and parsing it again gives back the same expression
+fn assert_round_trip(input: &str) -> Result<(), parse::ParseError> {
+    let exp = parse::Exp::try_from(input)?;
+    assert_eq!(parse::Exp::try_from(exp.to_string().as_str())?, exp);
+    Ok(())
+}
+
+#[test]
+fn test_float_round_trip() -> Result<(), parse::ParseError> {
+    assert_round_trip("0.1")?;
+    assert_round_trip("1e-10")?;
+    assert_round_trip("1234567.89")?;
+    assert_round_trip("1.0")?;
+    assert_eq!(Float(0.1).to_string(), "0.1");
+    assert_eq!(Float(1.0).to_string(), "1.0");
+    Ok(())
+}
+
+#[test]
+fn test_display_round_trip() -> Result<(), parse::ParseError> {
+    assert_round_trip(r#"(begin (display "a\t\"b\"") (let-values ((q r) (divmod 7 2)) q))"#)?;
+    assert_round_trip("((lambda x (if (<= x 1.5) (cons x nil) (box x))) 2.5)")?;
+    Ok(())
+}
+
+parse_testcase!(
+    "(list 1 x)",
+    List(vec![Int(1), Id("x".to_string())]),
+    test_list
+);
+
+#[test]
+fn test_show_ast() {
+    assert_eq!(crate::show_ast("(box 5)"), "Box(Int(5))");
+    assert!(crate::show_ast("(box 5").starts_with("Parse error:"));
+    assert_eq!(
+        crate::show_ast("(define-syntax (twice x) (+ x x)) (twice 1) (box 5)"),
+        "Add(Int(1), Int(1))\nBox(Int(5))"
+    );
+}
+
+parse_testcase!(
+    "(apply + (list 1 2))",
+    Apply {
+        func: Box::new(Id("+".to_string())),
+        args_list: Box::new(List(vec![Int(1), Int(2)])),
+    },
+    test_apply
+);
+
+#[test]
+fn test_spans() -> Result<(), parse::ParseError> {
+    let mut parser = parse::Parser::default();
+    parser.track_spans = true;
+    let exp = parser.parse("(+ 1 (* 2 3))")?;
+    assert_eq!(
+        format!("{:?}", exp),
+        "Spanned(0..13, Add(Int(1), Spanned(5..12, Mult(Int(2), Int(3)))))"
+    );
+    assert_eq!(exp.to_string(), "(+ 1 (* 2 3))");
+    Ok(())
+}
+
+parse_testcase!("#\\A", Char('A'), test_char);
+parse_testcase!("#\\space", Char(' '), test_char_space);
+parse_testcase!(
+    "(char->int #\\newline)",
+    CharToInt(Box::new(Char('\n'))),
+    test_char_to_int
+);
+
+#[test]
+fn test_unknown_char() {
+    assert!(matches!(
+        parse::Exp::try_from("#\\tab"),
+        Err(parse::ParseError::UnknownCharacter(_))
+    ));
+}
+
+#[test]
+fn test_parse_forms() -> Result<(), parse::ParseError> {
+    let forms =
+        parse::parse_forms("1 (+ 1 2)\n; comment\n \"three\" ").collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        forms,
+        vec![
+            Int(1),
+            Add {
+                lhs: Box::new(Int(1)),
+                rhs: Box::new(Int(2)),
+            },
+            Str("three".to_string()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_parse_forms_error_mid_stream() {
+    let mut forms = parse::parse_forms("1 (+ 2 3)) 4");
+    assert_eq!(forms.next().unwrap().unwrap(), Int(1));
+    assert!(forms.next().unwrap().is_ok());
+    assert!(matches!(
+        forms.next(),
+        Some(Err(parse::ParseError::SexpError(_)))
+    ));
+    assert!(forms.next().is_none());
+}
+
+#[test]
+fn test_parse_forms_top_level_macro() -> Result<(), parse::ParseError> {
+    let forms = parse::parse_forms("(define-syntax (twice x) (+ x x)) (twice 2)")
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        forms,
+        vec![Add {
+            lhs: Box::new(Int(2)),
+            rhs: Box::new(Int(2)),
+        }]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_session_macro_persists_between_inputs() {
+    let mut interpreter = crate::interp::Interpreter::default();
+    let mut parser = crate::parser();
+    let mut env = std::collections::HashMap::new();
+    let inputs = ["(define-syntax (twice x) (+ x x))", "(define y (twice 3))"];
+    for input in inputs {
+        assert_eq!(
+            crate::run(&mut interpreter, &mut parser, &mut env, input),
+            None
+        );
+    }
+    assert!(matches!(env.get("y"), Some(crate::interp::Value::Int(6))));
+}
+
+parse_testcase!(
+    "(lambda (x y) (f x y))",
+    Lambda {
+        params: vec!["x".to_string(), "y".to_string()],
+        rest: None,
+        body: Box::new(App {
+            func: Box::new(Id("f".to_string())),
+            args: vec![Id("x".to_string()), Id("y".to_string())],
+        }),
+    },
+    test_multi_param_lambda
+);
+
+#[test]
+fn test_malformed_keyword_not_app() {
+    assert!(matches!(
+        parse::Exp::try_from("(if true)"),
+        Err(parse::ParseError::ParseError)
+    ));
+    assert!(matches!(
+        parse::Exp::try_from("(lambda (x 1) x)"),
+        Err(parse::ParseError::MalformedParameters)
+    ));
+}
+
+#[test]
+fn debug_multi_arg_test() -> Result<(), parse::ParseError> {
+    assert_eq!(
+        format!("{:?}", parse::Exp::try_from("(lambda (x y) (f x y))")?),
+        "Lambda([x, y], App(Id(f), Id(x), Id(y)))"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_letrec_requires_lambdas() {
+    assert!(matches!(
+        parse::Exp::try_from("(letrec ((x 1)) x)"),
+        Err(parse::ParseError::MalformedLetRec)
+    ));
+}
+
+parse_testcase!(
+    "(lambda () 5)",
+    Lambda {
+        params: vec![],
+        rest: None,
+        body: Box::new(Int(5)),
+    },
+    test_thunk_lambda
+);
+parse_testcase!(
+    "(f)",
+    App {
+        func: Box::new(Id("f".to_string())),
+        args: vec![],
+    },
+    test_thunk_call
+);
+
+#[test]
+fn test_thunk_round_trip() -> Result<(), parse::ParseError> {
+    assert_round_trip("((lambda () 42))")
+}
+
+#[test]
+fn test_free_vars() -> Result<(), parse::ParseError> {
+    let exp = parse::Exp::try_from("(lambda x (+ x (let-values ((q r) (divmod y 2)) (+ q z))))")?;
+    let mut vars: Vec<String> = exp.free_vars().into_iter().collect();
+    vars.sort();
+    assert_eq!(vars, vec!["y".to_string(), "z".to_string()]);
+    Ok(())
+}
+
+#[test]
+fn test_trace_letrec_round_trip() -> Result<(), parse::ParseError> {
+    assert_round_trip("(letrec ((f (trace (lambda x (f x))))) (trace f))")
+}
+
+parse_testcase!("pi", Float(std::f64::consts::PI), test_pi);
+parse_testcase!("e", Float(std::f64::consts::E), test_e);
+parse_testcase!("inf", Float(f64::INFINITY), test_inf);
+parse_testcase!("-inf", Float(f64::NEG_INFINITY), test_neg_inf);
+
+#[test]
+fn test_nan() {
+    assert!(matches!(parse::Exp::try_from("nan"), Ok(Float(f)) if f.is_nan()));
+}
+
+#[test]
+fn test_nan_round_trip() -> Result<(), parse::ParseError> {
+    let exp = parse::Exp::try_from("nan")?;
+    assert_eq!(exp.to_string(), "nan");
+    Ok(())
+}
+
+parse_testcase!(
+    "epsilon",
+    Id("epsilon".to_string()),
+    test_constant_prefix_is_id
+);
+
+#[test]
+fn test_constant_cannot_be_bound() {
+    assert!(matches!(
+        parse::Exp::try_from("(let (e 5) e)"),
+        Err(parse::ParseError::ReservedName(name)) if name == "e"
+    ));
+    assert!(matches!(
+        parse::Exp::try_from("(lambda (x pi) x)"),
+        Err(parse::ParseError::ReservedName(name)) if name == "pi"
+    ));
+}
+
+parse_testcase!(
+    "(- 5)",
+    App {
+        func: Box::new(Id("-".to_string())),
+        args: vec![Int(5)],
+    },
+    test_unary_minus
+);
+parse_testcase!(
+    "(- 10 3)",
+    Sub {
+        lhs: Box::new(Int(10)),
+        rhs: Box::new(Int(3)),
+    },
+    test_binary_minus
+);
+parse_testcase!(
+    "(- 10 3 2)",
+    App {
+        func: Box::new(Id("-".to_string())),
+        args: vec![Int(10), Int(3), Int(2)],
+    },
+    test_minus_three_operands
+);
+
+parse_testcase!(
+    "(lambda (x . rest) x)",
+    Lambda {
+        params: vec!["x".to_string()],
+        rest: Some("rest".to_string()),
+        body: Box::new(Id("x".to_string())),
+    },
+    test_lambda_dotted_rest
+);
+parse_testcase!(
+    "(lambda (&rest xs) xs)",
+    Lambda {
+        params: vec![],
+        rest: Some("xs".to_string()),
+        body: Box::new(Id("xs".to_string())),
+    },
+    test_lambda_ampersand_rest
+);
+
+#[test]
+fn test_lambda_malformed_rest() {
+    assert!(matches!(
+        parse::Exp::try_from("(lambda (x . a b) x)"),
+        Err(parse::ParseError::MalformedParameters)
+    ));
+    assert!(matches!(
+        parse::Exp::try_from("(lambda (x .) x)"),
+        Err(parse::ParseError::MalformedParameters)
+    ));
+}
+
+#[test]
+fn test_lambda_rest_round_trip() -> Result<(), parse::ParseError> {
+    assert_round_trip("(lambda (x &rest r) r)")?;
+    assert_round_trip("(lambda (. r) r)")
+}
+
+parse_testcase!(
+    "(quasiquote (1 (unquote (+ 1 1)) 3))",
+    Quasiquote(Box::new(List(vec![
+        Int(1),
+        Unquote(Box::new(Add {
+            lhs: Box::new(Int(1)),
+            rhs: Box::new(Int(1)),
+        })),
+        Int(3),
+    ]))),
+    test_quasiquote
+);
+parse_testcase!(
+    "`(1 (2 ,x) ())",
+    Quasiquote(Box::new(List(vec![
+        Int(1),
+        List(vec![Int(2), Unquote(Box::new(Id("x".to_string())))]),
+        List(vec![]),
+    ]))),
+    test_quasiquote_reader_syntax
+);
+
+#[test]
+fn test_quasiquote_errors() {
+    assert!(matches!(
+        parse::Exp::try_from("(unquote x)"),
+        Err(parse::ParseError::MisplacedUnquote)
+    ));
+    assert!(matches!(
+        parse::Exp::try_from("`(1 x)"),
+        Err(parse::ParseError::QuotedSymbol(name)) if name == "x"
+    ));
+}
+
+#[test]
+fn test_quasiquote_round_trip() -> Result<(), parse::ParseError> {
+    assert_round_trip("`(1 (2 ,(+ x 1)) \"s\" ,y)")
+}
+
+parse_testcase!(
+    "(vector-set! v 0 1)",
+    VectorSet {
+        vector: Box::new(Id("v".to_string())),
+        index: Box::new(Int(0)),
+        value: Box::new(Int(1)),
+    },
+    test_vector_set
+);
+
+#[test]
+fn test_vector_round_trip() -> Result<(), parse::ParseError> {
+    assert_round_trip("(vector-set! (make-vector 3 0) 1 (vector-ref v 2))")
+}
+
+parse_testcase!(
+    "(if a 1 elif b 2 else 3)",
+    If {
+        cond: Box::new(Id("a".to_string())),
+        lhs: Box::new(Int(1)),
+        rhs: Box::new(If {
+            cond: Box::new(Id("b".to_string())),
+            lhs: Box::new(Int(2)),
+            rhs: Box::new(Int(3)),
+        }),
+    },
+    test_if_elif_else
+);
+parse_testcase!(
+    "(if a 1 elif b 2 elif c 3 else 4)",
+    If {
+        cond: Box::new(Id("a".to_string())),
+        lhs: Box::new(Int(1)),
+        rhs: Box::new(If {
+            cond: Box::new(Id("b".to_string())),
+            lhs: Box::new(Int(2)),
+            rhs: Box::new(If {
+                cond: Box::new(Id("c".to_string())),
+                lhs: Box::new(Int(3)),
+                rhs: Box::new(Int(4)),
+            }),
+        }),
+    },
+    test_if_elif_chain
+);
+parse_testcase!(
+    "(if a 1 else 2)",
+    If {
+        cond: Box::new(Id("a".to_string())),
+        lhs: Box::new(Int(1)),
+        rhs: Box::new(Int(2)),
+    },
+    test_if_else_keyword
+);
+
+#[test]
+fn test_if_chain_malformed() {
+    for src in [
+        "(if a 1 elif b 2)",
+        "(if a 1 elif b 2 3)",
+        "(if a 1 otherwise 2)",
+    ] {
+        assert!(matches!(
+            parse::Exp::try_from(src),
+            Err(parse::ParseError::MalformedIf)
+        ));
+    }
+}
+
+#[test]
+fn test_kind() -> Result<(), parse::ParseError> {
+    assert_eq!(parse::Exp::try_from("(+ 1 2)")?.kind(), "Add");
+    assert_eq!(parse::Exp::try_from("(gc)")?.kind(), "Gc");
+    assert_eq!(parse::Exp::try_from("x")?.kind(), "Id");
+    Ok(())
+}
+
+parse_testcase!(
+    "(string-split s \",\")",
+    StringSplit {
+        string: Box::new(Id("s".to_string())),
+        sep: Box::new(Str(",".to_string())),
+    },
+    test_string_split
+);
+
+parse_testcase!(
+    "(range 0 n)",
+    Range {
+        start: Box::new(Int(0)),
+        end: Box::new(Id("n".to_string())),
+        step: Box::new(Int(1)),
+    },
+    test_range_default_step
+);
+
+parse_testcase!("#t", Bool(true), test_scheme_true);
+parse_testcase!("#f", Bool(false), test_scheme_false);
+
+parse_testcase!(
+    "(define x 1)",
+    Define {
+        name: "x".to_string(),
+        value: Box::new(Int(1)),
+    },
+    test_define
+);
+
+#[test]
+fn test_begin_free_vars_exclude_defines() -> Result<(), parse::ParseError> {
+    let exp = parse::Exp::try_from("(begin (define y 1) (+ x y))")?;
+    let vars: Vec<String> = exp.free_vars().into_iter().collect();
+    assert_eq!(vars, vec!["x"]);
+    Ok(())
+}
+
+#[test]
+fn test_begin_free_vars_keep_uses_before_define() -> Result<(), parse::ParseError> {
+    let exp = parse::Exp::try_from("(begin (define x (+ x 1)) (define a b) (define b 2) (+ a b))")?;
+    let mut vars: Vec<String> = exp.free_vars().into_iter().collect();
+    vars.sort();
+    assert_eq!(vars, vec!["b", "x"]);
+    Ok(())
+}
+
+parse_testcase!(
+    "(and a (or b c))",
+    And(vec![
+        Id("a".to_string()),
+        Or(vec![Id("b".to_string()), Id("c".to_string())]),
+    ]),
+    test_and_or
+);
+
+parse_testcase!("007", Int(7), test_leading_zeros_decimal);
+parse_testcase!("010", Int(10), test_leading_zero_not_octal);
+parse_testcase!("0o10", Int(8), test_octal_prefix);
+parse_testcase!("-0o10", Int(-8), test_negative_octal);
+parse_testcase!("0o9", Id("0o9".to_string()), test_octal_invalid_digit);
+
+parse_testcase!(
+    "(begin (begin a b) c)",
+    Begin(vec![
+        Id("a".to_string()),
+        Id("b".to_string()),
+        Id("c".to_string()),
+    ]),
+    test_nested_begin_flattened
+);
+parse_testcase!(
+    "(begin a (begin b (begin c d)))",
+    Begin(vec![
+        Id("a".to_string()),
+        Id("b".to_string()),
+        Id("c".to_string()),
+        Id("d".to_string()),
+    ]),
+    test_deeply_nested_begin_flattened
+);
+parse_testcase!(
+    "(begin (begin (define x 1) x) x)",
+    Begin(vec![
+        Begin(vec![
+            Define {
+                name: "x".to_string(),
+                value: Box::new(Int(1)),
+            },
+            Id("x".to_string()),
+        ]),
+        Id("x".to_string()),
+    ]),
+    test_begin_with_define_not_flattened
+);
+parse_testcase!(
+    "(begin a (begin))",
+    Begin(vec![Id("a".to_string()), Begin(vec![])]),
+    test_empty_begin_not_flattened
+);
+parse_testcase!(
+    "(+ 1 (begin (begin a) b))",
+    Add {
+        lhs: Box::new(Int(1)),
+        rhs: Box::new(Begin(vec![Id("a".to_string()), Id("b".to_string())])),
+    },
+    test_begin_flattened_inside_other_forms
+);
+
+#[test]
+fn test_nested_begin_flattened_with_spans() -> Result<(), parse::ParseError> {
+    let mut parser = parse::Parser::default();
+    parser.track_spans = true;
+    let parse::Exp::Spanned { exp, .. } = parser.parse("(begin (begin 1 2) 3)")? else {
+        panic!("top-level form should be spanned");
+    };
+    assert_eq!(*exp, Begin(vec![Int(1), Int(2), Int(3)]));
+    Ok(())
+}
+
+#[test]
+fn test_number_to_string_round_trip() -> Result<(), parse::ParseError> {
+    for src in ["(number->string x)", "(number->string x 2)"] {
+        assert_eq!(parse::Exp::try_from(src)?.to_string(), src);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_brackets_as_parens() -> Result<(), parse::ParseError> {
+    assert_eq!(
+        parse::Exp::try_from("(let [x 1] x)")?,
+        parse::Exp::try_from("(let (x 1) x)")?
+    );
+    assert_eq!(
+        parse::Exp::try_from("[+ 1 [* 2 3]]")?,
+        parse::Exp::try_from("(+ 1 (* 2 3))")?
+    );
+    Ok(())
+}
+
+#[test]
+fn test_mismatched_delimiters() {
+    for input in ["(let [x 1) x)", "(+ 1 2]", "[+ 1 2)"] {
+        match parse::Exp::try_from(input) {
+            Err(parse::ParseError::SexpError(e)) => {
+                assert_eq!(e.message, "mismatched closing delimiter")
+            }
+            result => panic!("{} parsed as {:?}", input, result),
+        }
+    }
+}
+
+#[test]
+fn test_unexpected_closing_bracket() {
+    assert!(matches!(
+        parse::Exp::try_from("]"),
+        Err(parse::ParseError::SexpError(_))
+    ));
+}
@@ -0,0 +1,151 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Runs the binary with the given input piped to stdin and returns what it printed
+fn run_piped(input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cmpsc-470-final"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start interpreter");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_piped_program() {
+    assert_eq!(run_piped("(+ 1 2)\n"), "Int(3)\n");
+}
+
+#[test]
+fn test_piped_multiline_program() {
+    assert_eq!(
+        run_piped("(display\n  (* 2\n     (+ 1 2)))\n"),
+        "6\nInt(6)\n"
+    );
+}
+
+#[test]
+fn test_piped_parse_error() {
+    assert!(run_piped("(+ 1 2").starts_with("Parse error:"));
+}
+
+#[test]
+fn test_piped_box_shows_contents() {
+    assert_eq!(run_piped("(box 5)\n"), "Box(Int(5))\n");
+}
+
+#[test]
+fn test_ast_command() {
+    let path = std::env::temp_dir().join("cmpsc-470-final-ast-flag.txt");
+    std::fs::write(&path, "(+ 1 2)").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cmpsc-470-final"))
+        .arg("ast")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Add(Int(1), Int(2))\n"
+    );
+}
+
+#[test]
+fn test_piped_error_has_span() {
+    assert_eq!(
+        run_piped("(begin 1\n  (+ 1 true))\n"),
+        "Evaluation error: Value is not a number (at 11..21)\n"
+    );
+}
+
+#[test]
+fn test_piped_forms_evaluated_in_order() {
+    assert_eq!(
+        run_piped("(display 1)\n(+ 1 2)\n(+ 1"),
+        "1\nInt(1)\nInt(3)\nParse error: Sexp syntax error\n"
+    );
+}
+
+// Runs the binary with the given arguments and returns what it printed to stdout
+fn run_args(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_cmpsc-470-final"))
+        .args(args)
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_file_without_command_runs() {
+    let path = std::env::temp_dir().join("cmpsc-470-final-run-file.txt");
+    std::fs::write(&path, "(+ 1 2)").unwrap();
+    assert_eq!(run_args(&[path.to_str().unwrap()]), "Int(3)\n");
+}
+
+#[test]
+fn test_eval_command() {
+    assert_eq!(run_args(&["eval", "(+ 1 2)"]), "Int(3)\n");
+}
+
+#[test]
+fn test_unknown_flag_prints_usage() {
+    let output = Command::new(env!("CARGO_BIN_EXE_cmpsc-470-final"))
+        .arg("--json")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Usage:"));
+}
+
+#[test]
+fn test_exit_sets_status() {
+    let output = Command::new(env!("CARGO_BIN_EXE_cmpsc-470-final"))
+        .args(["eval", "(begin (display 1) (exit 3) (display 2))"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+}
+
+#[test]
+fn test_piped_error_has_backtrace() {
+    assert_eq!(
+        run_piped("(letrec ((f (lambda n (/ 1 n)))) (f 0))"),
+        "Evaluation error: Division by zero (at 33..38)\n  in (f Int(0))\n"
+    );
+}
+
+#[test]
+fn test_strict_flag_prints_warnings() {
+    let output = Command::new(env!("CARGO_BIN_EXE_cmpsc-470-final"))
+        .args(["--strict", "eval", "((lambda x 5) 1)"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Int(5)\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Warning: Parameter 'x' is never used\n"
+    );
+}
+
+#[test]
+fn test_strict_error_fails_check() {
+    let path = std::env::temp_dir().join("cmpsc-470-final-strict-check.txt");
+    std::fs::write(&path, "(lambda x 5)").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cmpsc-470-final"))
+        .arg("--strict=error")
+        .arg("check")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("Error: "));
+}